@@ -1,7 +1,11 @@
 use clap::{Parser, Subcommand};
+use std::time::Duration;
+
+use crate::commands::server::IdentifierMode;
+use crate::utils::{OutputFormat, ProgressMode, Units};
 
 #[derive(Parser)]
-#[command(name = "s3-storage")]
+#[command(name = "s3-uploader")]
 #[command(about = "Upload and download files from S3-compatible storage")]
 #[command(version = "1.0")]
 pub struct Cli {
@@ -9,6 +13,22 @@ pub struct Cli {
     #[arg(short, long, global = true)]
     pub verbose: bool,
 
+    /// Also append log lines, with timestamps, to this file
+    #[arg(long, global = true, value_name = "PATH")]
+    pub log_file: Option<std::path::PathBuf>,
+
+    /// Check the configuration and bucket access, print what would happen, and stop
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+
+    /// Check the endpoint is reachable before running the command (implied by --verbose)
+    #[arg(long, global = true)]
+    pub probe: bool,
+
+    /// Config file profile to take settings from (overrides env STORAGE_PROFILE; default `default`)
+    #[arg(long, global = true)]
+    pub profile: Option<String>,
+
     /// Storage bucket name (overrides env STORAGE_BUCKET)
     #[arg(long, global = true)]
     pub bucket: Option<String>,
@@ -25,13 +45,81 @@ pub struct Cli {
     #[arg(long, global = true)]
     pub secret_key: Option<String>,
 
+    /// Session token for temporary credentials (overrides env STORAGE_SESSION_TOKEN)
+    #[arg(long, global = true)]
+    pub session_token: Option<String>,
+
+    /// Command printing `{"access_key", "secret_key", "session_token"}` JSON to use as credentials
+    #[arg(long, global = true, conflicts_with_all = ["access_key", "secret_key", "session_token"])]
+    pub credentials_command: Option<String>,
+
+    /// ARN of an IAM role to assume through STS, using the configured keys to assume it
+    #[arg(long, global = true)]
+    pub assume_role: Option<String>,
+
+    /// Session name for --assume-role (default `s3-uploader-<unix time>`)
+    #[arg(long, global = true, requires = "assume_role")]
+    pub role_session_name: Option<String>,
+
+    /// External ID the role's trust policy requires, for --assume-role
+    #[arg(long, global = true, requires = "assume_role")]
+    pub external_id: Option<String>,
+
+    /// Passphrase for client-side encryption (overrides env S3_PASSPHRASE)
+    #[arg(long, global = true)]
+    pub passphrase: Option<String>,
+
     /// Storage endpoint URL (overrides env STORAGE_URL)
     #[arg(long, global = true)]
     pub endpoint: Option<String>,
 
-    /// Maximum file size in bytes (overrides env STORAGE_MAX_SIZE)
-    #[arg(long, global = true, default_value_t = 100 * 1024 * 1024)]
-    pub max_size: u64,
+    /// Base prefix applied to every key (overrides env STORAGE_DEFAULT_PREFIX)
+    #[arg(long, global = true)]
+    pub default_prefix: Option<String>,
+
+    /// Key delimiter that separates "folders", e.g. `|` or `:` (overrides env STORAGE_DELIMITER; default `/`)
+    #[arg(long, global = true)]
+    pub delimiter: Option<String>,
+
+    /// Maximum file size in bytes; 0 means no limit (overrides env STORAGE_MAX_SIZE)
+    #[arg(long, global = true)]
+    pub max_size: Option<u64>,
+
+    /// Disable the upload size limit (same as --max-size 0)
+    #[arg(long, global = true, conflicts_with = "max_size")]
+    pub no_size_limit: bool,
+
+    /// Size units in output: `binary` (KiB, MiB; powers of 1024) or `si` (KB, MB; powers of 1000)
+    #[arg(long, global = true, value_enum, default_value_t = Units::Binary)]
+    pub units: Units,
+
+    /// Print results as JSON (list, upload, download, delete); logs go to stderr
+    #[arg(long, global = true)]
+    pub json: bool,
+
+    /// Result format: `text`, `json` (same as --json), or `yaml` (stat only)
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text, conflicts_with = "json")]
+    pub output_format: OutputFormat,
+
+    /// Transfer progress: `auto` (a bar on a terminal, periodic log lines otherwise), `always` (a bar whenever stderr is a terminal), or `never`
+    #[arg(long, global = true, value_enum, default_value_t = ProgressMode::Auto)]
+    pub progress: ProgressMode,
+
+    /// Retries of requests that fail with throttling, 5xx, or connection errors
+    #[arg(long, global = true, default_value_t = 3)]
+    pub max_retries: u32,
+
+    /// Total time to spend retrying across the whole command, e.g. `90s`, `10m` or `1h`; once spent, failures are final
+    #[arg(long, global = true, value_parser = parse_duration)]
+    pub retry_budget: Option<Duration>,
+
+    /// Send upload bodies unsigned (`UNSIGNED-PAYLOAD`), for gateways that reject signed streaming payloads; relies on TLS for integrity
+    #[arg(long, global = true)]
+    pub unsigned_payload: bool,
+
+    /// Number of transfers in flight at once: multipart parts, and files when uploading a directory
+    #[arg(long, global = true, default_value_t = 4)]
+    pub concurrency: usize,
 
     #[command(subcommand)]
     pub command: Commands,
@@ -39,38 +127,405 @@ pub struct Cli {
 
 #[derive(Subcommand)]
 pub enum Commands {
-    /// Upload a file to storage
+    /// Upload a file (or a directory, recursively) to storage
     Upload {
+        /// File or directory to upload, or `-` to read stdin (requires --key)
         file_path: String,
         #[arg(long, default_value_t = 3600)]
         expires: u64,
+        /// File size in bytes at which multipart upload kicks in
+        #[arg(long, default_value_t = 16 * 1024 * 1024)]
+        multipart_threshold: u64,
+        /// Size in bytes of each multipart part (chosen from the file size if omitted)
+        #[arg(long)]
+        part_size: Option<u64>,
+        /// Always use a single-part upload
+        #[arg(long, conflicts_with = "force_multipart")]
+        no_multipart: bool,
+        /// Always use multipart upload, even for small files
+        #[arg(long)]
+        force_multipart: bool,
+        /// Keep a failed multipart upload and save its progress in
+        /// .s3upload-state, so running the same command again resumes it
+        #[arg(long)]
+        resumable: bool,
+        /// Ignore any saved multipart progress in .s3upload-state and start over
+        #[arg(long, requires = "resumable")]
+        restart: bool,
+        /// When uploading a directory, key files by basename only
+        #[arg(long)]
+        flatten: bool,
+        /// Folder to upload under: keys become `<prefix>/<relative path>`
+        #[arg(long)]
+        prefix: Option<String>,
+        /// Encrypt with AES-256-GCM before uploading, using a key derived from the passphrase
+        #[arg(long)]
+        encrypt: bool,
+        /// Store each file's Unix mode, uid and gid as metadata
+        #[arg(long)]
+        preserve_permissions: bool,
+        /// After uploading, confirm each key is listed with the expected size
+        #[arg(long)]
+        verify_listed: bool,
+        /// Object key to upload a single file to, instead of its file name
+        #[arg(long, visible_alias = "name", conflicts_with_all = ["prefix", "flatten"])]
+        key: Option<String>,
+        /// When uploading a directory, follow symlinks instead of skipping them
+        #[arg(long)]
+        follow_symlinks: bool,
+        /// User metadata entry, e.g. `owner=ops` (repeatable)
+        #[arg(long = "metadata", visible_alias = "meta", value_parser = parse_key_val)]
+        metadata: Vec<(String, String)>,
+        /// Object tag, e.g. `retention=30d` (repeatable, at most 10)
+        #[arg(long = "tag", value_parser = parse_key_val)]
+        tags: Vec<(String, String)>,
+        /// Read metadata from a file of key=value lines or a JSON object
+        #[arg(long)]
+        metadata_from_file: Option<String>,
+        /// Server-side encryption: AES256, aws:kms, or aws:kms:dsse (any case)
+        #[arg(long)]
+        sse: Option<String>,
+        /// KMS key ID or ARN for --sse aws:kms (default: the bucket's KMS key)
+        #[arg(long)]
+        sse_kms_key_id: Option<String>,
+        /// Base64-encoded JSON KMS encryption context for --sse aws:kms
+        #[arg(long)]
+        sse_kms_context: Option<String>,
+        /// Use an S3 Bucket Key for SSE-KMS to reduce KMS request costs
+        #[arg(long)]
+        bucket_key_enabled: bool,
+        /// Wait until the uploaded object is readable before returning
+        #[arg(long)]
+        wait: bool,
+        /// Seconds --wait gives up after
+        #[arg(long, default_value_t = 30, requires = "wait")]
+        wait_timeout: u64,
+        /// When uploading a directory, show a progress bar per file
+        #[arg(long)]
+        per_file_progress: bool,
+        /// Cache-Control header to set (overrides --auto-cache-control)
+        #[arg(long)]
+        cache_control: Option<String>,
+        /// Pick Cache-Control from the content type (see STORAGE_CACHE_RULES)
+        #[arg(long)]
+        auto_cache_control: bool,
+        /// Content type to store, instead of guessing from the file extension
+        #[arg(long)]
+        content_type: Option<String>,
+        /// Storage class, e.g. STANDARD_IA or GLACIER (default: the bucket's)
+        #[arg(long)]
+        storage_class: Option<String>,
     },
 
     /// Download a file from storage
     Download {
         file_name: String,
+        /// Path to save to, or `-` to write the object to stdout
         #[arg(long)]
         output: Option<String>,
         #[arg(long)]
         presign: bool,
+        /// With --presign, also draw the URL as a QR code on stderr
+        #[arg(long, requires = "presign")]
+        qr: bool,
+        /// With --presign, also save the URL as a QR code PNG to this path
+        #[arg(long, requires = "presign")]
+        qr_file: Option<String>,
+        /// With --presign, fail if the object doesn't exist instead of printing a dead link
+        #[arg(long, requires = "presign")]
+        check: bool,
         #[arg(long, default_value_t = 3600)]
         expires: u64,
+        /// Write buffer size in bytes for the output file (0 writes each chunk as it arrives)
+        #[arg(long, default_value_t = 0)]
+        buffer_size: usize,
+        /// Read the object ahead on a separate task while writing it
+        #[arg(long)]
+        prefetch: bool,
+        /// Only download if the object changed after this RFC 3339 time
+        #[arg(long)]
+        if_modified_since: Option<String>,
+        /// Name the local file after the name it was uploaded from
+        #[arg(long, conflicts_with = "output")]
+        restore_filename: bool,
+        /// Gunzip keys ending in .gz and save them without the suffix
+        #[arg(long)]
+        auto_decompress: bool,
+        /// If the object doesn't exist yet, wait up to this many seconds for it
+        #[arg(long, value_name = "SECONDS")]
+        wait_for: Option<u64>,
+        /// Skip checking the download against the object's stored SHA-256
+        #[arg(long)]
+        no_verify: bool,
+        /// Save client-side encrypted objects as stored, without decrypting
+        #[arg(long)]
+        no_decrypt: bool,
+        /// Restore the Unix mode, uid and gid stored by `upload --preserve-permissions`
+        #[arg(long)]
+        preserve_permissions: bool,
+        /// Treat FILE_NAME as a prefix and download every object under it into --output (a directory)
+        #[arg(long, conflicts_with_all = ["presign", "restore_filename", "wait_for"])]
+        recursive: bool,
+        /// With --recursive, only download objects with this tag, e.g. `env=prod` (repeatable; one extra request per object)
+        #[arg(long = "tag", value_parser = parse_key_val, requires = "recursive")]
+        tags: Vec<(String, String)>,
+        /// With --recursive, skip local files that already match the object
+        #[arg(long, requires = "recursive", conflicts_with = "update_only")]
+        skip_existing: bool,
+        /// With --recursive, treat FILE_NAME as a folder (adds a trailing `/` if missing)
+        #[arg(long, requires = "recursive")]
+        dir: bool,
+        /// With --recursive, only replace local files older than the object
+        #[arg(long, requires = "recursive")]
+        update_only: bool,
     },
 
     /// List files in storage bucket
     List {
+        /// Literal key prefix: `logs` also matches `logs-old/...`; use `logs/` or --dir for a folder
         #[arg(long)]
         prefix: Option<String>,
-        #[arg(long, default_value_t = 100)]
+        /// Most objects to list in total; 0 or negative lists every object
+        #[arg(long, default_value_t = 100, allow_negative_numbers = true)]
         limit: i32,
+        /// Objects requested per page (1-1000)
+        #[arg(long, default_value_t = 1000, value_parser = clap::value_parser!(i32).range(1..=1000))]
+        page_size: i32,
+        /// Show the owner of each object
+        #[arg(long)]
+        show_owner: bool,
+        /// Resume listing from a token printed by --show-token
+        #[arg(long)]
+        continuation_token: Option<String>,
+        /// Print the continuation token for the next page
+        #[arg(long)]
+        show_token: bool,
+        /// Fetch and show each object's stored checksum (one extra request per object)
+        #[arg(long)]
+        show_checksum: bool,
+        /// Fetch and show each object's user metadata (one extra request per object)
+        #[arg(long)]
+        show_metadata: bool,
+        /// Treat the prefix as a folder (adds a trailing `/` if missing)
+        #[arg(long)]
+        dir: bool,
+        /// Only list objects with this tag, e.g. `env=prod` (repeatable; one extra request per object)
+        #[arg(long = "tag", value_parser = parse_key_val, conflicts_with_all = ["continuation_token", "show_token"])]
+        tags: Vec<(String, String)>,
+    },
+
+    /// List in-progress multipart uploads
+    ListUploads {
+        /// Only show uploads whose key starts with this prefix
+        #[arg(long)]
+        prefix: Option<String>,
+        /// Treat the prefix as a folder (adds a trailing `/` if missing)
+        #[arg(long)]
+        dir: bool,
     },
 
     /// Delete a file from storage
-    Delete { file_name: String },
+    Delete {
+        file_name: String,
+        /// Succeed even if the object does not exist
+        #[arg(long)]
+        ignore_missing: bool,
+        /// Treat FILE_NAME as a prefix and delete every object under it
+        #[arg(long, conflicts_with = "ignore_missing")]
+        recursive: bool,
+        /// With --recursive, don't ask for confirmation
+        #[arg(long, short = 'y', requires = "recursive")]
+        yes: bool,
+        /// With --recursive, treat FILE_NAME as a folder (adds a trailing `/` if missing)
+        #[arg(long, requires = "recursive")]
+        dir: bool,
+    },
+
+    /// Delete every object (and version) in the bucket
+    Purge {
+        /// Must repeat the bucket name to confirm
+        #[arg(long)]
+        confirm: String,
+        /// Also delete the bucket once it is empty
+        #[arg(long)]
+        delete_bucket: bool,
+    },
+
+    /// Copy an object within the bucket or to another bucket
+    Copy {
+        source: String,
+        dest: String,
+        /// Bucket to copy into (defaults to the source bucket)
+        #[arg(long)]
+        dest_bucket: Option<String>,
+        /// Delete the source after a successful copy
+        #[arg(long = "move")]
+        move_source: bool,
+        /// Only copy if the source ETag matches
+        #[arg(long)]
+        copy_source_if_match: Option<String>,
+        /// Only copy if the source is unmodified since this RFC 3339 time
+        #[arg(long)]
+        copy_source_if_unmodified_since: Option<String>,
+    },
+
+    /// Rename every key matching a regex (copy, then delete the original)
+    RenameRegex {
+        /// Regex matched against each key, e.g. `(.*)\.jpeg$`
+        #[arg(long = "match")]
+        pattern: String,
+        /// Replacement for the match, e.g. `$1.jpg`
+        #[arg(long)]
+        replace: String,
+        /// Only rename keys under this prefix
+        #[arg(long)]
+        prefix: Option<String>,
+        /// Don't ask for confirmation
+        #[arg(long, short = 'y')]
+        yes: bool,
+    },
+
+    /// Copy between local paths and s3:// URIs, choosing upload, download,
+    /// copy or sync from the arguments
+    Transfer {
+        /// Local path or s3://bucket/key (a trailing `/` means a prefix)
+        src: String,
+        /// Local path or s3://bucket/key (a trailing `/` means a prefix)
+        dst: String,
+        /// When uploading a directory, delete remote objects missing locally
+        #[arg(long)]
+        delete: bool,
+    },
+
+    /// Fetch a file from an http(s) URL and upload it
+    UploadUrl {
+        url: String,
+        /// Object key to upload to (defaults to the last segment of the URL path)
+        #[arg(long)]
+        key: Option<String>,
+        /// Maximum number of redirects to follow
+        #[arg(long, default_value_t = 5)]
+        follow_redirects: usize,
+    },
+
+    /// Upload new and changed files from a directory to a prefix
+    Sync {
+        local_dir: String,
+        /// Folder to sync into, e.g. `site/` (defaults to the default prefix)
+        prefix: Option<String>,
+        /// Delete objects under the prefix that no longer exist locally
+        #[arg(long)]
+        delete: bool,
+    },
+
+    /// Upload an index.html linking to every object under a prefix
+    GenerateIndex {
+        /// Prefix to index, e.g. `public/` (defaults to the default prefix)
+        #[arg(long)]
+        prefix: Option<String>,
+    },
+
+    /// Show an object's metadata
+    #[command(visible_alias = "head")]
+    Stat {
+        file_name: String,
+        /// Also hex-dump the first N bytes (at most 4096)
+        #[arg(long)]
+        peek: Option<u64>,
+    },
+
+    /// Show the bucket's region, versioning, and object lock settings
+    BucketInfo,
+
+    /// Create the bucket in --region; succeeds if it already exists and is yours
+    CreateBucket,
+
+    /// Change an object's metadata, keeping whatever isn't mentioned
+    UpdateMetadata {
+        file_name: String,
+        /// Add or overwrite a user metadata entry, e.g. `owner=ops` (repeatable)
+        #[arg(long = "set", value_parser = parse_key_val)]
+        set: Vec<(String, String)>,
+        /// Remove a user metadata entry (repeatable)
+        #[arg(long = "remove")]
+        remove: Vec<String>,
+        /// New Content-Type
+        #[arg(long)]
+        content_type: Option<String>,
+        /// New Cache-Control
+        #[arg(long)]
+        cache_control: Option<String>,
+    },
 
     /// Start web UI server
     Server {
         #[arg(long, default_value_t = 8080)]
         port: u16,
+        /// Route uploads by content type, e.g. `image/=images/` (repeatable)
+        #[arg(long = "route", value_parser = parse_key_val)]
+        routes: Vec<(String, String)>,
+        /// Key prefix for content types no --route matches
+        #[arg(long, default_value = "other/")]
+        route_default: String,
+        /// How the identifier field names files: `index` (id-1.ext, id-2.ext)
+        /// or `prefix` (id-<original name>)
+        #[arg(long, value_enum, default_value_t = IdentifierMode::Index)]
+        identifier_mode: IdentifierMode,
+        /// Re-read credentials from the environment and .env every N seconds
+        #[arg(long)]
+        refresh_credentials: Option<u64>,
+        /// Directory to stage uploads in (defaults to the system temp dir)
+        #[arg(long)]
+        temp_dir: Option<String>,
+        /// File size in bytes at which uploads switch to multipart
+        #[arg(long, default_value_t = 16 * 1024 * 1024)]
+        multipart_threshold: u64,
+        /// Size in bytes of each multipart part (chosen from the file size if omitted)
+        #[arg(long)]
+        part_size: Option<u64>,
+        /// Make download links save the file (Content-Disposition: attachment) instead of opening it
+        #[arg(long)]
+        force_download: bool,
+        /// Canned ACL for uploaded objects, e.g. `public-read` for a public CDN origin
+        #[arg(long)]
+        upload_acl: Option<String>,
+    },
+
+    /// Print a shell completion script, e.g. `source <(s3-uploader completions bash)`
+    Completions {
+        /// Shell to complete for
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
     },
 }
+
+/// Parses a duration given as seconds, or with an `s`, `m` or `h` suffix.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (number, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, "s"),
+    };
+    let scale = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        _ => return Err(format!("invalid duration '{}': use e.g. 90s, 10m or 1h", s)),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(scale))
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("invalid duration '{}': use e.g. 90s, 10m or 1h", s))
+}
+
+/// Parses a `KEY=VALUE` argument.
+fn parse_key_val(s: &str) -> Result<(String, String), String> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected KEY=VALUE, got `{}`", s))?;
+    if key.is_empty() {
+        return Err(format!("empty key in `{}`", s));
+    }
+    Ok((key.to_string(), value.to_string()))
+}
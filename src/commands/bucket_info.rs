@@ -0,0 +1,94 @@
+use crate::{config::StorageConfig, s3_client::create_client};
+use anyhow::Result;
+use aws_sdk_s3::config::http::HttpResponse;
+use aws_sdk_s3::error::{ProvideErrorMetadata, SdkError};
+use std::io::Write;
+
+/// Reports a bucket's region, versioning status, and object lock setting.
+///
+/// S3-compatible endpoints often implement only part of the bucket API, so
+/// each lookup that fails with "not implemented" (or similar) is reported as
+/// unsupported rather than failing the whole command.
+pub async fn bucket_info(config: &StorageConfig, verbose: bool, out: &mut dyn Write) -> Result<()> {
+    let client = create_client(config, verbose).await?;
+    if verbose {
+        elog!("🪣 Inspecting bucket {}", config.bucket);
+    }
+
+    let region = match client
+        .get_bucket_location()
+        .bucket(&config.bucket)
+        .send()
+        .await
+    {
+        // An empty location constraint means us-east-1
+        Ok(location) => match location.location_constraint().map(|c| c.as_str()) {
+            None | Some("") => "us-east-1".to_string(),
+            Some(region) => region.to_string(),
+        },
+        Err(e) => unsupported_or(e)?,
+    };
+
+    let versioning = match client
+        .get_bucket_versioning()
+        .bucket(&config.bucket)
+        .send()
+        .await
+    {
+        // A bucket that was never versioned has no status at all
+        Ok(versioning) => versioning
+            .status()
+            .map(|s| s.as_str().to_string())
+            .unwrap_or_else(|| "Disabled".to_string()),
+        Err(e) => unsupported_or(e)?,
+    };
+
+    let object_lock = match client
+        .get_object_lock_configuration()
+        .bucket(&config.bucket)
+        .send()
+        .await
+    {
+        Ok(lock) => match lock
+            .object_lock_configuration()
+            .and_then(|c| c.object_lock_enabled())
+        {
+            Some(enabled) => enabled.as_str().to_string(),
+            None => "Disabled".to_string(),
+        },
+        Err(e) if e.code() == Some("ObjectLockConfigurationNotFoundError") => {
+            "Disabled".to_string()
+        }
+        Err(e) => unsupported_or(e)?,
+    };
+
+    writeln!(out, "Bucket: {}", config.bucket)?;
+    writeln!(out, "Region: {}", region)?;
+    writeln!(out, "Versioning: {}", versioning)?;
+    writeln!(out, "Object lock: {}", object_lock)?;
+
+    Ok(())
+}
+
+/// Turns "this endpoint doesn't do that" into a displayable value and
+/// passes every other error through.
+pub(crate) fn unsupported_or<E>(err: SdkError<E, HttpResponse>) -> Result<String>
+where
+    E: ProvideErrorMetadata + std::error::Error + Send + Sync + 'static,
+{
+    if is_unsupported(&err) {
+        Ok("unsupported by this endpoint".to_string())
+    } else {
+        Err(err.into())
+    }
+}
+
+/// Whether `err` says the endpoint doesn't implement the operation, as
+/// opposed to refusing or failing it.
+pub(crate) fn is_unsupported<E: ProvideErrorMetadata>(err: &SdkError<E, HttpResponse>) -> bool {
+    let status = err.raw_response().map(|r| r.status().as_u16());
+    matches!(
+        err.code(),
+        Some("NotImplemented" | "MethodNotAllowed" | "UnsupportedOperation")
+    ) || matches!(status, Some(405 | 501))
+}
@@ -0,0 +1,381 @@
+use crate::{
+    config::StorageConfig,
+    error::StorageError,
+    s3_client::create_client,
+    utils::{format_size, make_progress_bar},
+};
+use anyhow::{bail, Context, Result};
+use aws_sdk_s3::operation::head_object::HeadObjectOutput;
+use aws_sdk_s3::primitives::{DateTime, DateTimeFormat};
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
+use aws_sdk_s3::Client;
+use futures::{StreamExt, TryStreamExt};
+
+#[derive(Debug, Clone, Default)]
+pub struct CopyOptions {
+    /// Only copy if the source ETag matches
+    pub if_match: Option<String>,
+    /// Only copy if the source is unmodified since this RFC 3339 time
+    pub if_unmodified_since: Option<String>,
+    /// Bucket to copy into, instead of the source bucket
+    pub dest_bucket: Option<String>,
+    /// Delete the source once the copy has succeeded
+    pub move_source: bool,
+}
+
+pub async fn copy_file(
+    source: &str,
+    dest: &str,
+    options: &CopyOptions,
+    config: &StorageConfig,
+    verbose: bool,
+) -> Result<()> {
+    let source_key = config.prefixed(source);
+    let dest_key = config.prefixed(dest);
+    let dest_bucket = options.dest_bucket.as_deref().unwrap_or(&config.bucket);
+    let if_match = options.if_match.as_deref();
+    let if_unmodified_since = options.if_unmodified_since.as_deref();
+
+    if options.move_source && dest_bucket == config.bucket && dest_key == source_key {
+        bail!("Can't move {} onto itself", source_key);
+    }
+
+    let unmodified_since = if_unmodified_since
+        .map(|s| {
+            DateTime::from_str(s, DateTimeFormat::DateTime)
+                .with_context(|| format!("Invalid RFC 3339 timestamp: {}", s))
+        })
+        .transpose()?;
+
+    let client = create_client(config, verbose).await?;
+    if verbose {
+        elog!(
+            "📋 {} {}/{} -> {}/{}",
+            if options.move_source {
+                "Moving"
+            } else {
+                "Copying"
+            },
+            config.bucket,
+            source_key,
+            dest_bucket,
+            dest_key
+        );
+        if let Some(etag) = if_match {
+            elog!("  Only if source ETag matches: {}", etag);
+        }
+        if let Some(since) = if_unmodified_since {
+            elog!("  Only if source unmodified since: {}", since);
+        }
+    }
+
+    let head = client
+        .head_object()
+        .bucket(&config.bucket)
+        .key(&source_key)
+        .send()
+        .await;
+    let head = match head {
+        Ok(head) => head,
+        Err(e) if e.as_service_error().is_some_and(|se| se.is_not_found()) => {
+            return Err(StorageError::NotFound {
+                key: source_key,
+                bucket: config.bucket.clone(),
+            }
+            .into());
+        }
+        Err(e) => return Err(StorageError::from(e).into()),
+    };
+    let size = head.content_length().unwrap_or(0) as u64;
+    let destination = Destination {
+        bucket: dest_bucket,
+        key: &dest_key,
+    };
+
+    let result = if size > MAX_SINGLE_COPY {
+        let conditions = CopyConditions {
+            if_match,
+            unmodified_since,
+        };
+        copy_multipart(
+            &client,
+            config,
+            &source_key,
+            &destination,
+            &head,
+            &conditions,
+            verbose,
+        )
+        .await
+    } else {
+        client
+            .copy_object()
+            .bucket(dest_bucket)
+            .copy_source(copy_source(&config.bucket, &source_key))
+            .key(&dest_key)
+            .set_copy_source_if_match(if_match.map(str::to_string))
+            .set_copy_source_if_unmodified_since(unmodified_since)
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|e| StorageError::from(e).into())
+    };
+
+    if let Err(e) = result {
+        if is_precondition_failed(&e) {
+            if if_match.is_none() && unmodified_since.is_none() {
+                bail!(
+                    "Source '{}' changed during the copy; copy was not performed",
+                    source_key
+                );
+            }
+            bail!(
+                "Source '{}' changed since the given condition; copy was not performed",
+                source_key
+            );
+        }
+        return Err(e);
+    }
+
+    if verbose {
+        elog!("✅ Copied {} -> {}/{}", source_key, dest_bucket, dest_key);
+    }
+
+    if options.move_source {
+        client
+            .delete_object()
+            .bucket(&config.bucket)
+            .key(&source_key)
+            .send()
+            .await
+            .map_err(StorageError::from)
+            .with_context(|| {
+                format!(
+                    "Copied to {}/{} but failed to delete the source {}",
+                    dest_bucket, dest_key, source_key
+                )
+            })?;
+        if verbose {
+            elog!("🗑️ Deleted source {}", source_key);
+        }
+    }
+
+    Ok(())
+}
+
+/// Where a copy is written.
+struct Destination<'a> {
+    bucket: &'a str,
+    key: &'a str,
+}
+
+/// Builds the `x-amz-copy-source` value, URL-encoding each key segment so
+/// spaces and special characters survive the header.
+pub(crate) fn copy_source(bucket: &str, key: &str) -> String {
+    let encoded: Vec<_> = key.split('/').map(urlencoding::encode).collect();
+    format!("{}/{}", bucket, encoded.join("/"))
+}
+
+/// Largest object `copy_object` can copy in one request.
+const MAX_SINGLE_COPY: u64 = 5 * 1024 * 1024 * 1024;
+
+/// Preferred part size for multipart copies, raised for very large objects
+/// so the copy stays within the 10,000 part limit.
+const COPY_PART_SIZE: u64 = 512 * 1024 * 1024;
+
+/// Source conditions checked by every part of a multipart copy. The ETag
+/// defaults to the source's when the copy starts, so a source replaced
+/// partway through can't produce a mixed object.
+struct CopyConditions<'a> {
+    if_match: Option<&'a str>,
+    unmodified_since: Option<DateTime>,
+}
+
+fn is_precondition_failed(err: &anyhow::Error) -> bool {
+    matches!(
+        StorageError::find(err),
+        Some(StorageError::S3 { code: Some(code), .. }) if code == "PreconditionFailed"
+    )
+}
+
+/// Copies an object over 5 GiB with ranged `upload_part_copy` calls, up to
+/// `config.concurrency` at a time. The headers and metadata of the source
+/// are carried over, as `copy_object` does by default.
+async fn copy_multipart(
+    client: &Client,
+    config: &StorageConfig,
+    source_key: &str,
+    dest: &Destination<'_>,
+    head: &HeadObjectOutput,
+    conditions: &CopyConditions<'_>,
+    verbose: bool,
+) -> Result<()> {
+    let size = head.content_length().unwrap_or(0) as u64;
+    let part_size = COPY_PART_SIZE.max(size.div_ceil(10_000).div_ceil(1024 * 1024) * 1024 * 1024);
+    let part_count = size.div_ceil(part_size);
+    if verbose {
+        elog!(
+            "  Source is {}, copying in {} parts of {}",
+            format_size(size, config.units),
+            part_count,
+            format_size(part_size, config.units)
+        );
+    }
+
+    let created = client
+        .create_multipart_upload()
+        .bucket(dest.bucket)
+        .key(dest.key)
+        .set_content_type(head.content_type().map(str::to_string))
+        .set_cache_control(head.cache_control().map(str::to_string))
+        .set_content_disposition(head.content_disposition().map(str::to_string))
+        .set_content_encoding(head.content_encoding().map(str::to_string))
+        .set_content_language(head.content_language().map(str::to_string))
+        .set_metadata(head.metadata().cloned())
+        .set_storage_class(head.storage_class().cloned())
+        .send()
+        .await
+        .map_err(StorageError::from)?;
+    let upload_id = created
+        .upload_id()
+        .ok_or_else(|| anyhow::anyhow!("Multipart copy to {} returned no upload id", dest.key))?
+        .to_string();
+
+    // Without `--if-match`, every part is pinned to the version just read,
+    // so a source replaced mid-copy fails the copy rather than mixing
+    let if_match = conditions.if_match.or(head.e_tag());
+    let source = copy_source(&config.bucket, source_key);
+    let progress = make_progress_bar(size, config.units, config.progress, verbose);
+    let (upload_id_ref, source_ref, progress_ref) = (&upload_id, &source, &progress);
+    let copied: Result<Vec<CompletedPart>> = futures::stream::iter(0..part_count)
+        .map(|index| async move {
+            let start = index * part_size;
+            let end = (start + part_size).min(size) - 1;
+            let part_number = index as i32 + 1;
+            let result = client
+                .upload_part_copy()
+                .bucket(dest.bucket)
+                .key(dest.key)
+                .upload_id(upload_id_ref)
+                .part_number(part_number)
+                .copy_source(source_ref)
+                .copy_source_range(format!("bytes={}-{}", start, end))
+                .set_copy_source_if_match(if_match.map(str::to_string))
+                .set_copy_source_if_unmodified_since(conditions.unmodified_since)
+                .send()
+                .await
+                .map_err(StorageError::from)?;
+            progress_ref.inc(end + 1 - start);
+            Ok(CompletedPart::builder()
+                .part_number(part_number)
+                .set_e_tag(
+                    result
+                        .copy_part_result()
+                        .and_then(|r| r.e_tag())
+                        .map(str::to_string),
+                )
+                .build())
+        })
+        .buffered(config.concurrency)
+        .try_collect()
+        .await;
+
+    let completed = match copied {
+        Ok(parts) => client
+            .complete_multipart_upload()
+            .bucket(dest.bucket)
+            .key(dest.key)
+            .upload_id(&upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(parts))
+                    .build(),
+            )
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|e| StorageError::from(e).into()),
+        Err(e) => Err(e),
+    };
+
+    match completed {
+        Ok(()) => {
+            progress.finish_and_clear();
+            Ok(())
+        }
+        Err(e) => {
+            progress.abandon();
+            if verbose {
+                elog!("  Aborting multipart copy {}", upload_id);
+            }
+            if let Err(abort_err) = client
+                .abort_multipart_upload()
+                .bucket(dest.bucket)
+                .key(dest.key)
+                .upload_id(&upload_id)
+                .send()
+                .await
+            {
+                elog!(
+                    "❌ Failed to abort multipart copy {}: {:?}",
+                    upload_id,
+                    abort_err
+                );
+            }
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::s3_client::stub_client;
+    use std::sync::{Arc, Mutex};
+
+    #[tokio::test]
+    async fn multipart_copy_pins_every_part_to_the_source_etag() {
+        let conditions_seen: Arc<Mutex<Vec<Option<String>>>> = Arc::default();
+        let seen = conditions_seen.clone();
+        let client = stub_client(0, move |request| {
+            let query = request.uri().query().unwrap_or_default().to_string();
+            let response = http::Response::builder().status(200);
+            let body = if query.contains("partNumber") {
+                seen.lock().unwrap().push(
+                    request
+                        .headers()
+                        .get("x-amz-copy-source-if-match")
+                        .map(|v| v.to_str().unwrap().to_string()),
+                );
+                "<CopyPartResult><ETag>\"part\"</ETag></CopyPartResult>"
+            } else if query.contains("uploads") {
+                "<InitiateMultipartUploadResult><UploadId>upload-id</UploadId></InitiateMultipartUploadResult>"
+            } else {
+                "<CompleteMultipartUploadResult><ETag>\"done\"</ETag></CompleteMultipartUploadResult>"
+            };
+            response.body(body.to_string()).unwrap()
+        });
+        let config = StorageConfig::new("bucket", "us-east-1", "key", "secret");
+        let head = HeadObjectOutput::builder()
+            .content_length(6 * 1024 * 1024 * 1024)
+            .e_tag("\"source-v1\"")
+            .build();
+        let conditions = CopyConditions {
+            if_match: None,
+            unmodified_since: None,
+        };
+        let dest = Destination {
+            bucket: "bucket",
+            key: "copy",
+        };
+
+        copy_multipart(&client, &config, "big", &dest, &head, &conditions, false)
+            .await
+            .unwrap();
+        let seen = conditions_seen.lock().unwrap();
+        assert_eq!(seen.len(), 12);
+        assert!(seen
+            .iter()
+            .all(|etag| etag.as_deref() == Some("\"source-v1\"")));
+    }
+}
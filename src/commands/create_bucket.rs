@@ -0,0 +1,102 @@
+use crate::{config::StorageConfig, error::StorageError, s3_client::create_client};
+use anyhow::Result;
+use aws_sdk_s3::types::{BucketLocationConstraint, CreateBucketConfiguration};
+use std::io::Write;
+
+/// What `create_bucket` found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CreateOutcome {
+    Created,
+    /// The bucket already existed and belongs to these credentials
+    AlreadyOwned,
+}
+
+/// Creates `config.bucket` in `config.region`, succeeding without changes
+/// if it already exists and is ours, so provisioning scripts can run it
+/// repeatedly. A bucket owned by another account fails with
+/// `StorageError::BucketTaken`.
+///
+/// With `dry_run`, checks whether the bucket exists and reports what would
+/// happen instead.
+pub async fn create_bucket(
+    dry_run: bool,
+    config: &StorageConfig,
+    verbose: bool,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let client = create_client(config, verbose).await?;
+    if dry_run {
+        // The usual dry-run check needs the bucket to exist already
+        let exists = match client.head_bucket().bucket(&config.bucket).send().await {
+            Ok(_) => true,
+            Err(e) if e.as_service_error().is_some_and(|se| se.is_not_found()) => false,
+            Err(e) => return Err(e.into()),
+        };
+        if exists {
+            writeln!(
+                out,
+                "Bucket '{}' already exists; would do nothing",
+                config.bucket
+            )?;
+        } else {
+            writeln!(
+                out,
+                "Would create bucket '{}' in region {}",
+                config.bucket, config.region
+            )?;
+        }
+        return Ok(());
+    }
+
+    if verbose {
+        elog!(
+            "🪣 Creating bucket {} in region {}",
+            config.bucket,
+            config.region
+        );
+    }
+    // us-east-1 is the default, and S3 rejects it as an explicit constraint
+    let location = (config.region != "us-east-1").then(|| {
+        CreateBucketConfiguration::builder()
+            .location_constraint(BucketLocationConstraint::from(config.region.as_str()))
+            .build()
+    });
+    let outcome = match client
+        .create_bucket()
+        .bucket(&config.bucket)
+        .set_create_bucket_configuration(location)
+        .send()
+        .await
+    {
+        Ok(_) => CreateOutcome::Created,
+        Err(e) => match e.as_service_error() {
+            Some(se) if se.is_bucket_already_owned_by_you() => CreateOutcome::AlreadyOwned,
+            Some(se) if se.is_bucket_already_exists() => {
+                return Err(StorageError::BucketTaken {
+                    bucket: config.bucket.clone(),
+                }
+                .into());
+            }
+            _ => return Err(e.into()),
+        },
+    };
+
+    if config.json {
+        let result = serde_json::json!({
+            "bucket": config.bucket,
+            "region": config.region,
+            "created": outcome == CreateOutcome::Created,
+        });
+        writeln!(out, "{}", result)?;
+    } else {
+        match outcome {
+            CreateOutcome::Created => writeln!(out, "Created bucket {}", config.bucket)?,
+            CreateOutcome::AlreadyOwned => writeln!(
+                out,
+                "Bucket {} already exists and is yours; nothing to do",
+                config.bucket
+            )?,
+        }
+    }
+    Ok(())
+}
@@ -1,22 +1,204 @@
-use crate::{config::StorageConfig, s3_client::create_client};
-use anyhow::Result;
+use crate::{
+    commands::list::list_all,
+    config::StorageConfig,
+    error::StorageError,
+    keys::as_dir,
+    s3_client::{create_client, with_region_retry},
+};
+use anyhow::{bail, Result};
+use aws_sdk_s3::types::{Delete, ObjectIdentifier};
+use aws_sdk_s3::Client;
+use std::io::{IsTerminal, Write};
 
-pub async fn delete_file(file_name: &str, config: &StorageConfig, verbose: bool) -> Result<()> {
-    let client = create_client(config, verbose).await?;
+/// What `delete_file` found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeleteOutcome {
+    Deleted,
+    /// The object did not exist, so nothing was deleted
+    NotFound,
+}
+
+pub async fn delete_file(
+    file_name: &str,
+    config: &StorageConfig,
+    verbose: bool,
+) -> Result<DeleteOutcome> {
+    let key = config.prefixed(file_name);
     if verbose {
-        println!("🗑️ Deleting file: {}", file_name);
+        elog!("🗑️ Deleting file: {}", key);
     }
 
-    client
-        .delete_object()
-        .bucket(&config.bucket)
-        .key(file_name)
-        .send()
-        .await?;
+    let key_ref = &key;
+    let deleted = with_region_retry(config, verbose, |client| async move {
+        // S3 reports success when deleting a missing key, so check existence first
+        let head = client
+            .head_object()
+            .bucket(&config.bucket)
+            .key(key_ref)
+            .send()
+            .await;
+        if let Err(e) = head {
+            if e.as_service_error().is_some_and(|se| se.is_not_found()) {
+                return Ok(false);
+            }
+            return Err(StorageError::from(e).into());
+        }
+
+        client
+            .delete_object()
+            .bucket(&config.bucket)
+            .key(key_ref)
+            .send()
+            .await
+            .map_err(StorageError::from)?;
+        Ok(true)
+    })
+    .await?;
+
+    Ok(if deleted {
+        DeleteOutcome::Deleted
+    } else {
+        DeleteOutcome::NotFound
+    })
+}
 
+/// Deletes every object whose key starts with `prefix` (taken relative to
+/// the default prefix), with `delete_objects` batches of up to 1000 keys.
+///
+/// With `dir`, the prefix is a folder: `logs` deletes `logs/...` but not
+/// `logs-old/...`. Unless `yes` is set, asks for confirmation first,
+/// showing the count. Keys the backend refuses are reported one by one,
+/// and make the command fail once the rest are deleted.
+pub async fn delete_prefix(
+    prefix: &str,
+    dir: bool,
+    yes: bool,
+    config: &StorageConfig,
+    verbose: bool,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let prefix = if dir {
+        config.prefixed(&as_dir(prefix, &config.delimiter))
+    } else {
+        config.prefixed(prefix)
+    };
+    if prefix.is_empty() {
+        bail!("Refusing to delete every object in the bucket; give a prefix, or use purge");
+    }
     if verbose {
-        println!("✅ Deleted file: {}", file_name);
+        elog!("🗑️ Deleting every object under '{}'", prefix);
+    }
+
+    let client = create_client(config, verbose).await?;
+    let objects = list_all(&client, &config.bucket, Some(&prefix)).await?;
+    let keys: Vec<ObjectIdentifier> = objects
+        .iter()
+        .filter_map(|o| o.key())
+        .map(|key| ObjectIdentifier::builder().key(key).build())
+        .collect::<std::result::Result<_, _>>()?;
+    if keys.is_empty() {
+        writeln!(out, "Nothing to delete under '{}'", prefix)?;
+        return Ok(());
+    }
+
+    if !yes {
+        if !std::io::stdin().is_terminal() {
+            bail!("Refusing to delete without confirmation; pass --yes to skip the prompt");
+        }
+        let confirmed = dialoguer::Confirm::new()
+            .with_prompt(format!(
+                "Delete {} object(s) under '{}' in bucket {}?",
+                keys.len(),
+                prefix,
+                config.bucket
+            ))
+            .default(false)
+            .interact()?;
+        if !confirmed {
+            writeln!(out, "Cancelled; nothing was deleted")?;
+            return Ok(());
+        }
     }
 
+    let summary = delete_batch(&client, &config.bucket, keys, verbose).await?;
+    for (key, message) in &summary.failed {
+        elog!("❌ Failed to delete {}: {}", key, message);
+    }
+    if config.json {
+        let failed: Vec<_> = summary
+            .failed
+            .iter()
+            .map(|(key, message)| serde_json::json!({ "key": key, "error": message }))
+            .collect();
+        let result = serde_json::json!({
+            "prefix": prefix,
+            "bucket": config.bucket,
+            "deleted": summary.deleted,
+            "failed": failed,
+        });
+        writeln!(out, "{}", result)?;
+    } else {
+        writeln!(
+            out,
+            "Deleted {} object(s), {} failed",
+            summary.deleted,
+            summary.failed.len()
+        )?;
+    }
+    if !summary.failed.is_empty() {
+        bail!("{} object(s) could not be deleted", summary.failed.len());
+    }
     Ok(())
 }
+
+/// Outcome of a batched `delete_objects` run.
+#[derive(Debug, Default)]
+pub struct BatchDeleteSummary {
+    pub deleted: usize,
+    /// `(key, message)` for every object the backend refused to delete
+    pub failed: Vec<(String, String)>,
+}
+
+/// Deletes `objects` in batches of 1000, the `delete_objects` maximum.
+pub async fn delete_batch(
+    client: &Client,
+    bucket: &str,
+    objects: Vec<ObjectIdentifier>,
+    verbose: bool,
+) -> Result<BatchDeleteSummary> {
+    let mut summary = BatchDeleteSummary::default();
+
+    for chunk in objects.chunks(1000) {
+        let response = client
+            .delete_objects()
+            .bucket(bucket)
+            .delete(
+                Delete::builder()
+                    .set_objects(Some(chunk.to_vec()))
+                    .quiet(true)
+                    .build()?,
+            )
+            .send()
+            .await
+            .map_err(StorageError::from)?;
+
+        let errors = response.errors();
+        summary.deleted += chunk.len() - errors.len();
+        for error in errors {
+            summary.failed.push((
+                error.key().unwrap_or("unknown").to_string(),
+                error.message().unwrap_or("unknown error").to_string(),
+            ));
+        }
+
+        if verbose {
+            elog!(
+                "  Deleted batch of {} ({} failed)",
+                chunk.len() - errors.len(),
+                errors.len()
+            );
+        }
+    }
+
+    Ok(summary)
+}
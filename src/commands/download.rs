@@ -1,90 +1,938 @@
-use anyhow::Result;
-use std::{env, fs, path::Path};
-use tokio::io::AsyncWriteExt;
+use anyhow::{bail, Context, Result};
+use async_compression::tokio::write::GzipDecoder;
+use aws_sdk_s3::operation::get_object::GetObjectOutput;
+use aws_sdk_s3::primitives::{ByteStream, ByteStreamError};
+use aws_sdk_s3::primitives::{DateTime, DateTimeFormat};
+use aws_sdk_s3::types::{ChecksumMode, Object};
+use bytes::Bytes;
+use futures::StreamExt;
+use indicatif::ProgressBar;
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use std::{env, fs, io::Write, path::Path};
+use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc::Receiver;
 
-use crate::{config::StorageConfig, s3_client::create_client, utils::format_size};
+use crate::{
+    commands::list::{filter_by_tags, list_all},
+    config::StorageConfig,
+    crypto::{self, Decryptor},
+    error::StorageError,
+    keys::{
+        as_dir, is_dir_key, parse_s3_uri, sanitize_key, ORIGINAL_FILENAME_METADATA, SHA256_METADATA,
+    },
+    permissions,
+    s3_client::{create_client, with_region_retry},
+    utils::{hash_file, hex, make_progress_bar, report_batch},
+};
 
-pub async fn download_file(
+#[derive(Debug, Clone)]
+pub struct DownloadOptions {
+    pub output: Option<String>,
+    /// Print a presigned URL instead of downloading
+    pub presign: bool,
+    /// Confirm the object exists before presigning
+    pub check: bool,
+    pub expires_seconds: u64,
+    /// With `presign`, also draw the URL as a QR code on stderr
+    pub qr: bool,
+    /// With `presign`, also save the URL as a QR code PNG here
+    pub qr_file: Option<String>,
+    /// Write buffer size in bytes for the output file; 0 writes each
+    /// chunk as it arrives
+    pub buffer_size: usize,
+    /// Read the body ahead on a separate task while writing
+    pub prefetch: bool,
+    /// Only download if the object changed after this RFC 3339 time
+    pub if_modified_since: Option<String>,
+    /// Name the local file from the object's original-filename metadata
+    pub restore_filename: bool,
+    /// Gunzip `.gz` keys while writing, dropping the suffix from the name
+    pub auto_decompress: bool,
+    /// On NotFound, wait up to this long for the object to appear
+    pub wait_for: Option<Duration>,
+    /// Check the SHA-256 of the downloaded bytes against the one stored
+    /// with the object, when there is one
+    pub verify: bool,
+    /// Decrypt client-side encrypted objects; otherwise save the ciphertext
+    pub decrypt: bool,
+    /// Restore the Unix mode and owner recorded at upload
+    pub preserve_permissions: bool,
+    /// Draw a progress bar; off when several downloads run at once
+    pub show_progress: bool,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        DownloadOptions {
+            output: None,
+            presign: false,
+            check: false,
+            expires_seconds: 3600,
+            qr: false,
+            qr_file: None,
+            buffer_size: 0,
+            prefetch: false,
+            if_modified_since: None,
+            restore_filename: false,
+            auto_decompress: false,
+            wait_for: None,
+            verify: true,
+            decrypt: true,
+            preserve_permissions: false,
+            show_progress: true,
+        }
+    }
+}
+
+/// Draws `code` with half-block characters, two modules per character cell.
+/// Light and dark are swapped, since most terminals are light-on-dark and
+/// scanners expect dark modules on a light background.
+fn render_qr(code: &qrcode::QrCode) -> String {
+    use qrcode::render::unicode::Dense1x2;
+
+    code.render::<Dense1x2>()
+        .dark_color(Dense1x2::Light)
+        .light_color(Dense1x2::Dark)
+        .quiet_zone(true)
+        .build()
+}
+
+/// Creates the directory a download is written into, if it's missing,
+/// with errors that name the path at fault.
+fn create_output_dir(dir: &Path, verbose: bool) -> Result<()> {
+    if dir.as_os_str().is_empty() || dir.is_dir() {
+        return Ok(());
+    }
+    // create_dir_all's error for a file in the way is just "File exists"
+    if let Some(file) = dir.ancestors().find(|a| a.exists()) {
+        if !file.is_dir() {
+            bail!(StorageError::Io(std::io::Error::new(std::io::ErrorKind::AlreadyExists, format!(
+                "Can't create directory {}: {} is a file, not a directory (choose another --output)",
+                dir.display(),
+                file.display()
+            ))));
+        }
+    }
+    fs::create_dir_all(dir)
+        .map_err(StorageError::from)
+        .with_context(|| {
+        format!(
+            "Failed to create directory {} (check you have write permission there, or choose another --output)",
+            dir.display()
+        )
+    })?;
+    if verbose {
+        elog!("  Created directory {}", dir.display());
+    }
+    Ok(())
+}
+
+/// Which existing local files a recursive download leaves alone.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Overwrite {
+    /// Download every object
+    #[default]
+    Always,
+    /// Skip files that already match the object
+    SkipExisting,
+    /// Skip files at least as new as the object
+    UpdateOnly,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RecursiveOptions {
+    /// Only download objects carrying every one of these tags
+    pub tags: Vec<(String, String)>,
+    pub overwrite: Overwrite,
+    /// Treat the prefix as a folder, so `logs` doesn't match `logs-old/`
+    pub dir: bool,
+}
+
+/// Downloads every object under `file_name`, taken as a key prefix (or an
+/// `s3://bucket/prefix` URI), that carries all of `recursive.tags`, into
+/// the `options.output` directory (default: the current one). Each object
+/// is saved at its key relative to the prefix's folder.
+pub async fn download_recursive(
     file_name: &str,
-    output_path: Option<&str>,
-    presign: bool,
-    expires_seconds: u64,
+    recursive: &RecursiveOptions,
+    options: &DownloadOptions,
     config: &StorageConfig,
     verbose: bool,
+    out: &mut dyn Write,
 ) -> Result<()> {
+    let (config, prefix) = if file_name.starts_with("s3://") {
+        let (bucket, key) = parse_s3_uri(file_name)?;
+        let config = StorageConfig {
+            bucket,
+            default_prefix: None,
+            ..config.clone()
+        };
+        (config, key)
+    } else {
+        (config.clone(), config.prefixed(file_name))
+    };
+    let prefix = if recursive.dir {
+        as_dir(&prefix, &config.delimiter)
+    } else {
+        prefix
+    };
+    let dir = match &options.output {
+        Some(dir) => Path::new(dir).to_path_buf(),
+        None => env::current_dir()?,
+    };
+    if verbose {
+        elog!(
+            "📥 Downloading everything under '{}' into {}",
+            prefix,
+            dir.display()
+        );
+    }
+
+    let planned = plan_prefix_download(&prefix, &dir, recursive, &config, verbose).await?;
+    if planned.is_empty() {
+        writeln!(out, "Nothing to download under '{}'", prefix)?;
+        return Ok(());
+    }
+    download_planned(&planned, options, &config, verbose, out).await
+}
+
+/// The part of `prefix` up to and including its last `delimiter`, so a
+/// partial name like `logs` keeps `logs-old/a.txt` whole rather than
+/// saving it as `-old/a.txt`.
+fn folder_of<'a>(prefix: &'a str, delimiter: &str) -> &'a str {
+    match prefix.rfind(delimiter) {
+        Some(end) => &prefix[..end + delimiter.len()],
+        None => "",
+    }
+}
+
+/// Lists the objects under `prefix` that carry all of `recursive.tags`,
+/// each paired with its path under `dir`: the key relative to the prefix's
+/// folder, with each `config.delimiter` segment a directory. Folder
+/// markers, and local files `recursive.overwrite` keeps, are skipped.
+pub async fn plan_prefix_download(
+    prefix: &str,
+    dir: &Path,
+    recursive: &RecursiveOptions,
+    config: &StorageConfig,
+    verbose: bool,
+) -> Result<Vec<(String, PathBuf)>> {
+    let tags = &recursive.tags;
     let client = create_client(config, verbose).await?;
+    let mut objects = list_all(
+        &client,
+        &config.bucket,
+        (!prefix.is_empty()).then_some(prefix),
+    )
+    .await?;
+    if !tags.is_empty() {
+        objects =
+            filter_by_tags(&client, &config.bucket, objects, tags, config.concurrency).await?;
+    }
+
+    let mut planned = Vec::new();
+    let base = folder_of(prefix, &config.delimiter);
+    for object in &objects {
+        let Some(key) = object.key() else { continue };
+        let relative = &key[base.len()..];
+        if is_dir_key(relative, &config.delimiter) {
+            continue;
+        }
+        // A key can't be allowed to climb out of the target directory
+        let relative = sanitize_key(&relative.replace(config.delimiter.as_str(), "/"))?;
+        planned.push((object, key.to_string(), dir.join(relative)));
+    }
 
-    if presign {
+    let overwrite = recursive.overwrite;
+    let checked: Vec<Result<Option<(String, PathBuf)>>> = futures::stream::iter(planned)
+        .map(|(object, key, path)| async move {
+            if is_current(object, &path, overwrite).await? {
+                if verbose {
+                    elog!("  Skipping {}: {} is up to date", key, path.display());
+                }
+                return Ok(None);
+            }
+            Ok(Some((key, path)))
+        })
+        .buffered(config.concurrency.max(1))
+        .collect()
+        .await;
+    let total = checked.len();
+    let planned = checked
+        .into_iter()
+        .filter_map(Result::transpose)
+        .collect::<Result<Vec<_>>>()?;
+    if planned.len() < total {
+        elog!(
+            "Skipping {} file(s) already up to date",
+            total - planned.len()
+        );
+    }
+    Ok(planned)
+}
+
+/// Whether the local file at `path` can be kept instead of downloading
+/// `object` again.
+///
+/// With `SkipExisting`, the sizes must match, and then the contents: by MD5
+/// when the ETag is a plain MD5, otherwise by the file being at least as
+/// new as the object. With `UpdateOnly`, only the modification times count.
+async fn is_current(object: &Object, path: &Path, overwrite: Overwrite) -> Result<bool> {
+    if overwrite == Overwrite::Always {
+        return Ok(false);
+    }
+    let Ok(local) = fs::metadata(path) else {
+        return Ok(false);
+    };
+    let local_modified = local
+        .modified()?
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs() as i64;
+    let newer_locally = object
+        .last_modified()
+        .is_some_and(|remote| local_modified >= remote.secs());
+    if overwrite == Overwrite::UpdateOnly {
+        return Ok(newer_locally);
+    }
+
+    if object.size() != Some(local.len() as i64) {
+        return Ok(false);
+    }
+    let etag = object.e_tag().unwrap_or_default().trim_matches('"');
+    if !etag.is_empty() && !etag.contains('-') {
+        let path = path.to_path_buf();
+        let md5 = tokio::task::spawn_blocking(move || hash_file::<Md5>(&path)).await??;
+        return Ok(etag.eq_ignore_ascii_case(&hex(&md5)));
+    }
+    // Multipart ETags aren't a content hash
+    Ok(newer_locally)
+}
+
+/// Downloads each planned `(key, path)`, `config.concurrency` at a time. A
+/// failure doesn't stop the rest; the command fails at the end if any did.
+pub async fn download_planned(
+    planned: &[(String, PathBuf)],
+    options: &DownloadOptions,
+    config: &StorageConfig,
+    verbose: bool,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let results: Vec<(&String, Result<Vec<u8>>)> = futures::stream::iter(planned)
+        .map(|(key, path)| async move {
+            let options = DownloadOptions {
+                output: Some(path.to_string_lossy().into_owned()),
+                show_progress: false,
+                ..options.clone()
+            };
+            // Each download reports into its own buffer so lines don't interleave
+            let mut report = Vec::new();
+            let uri = format!("s3://{}/{}", config.bucket, key);
+            let result = download_file(&uri, &options, config, verbose, &mut report)
+                .await
+                .map(|_| report);
+            (key, result)
+        })
+        .buffer_unordered(config.concurrency.max(1))
+        .collect()
+        .await;
+    report_batch(results, "download", "Downloaded", out)
+}
+
+/// An `--output` of `-` writes the object to stdout.
+pub const STDOUT_PATH: &str = "-";
+
+/// Body chunks the reader may fetch ahead of the writer.
+const PREFETCH_CHUNKS: usize = 8;
+
+/// Where the writer takes body chunks from.
+enum Chunks {
+    /// Straight from the response body
+    Direct(ByteStream),
+    /// From a reader task fetching up to `PREFETCH_CHUNKS` ahead
+    Prefetched(Receiver<Result<Bytes, ByteStreamError>>),
+}
+
+impl Chunks {
+    async fn next(&mut self) -> Option<Result<Bytes, ByteStreamError>> {
+        match self {
+            Chunks::Direct(body) => body.try_next().await.transpose(),
+            Chunks::Prefetched(rx) => rx.recv().await,
+        }
+    }
+}
+
+/// What `get_object` came back with.
+enum Fetched {
+    Object(Box<GetObjectOutput>),
+    Missing,
+    NotModified,
+}
+
+/// Downloads an object, or with `presign` writes a presigned URL to `out`.
+pub async fn download_file(
+    file_name: &str,
+    options: &DownloadOptions,
+    config: &StorageConfig,
+    verbose: bool,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let if_modified_since = options
+        .if_modified_since
+        .as_deref()
+        .map(|s| {
+            DateTime::from_str(s, DateTimeFormat::DateTime)
+                .with_context(|| format!("Invalid RFC 3339 timestamp: {}", s))
+        })
+        .transpose()?;
+
+    // An s3://bucket/key URI names its own bucket and bypasses the default prefix
+    let uri_config;
+    let (config, key, local_name) = if file_name.starts_with("s3://") {
+        let (bucket, key) = parse_s3_uri(file_name)?;
+        uri_config = StorageConfig {
+            bucket,
+            ..config.clone()
+        };
+        (&uri_config, key.clone(), key)
+    } else {
+        (config, config.prefixed(file_name), file_name.to_string())
+    };
+
+    if options.presign {
         // Generate presigned URL
         if verbose {
-            println!("🔗 Generating presigned URL for {}", file_name);
+            elog!("🔗 Generating presigned URL for {}", key);
+        }
+        let client = create_client(config, verbose).await?;
+        if options.check {
+            match client
+                .head_object()
+                .bucket(&config.bucket)
+                .key(&key)
+                .send()
+                .await
+            {
+                Ok(_) => {}
+                Err(e) if e.as_service_error().is_some_and(|se| se.is_not_found()) => {
+                    return Err(StorageError::NotFound {
+                        key,
+                        bucket: config.bucket.clone(),
+                    }
+                    .into());
+                }
+                Err(e) => return Err(StorageError::from(e).into()),
+            }
+            if verbose {
+                elog!("  Confirmed {} exists", key);
+            }
         }
         let presign_config = aws_sdk_s3::presigning::PresigningConfig::expires_in(
-            std::time::Duration::from_secs(expires_seconds),
+            std::time::Duration::from_secs(options.expires_seconds),
         )?;
         let presigned_req = client
             .get_object()
             .bucket(&config.bucket)
-            .key(file_name)
+            .key(&key)
             .presigned(presign_config)
             .await?;
-        println!("{}", presigned_req.uri());
+        if config.json {
+            let result = serde_json::json!({
+                "key": key,
+                "bucket": config.bucket,
+                "url": presigned_req.uri(),
+                "expires_in": options.expires_seconds,
+            });
+            writeln!(out, "{}", result)?;
+        } else {
+            writeln!(out, "{}", presigned_req.uri())?;
+        }
+        if options.qr || options.qr_file.is_some() {
+            let code = qrcode::QrCode::new(presigned_req.uri())
+                .context("The presigned URL is too long for a QR code")?;
+            if options.qr {
+                // stderr, so stdout still carries just the URL
+                elog!("{}", render_qr(&code));
+            }
+            if let Some(path) = &options.qr_file {
+                code.render::<image::Luma<u8>>()
+                    .min_dimensions(256, 256)
+                    .build()
+                    .save_with_format(path, image::ImageFormat::Png)
+                    .with_context(|| format!("Failed to save QR code to {}", path))?;
+                if verbose {
+                    elog!("  Saved QR code to {}", path);
+                }
+            }
+        }
         return Ok(());
     }
 
-    // Determine output path
-    let output_path = match output_path {
-        Some(p) => Path::new(p).to_path_buf(),
-        None => env::current_dir()?.join(file_name),
-    };
+    // With `--output -` the object's bytes are all that goes to stdout, and
+    // anything else the command reports goes to stderr
+    let to_stdout = options.output.as_deref() == Some(STDOUT_PATH);
+    if to_stdout && options.preserve_permissions {
+        bail!(StorageError::Config(
+            "--preserve-permissions needs a file to apply to; it can't be used with --output -"
+                .to_string()
+        ));
+    }
+    let mut stderr = std::io::stderr();
+    let out: &mut dyn Write = if to_stdout { &mut stderr } else { out };
 
     if verbose {
-        println!("📥 Downloading {} -> {}", file_name, output_path.display());
+        elog!("📥 Downloading {}", key);
     }
 
-    if let Some(parent) = output_path.parent() {
-        if !parent.exists() {
-            fs::create_dir_all(parent)?;
+    let mut fetched =
+        fetch_object(config, &key, if_modified_since, options.verify, verbose).await?;
+    if let (Fetched::Missing, Some(timeout)) = (&fetched, options.wait_for) {
+        if wait_for_object(config, &key, timeout, verbose).await? {
+            fetched =
+                fetch_object(config, &key, if_modified_since, options.verify, verbose).await?;
+        }
+    }
+    let object = match fetched {
+        Fetched::Object(object) => object,
+        Fetched::Missing => {
+            return Err(StorageError::NotFound {
+                key,
+                bucket: config.bucket.clone(),
+            }
+            .into());
+        }
+        Fetched::NotModified => {
+            let since = options.if_modified_since.as_deref().unwrap_or_default();
+            if config.json {
+                let result = serde_json::json!({
+                    "key": key,
+                    "bucket": config.bucket,
+                    "skipped": "not_modified",
+                    "if_modified_since": since,
+                });
+                writeln!(out, "{}", result)?;
+            } else {
+                writeln!(out, "Skipped {}: not modified since {}", key, since)?;
+            }
+            return Ok(());
+        }
+    };
+
+    let decompress = options.auto_decompress && key.ends_with(".gz");
+
+    // Determine output path
+    let output_path = match &options.output {
+        Some(p) => Path::new(p).to_path_buf(),
+        None => {
+            let restored = options
+                .restore_filename
+                .then(|| original_filename(&object))
+                .flatten();
             if verbose {
-                println!("  Created directory {}", parent.display());
+                if let Some(name) = &restored {
+                    elog!("  Restored original filename: {}", name);
+                }
             }
+            let name = restored.unwrap_or(local_name);
+            let name = match name.strip_suffix(".gz") {
+                Some(stripped) if decompress && !stripped.is_empty() => stripped.to_string(),
+                _ => name,
+            };
+            env::current_dir()?.join(name)
+        }
+    };
+    if to_stdout {
+        if verbose {
+            elog!("  Writing to stdout");
+        }
+    } else {
+        if verbose {
+            elog!("  Writing to {}", output_path.display());
+        }
+        if let Some(parent) = output_path.parent() {
+            create_output_dir(parent, verbose)?;
         }
     }
 
-    let mut object = client
-        .get_object()
-        .bucket(&config.bucket)
-        .key(file_name)
-        .send()
-        .await?;
     let content_length = object.content_length().unwrap_or(0);
+    let object_metadata = object.metadata().cloned().unwrap_or_default();
+
+    // Download beside the target and rename into place at the end, so a
+    // failed or interrupted download never leaves a truncated file there
+    let mut partial_name = output_path.file_name().unwrap_or_default().to_os_string();
+    partial_name.push(".partial");
+    let partial_path = output_path.with_file_name(partial_name);
+
+    let expected = if options.verify {
+        expected_sha256(&object)?
+    } else {
+        None
+    };
+    let mut hasher = options.verify.then(Sha256::new);
+
+    let mut decryptor = match object.metadata() {
+        Some(metadata) if options.decrypt && crypto::is_encrypted(Some(metadata)) => {
+            if verbose {
+                elog!("  Object is client-side encrypted; decrypting");
+            }
+            let passphrase = crypto::passphrase(config, false)?;
+            Some(Decryptor::new(metadata, &passphrase)?)
+        }
+        _ => None,
+    };
+
+    // With --prefetch, read ahead on a separate task so the network isn't
+    // idle while a chunk is being written; the bounded channel caps how
+    // much is held in memory
+    let mut reader = None;
+    let mut chunks = if options.prefetch {
+        let mut body = object.body;
+        let (tx, rx) = tokio::sync::mpsc::channel(PREFETCH_CHUNKS);
+        reader = Some(tokio::spawn(async move {
+            while let Some(chunk) = body.try_next().await.transpose() {
+                let failed = chunk.is_err();
+                if tx.send(chunk).await.is_err() || failed {
+                    break;
+                }
+            }
+        }));
+        Chunks::Prefetched(rx)
+    } else {
+        Chunks::Direct(object.body)
+    };
 
-    let mut file = tokio::fs::File::create(&output_path).await?;
-    let mut downloaded = 0;
+    let progress = if content_length > 0 && !to_stdout && options.show_progress {
+        make_progress_bar(
+            content_length as u64,
+            config.units,
+            config.progress,
+            verbose,
+        )
+    } else {
+        ProgressBar::hidden()
+    };
+    let written = async {
+        // With a buffer size, coalesce small network chunks into fewer,
+        // larger writes; chunks at least that big are written directly
+        let buffer_size = options.buffer_size.max(1);
+        let sink = Sink {
+            progress: &progress,
+            hasher: hasher.as_mut(),
+            decryptor: decryptor.as_mut(),
+            decompress,
+            verbose,
+        };
+        if to_stdout {
+            let stdout = BufWriter::with_capacity(buffer_size, tokio::io::stdout());
+            write_body(&mut chunks, stdout, sink).await?;
+        } else {
+            let file = tokio::fs::File::create(&partial_path).await?;
+            let file = write_body(
+                &mut chunks,
+                BufWriter::with_capacity(buffer_size, file),
+                sink,
+            )
+            .await?;
+            file.get_ref().sync_all().await?;
+        }
 
-    while let Some(chunk) = object.body.try_next().await? {
-        downloaded += chunk.len();
-        file.write_all(&chunk).await?;
+        if let Some(hasher) = hasher {
+            let digest = hasher.finalize();
+            if verbose {
+                elog!("  SHA-256: {}", hex(&digest));
+            }
+            if let Some((expected, source)) = &expected {
+                if digest[..] != expected[..] {
+                    bail!(StorageError::backend(format!(
+                        "SHA-256 mismatch for {}: expected {} ({}), got {}; {}",
+                        key,
+                        hex(expected),
+                        source,
+                        hex(&digest),
+                        if to_stdout {
+                            "the data written to stdout is corrupt"
+                        } else {
+                            "the download was discarded"
+                        }
+                    )));
+                }
+                if verbose {
+                    elog!("  Verified against {}", source);
+                }
+            }
+        }
+        anyhow::Ok(())
+    }
+    .await;
 
-        if verbose && content_length > 0 {
-            let percent = (downloaded as f64 / content_length as f64 * 100.0) as u32;
-            print!(
-                "\r  Progress: {}% ({}/{})",
-                percent,
-                format_size(downloaded as u64),
-                format_size(content_length as u64)
-            );
+    if let Err(e) = written {
+        progress.abandon();
+        if let Some(reader) = &reader {
+            reader.abort();
+        }
+        if !to_stdout {
+            let _ = tokio::fs::remove_file(&partial_path).await;
         }
+        return Err(e);
     }
 
-    if verbose && content_length > 0 {
-        println!("\n✅ Download completed");
+    progress.finish_and_clear();
+    if verbose {
+        elog!("  ✅ Download completed");
     }
+    if to_stdout {
+        return Ok(());
+    }
+
+    tokio::fs::rename(&partial_path, &output_path)
+        .await
+        .map_err(StorageError::from)
+        .with_context(|| {
+            format!(
+                "Failed to move {} into place at {}",
+                partial_path.display(),
+                output_path.display()
+            )
+        })?;
+    if options.preserve_permissions {
+        permissions::restore(&output_path, &object_metadata, verbose)?;
+    }
+    if verbose {
+        elog!("  Saved to: {}", output_path.display());
+    }
+    if config.json {
+        let result = serde_json::json!({
+            "key": key,
+            "bucket": config.bucket,
+            "path": output_path,
+            "size": content_length,
+        });
+        writeln!(out, "{}", result)?;
+    }
+
+    Ok(())
+}
+
+async fn fetch_object(
+    config: &StorageConfig,
+    key: &str,
+    if_modified_since: Option<DateTime>,
+    with_checksum: bool,
+    verbose: bool,
+) -> Result<Fetched> {
+    with_region_retry(config, verbose, |client| async move {
+        match client
+            .get_object()
+            .bucket(&config.bucket)
+            .key(key)
+            .set_if_modified_since(if_modified_since)
+            .set_checksum_mode(with_checksum.then_some(ChecksumMode::Enabled))
+            .send()
+            .await
+        {
+            Ok(object) => Ok(Fetched::Object(Box::new(object))),
+            Err(e) if e.as_service_error().is_some_and(|se| se.is_no_such_key()) => {
+                Ok(Fetched::Missing)
+            }
+            Err(e) if e.raw_response().is_some_and(|r| r.status().as_u16() == 304) => {
+                Ok(Fetched::NotModified)
+            }
+            Err(e) => Err(StorageError::from(e).into()),
+        }
+    })
+    .await
+}
 
-    file.flush().await?;
+/// Polls `head_object` until `key` exists, for consumers that may start
+/// before the producer has finished uploading. Returns whether it appeared.
+async fn wait_for_object(
+    config: &StorageConfig,
+    key: &str,
+    timeout: Duration,
+    verbose: bool,
+) -> Result<bool> {
     if verbose {
-        println!("  Saved to: {}", output_path.display());
+        elog!(
+            "  ⏳ {} not found yet, waiting up to {}s",
+            key,
+            timeout.as_secs()
+        );
+    }
+    let client = create_client(config, verbose).await?;
+    let started = Instant::now();
+    let mut delay = Duration::from_millis(250);
+    loop {
+        match client
+            .head_object()
+            .bucket(&config.bucket)
+            .key(key)
+            .send()
+            .await
+        {
+            Ok(_) => {
+                if verbose {
+                    elog!(
+                        "  {} appeared after {} ms",
+                        key,
+                        started.elapsed().as_millis()
+                    );
+                }
+                return Ok(true);
+            }
+            Err(e) if e.as_service_error().is_some_and(|se| se.is_not_found()) => {}
+            Err(e) => return Err(StorageError::from(e).into()),
+        }
+        if started.elapsed() >= timeout {
+            if verbose {
+                elog!("  Gave up waiting for {} after {}s", key, timeout.as_secs());
+            }
+            return Ok(false);
+        }
+        tokio::time::sleep(delay.min(timeout.saturating_sub(started.elapsed()))).await;
+        delay = (delay * 2).min(Duration::from_secs(5));
+    }
+}
+
+/// Where downloaded bytes are accounted for on their way to the output.
+struct Sink<'a> {
+    progress: &'a ProgressBar,
+    hasher: Option<&'a mut Sha256>,
+    decryptor: Option<&'a mut Decryptor>,
+    /// Gunzip the (decrypted) bytes before writing them
+    decompress: bool,
+    verbose: bool,
+}
+
+/// Writes the received body to `writer` and returns it, flushed.
+async fn write_body<W: AsyncWrite + Unpin>(
+    chunks: &mut Chunks,
+    mut writer: W,
+    sink: Sink<'_>,
+) -> Result<W> {
+    if !sink.decompress {
+        write_chunks(
+            chunks,
+            &mut writer,
+            sink.progress,
+            sink.hasher,
+            sink.decryptor,
+        )
+        .await?;
+        writer.flush().await?;
+        return Ok(writer);
+    }
+
+    if sink.verbose {
+        elog!("  Decompressing gzip data");
+    }
+    let mut decoder = GzipDecoder::new(writer);
+    // Shutdown also checks the gzip trailer, so truncated data fails there
+    let decoded = async {
+        write_chunks(
+            chunks,
+            &mut decoder,
+            sink.progress,
+            sink.hasher,
+            sink.decryptor,
+        )
+        .await?;
+        decoder.shutdown().await?;
+        anyhow::Ok(())
     }
+    .await;
+    decoded.context("Failed while decompressing gzip data (is the object really gzip?)")?;
+    Ok(decoder.into_inner())
+}
 
+/// Writes received chunks to `writer`, advancing `progress` by the bytes
+/// received and feeding them to `hasher`. With a `decryptor`, the plaintext
+/// is written instead; the hash and progress still cover the bytes received.
+async fn write_chunks<W: AsyncWrite + Unpin>(
+    chunks: &mut Chunks,
+    writer: &mut W,
+    progress: &ProgressBar,
+    mut hasher: Option<&mut Sha256>,
+    mut decryptor: Option<&mut Decryptor>,
+) -> Result<()> {
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk?;
+        match decryptor.as_deref_mut() {
+            Some(decryptor) => writer.write_all(&decryptor.update(&chunk)?).await?,
+            None => writer.write_all(&chunk).await?,
+        }
+        if let Some(hasher) = hasher.as_deref_mut() {
+            hasher.update(&chunk);
+        }
+        progress.inc(chunk.len() as u64);
+    }
+    if let Some(decryptor) = decryptor {
+        writer.write_all(&decryptor.finish()?).await?;
+    }
     Ok(())
 }
+
+/// The SHA-256 the object's bytes should have, with where it came from:
+/// the `x-amz-meta-sha256` entry written on upload, or S3's own full-object
+/// `ChecksumSHA256`. Composite multipart checksums (`...-N`) cover the parts,
+/// not the whole object, so they can't be compared.
+fn expected_sha256(object: &GetObjectOutput) -> Result<Option<(Vec<u8>, &'static str)>> {
+    use base64::Engine;
+
+    if let Some(value) = object.metadata().and_then(|m| m.get(SHA256_METADATA)) {
+        let bytes = (value.len() == 64 && value.is_ascii())
+            .then(|| {
+                (0..64)
+                    .step_by(2)
+                    .map(|i| u8::from_str_radix(&value[i..i + 2], 16))
+                    .collect::<Result<Vec<u8>, _>>()
+                    .ok()
+            })
+            .flatten()
+            .ok_or_else(|| {
+                StorageError::backend(format!(
+                    "x-amz-meta-sha256 '{}' is not a hex SHA-256",
+                    value
+                ))
+            })?;
+        return Ok(Some((bytes, "x-amz-meta-sha256")));
+    }
+    if let Some(value) = object.checksum_sha256().filter(|v| !v.contains('-')) {
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(value)
+            .ok()
+            .filter(|b| b.len() == 32)
+            .ok_or_else(|| {
+                StorageError::backend(format!(
+                    "ChecksumSHA256 '{}' is not a base64 SHA-256",
+                    value
+                ))
+            })?;
+        return Ok(Some((bytes, "ChecksumSHA256")));
+    }
+    Ok(None)
+}
+
+/// The original filename recorded at upload, reduced to a bare file name so
+/// the metadata can't steer the download outside the working directory.
+fn original_filename(object: &GetObjectOutput) -> Option<String> {
+    let encoded = object.metadata()?.get(ORIGINAL_FILENAME_METADATA)?;
+    let decoded = urlencoding::decode(encoded).ok()?;
+    let name = Path::new(decoded.as_ref()).file_name()?.to_str()?;
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folder_of_stops_at_the_last_delimiter() {
+        assert_eq!(folder_of("logs/", "/"), "logs/");
+        assert_eq!(folder_of("logs", "/"), "");
+        assert_eq!(folder_of("app/logs", "/"), "app/");
+        assert_eq!(folder_of("app|logs|2024", "|"), "app|logs|");
+        assert_eq!(folder_of("", "/"), "");
+    }
+}
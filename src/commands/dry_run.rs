@@ -0,0 +1,177 @@
+use crate::{
+    cli::Commands, commands::upload::STDIN_PATH, config::StorageConfig, error::StorageError,
+    keys::as_dir, s3_client::with_region_retry,
+};
+use anyhow::{Context, Result};
+use std::io::Write;
+
+/// Checks the configuration works, then reports what `command` would do
+/// instead of doing it.
+///
+/// The check builds a client and calls `head_bucket`, which needs valid
+/// credentials and a reachable, existing bucket but changes nothing.
+pub async fn dry_run(
+    command: &Commands,
+    config: &StorageConfig,
+    verbose: bool,
+    out: &mut dyn Write,
+) -> Result<()> {
+    if verbose {
+        elog!("🧪 Dry run: checking access to bucket {}", config.bucket);
+    }
+
+    with_region_retry(config, verbose, |client| async move {
+        client
+            .head_bucket()
+            .bucket(&config.bucket)
+            .send()
+            .await
+            .map_err(StorageError::from)?;
+        Ok(())
+    })
+    .await
+    .with_context(|| {
+        format!(
+            "Dry run failed: cannot access bucket '{}' with the current configuration",
+            config.bucket
+        )
+    })?;
+
+    writeln!(out, "Bucket '{}' is reachable", config.bucket)?;
+    writeln!(out, "Would {}", describe(command, config))?;
+    Ok(())
+}
+
+/// One-line summary of the operation `command` would perform.
+fn describe(command: &Commands, config: &StorageConfig) -> String {
+    let bucket = &config.bucket;
+    match command {
+        Commands::Upload {
+            file_path,
+            key: Some(key),
+            ..
+        } => {
+            format!(
+                "upload {} to bucket '{}' as {}",
+                if file_path == STDIN_PATH {
+                    "stdin"
+                } else {
+                    file_path
+                },
+                bucket,
+                config.prefixed(key)
+            )
+        }
+        Commands::Upload { file_path, .. } => {
+            format!("upload {} to bucket '{}'", file_path, bucket)
+        }
+        Commands::UploadUrl { url, .. } => {
+            format!("fetch {} and upload it to bucket '{}'", url, bucket)
+        }
+        Commands::Download {
+            file_name,
+            output,
+            presign,
+            recursive,
+            ..
+        } => {
+            if *recursive {
+                let target = output.as_deref().unwrap_or("the current directory");
+                format!(
+                    "download every object under '{}' to {}",
+                    config.prefixed(file_name),
+                    target
+                )
+            } else if *presign {
+                format!("print a presigned URL for {}", config.prefixed(file_name))
+            } else {
+                let target = output.as_deref().unwrap_or("the current directory");
+                format!("download {} to {}", config.prefixed(file_name), target)
+            }
+        }
+        Commands::List {
+            prefix, limit, dir, ..
+        } => {
+            let prefix = match prefix {
+                Some(p) => Some(config.prefixed(p)),
+                None => config.default_prefix.clone(),
+            };
+            let prefix = match prefix {
+                Some(p) if *dir => Some(as_dir(&p, &config.delimiter)),
+                p => p,
+            };
+            match prefix {
+                Some(p) => format!("list up to {} object(s) under '{}'", limit, p),
+                None => format!("list up to {} object(s) in bucket '{}'", limit, bucket),
+            }
+        }
+        Commands::ListUploads { .. } => {
+            format!("list in-progress multipart uploads in bucket '{}'", bucket)
+        }
+        Commands::Delete {
+            file_name,
+            recursive: true,
+            ..
+        } => {
+            format!("delete every object under '{}'", config.prefixed(file_name))
+        }
+        Commands::Delete { file_name, .. } => {
+            format!("delete {}", config.prefixed(file_name))
+        }
+        Commands::Purge { delete_bucket, .. } => {
+            if *delete_bucket {
+                format!(
+                    "delete every object in bucket '{}', then the bucket",
+                    bucket
+                )
+            } else {
+                format!("delete every object in bucket '{}'", bucket)
+            }
+        }
+        Commands::Copy {
+            source,
+            dest,
+            dest_bucket,
+            move_source,
+            ..
+        } => format!(
+            "{} {} to {}/{}",
+            if *move_source { "move" } else { "copy" },
+            config.prefixed(source),
+            dest_bucket.as_deref().unwrap_or(bucket),
+            config.prefixed(dest)
+        ),
+        Commands::GenerateIndex { prefix } => {
+            let prefix = match prefix {
+                Some(p) => config.prefixed(p),
+                None => config.default_prefix.clone().unwrap_or_default(),
+            };
+            format!(
+                "upload {}index.html listing the objects under '{}'",
+                prefix, prefix
+            )
+        }
+        Commands::Stat { file_name, .. } => {
+            format!("show the metadata of {}", config.prefixed(file_name))
+        }
+        Commands::BucketInfo => format!("show the configuration of bucket '{}'", bucket),
+        Commands::CreateBucket => format!("create bucket '{}'", bucket),
+        Commands::UpdateMetadata { file_name, .. } => {
+            format!("update the metadata of {}", config.prefixed(file_name))
+        }
+        Commands::RenameRegex { pattern, .. } => {
+            format!("rename keys matching /{}/ in bucket '{}'", pattern, bucket)
+        }
+        Commands::Transfer { src, dst, .. } => format!("transfer {} to {}", src, dst),
+        Commands::Sync { local_dir, .. } => {
+            format!("sync {} to bucket '{}'", local_dir, bucket)
+        }
+        Commands::Server { port, .. } => {
+            format!(
+                "start the upload server on port {} for bucket '{}'",
+                port, bucket
+            )
+        }
+        Commands::Completions { shell } => format!("print the {} completion script", shell),
+    }
+}
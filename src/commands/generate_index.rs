@@ -0,0 +1,114 @@
+use crate::{
+    commands::list::list_all, config::StorageConfig, s3_client::with_region_retry,
+    utils::format_size,
+};
+use anyhow::Result;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::Object;
+
+const INDEX_NAME: &str = "index.html";
+
+/// Lists every object under `prefix` and uploads an `index.html` beside them
+/// linking to each, so the prefix can be browsed as a static site.
+pub async fn generate_index(
+    prefix: Option<&str>,
+    config: &StorageConfig,
+    verbose: bool,
+) -> Result<()> {
+    let prefix = match prefix {
+        Some(p) => config.prefixed(p),
+        None => config.default_prefix.clone().unwrap_or_default(),
+    };
+    let index_key = format!("{}{}", prefix, INDEX_NAME);
+
+    if verbose {
+        elog!("🗂️ Generating {} for prefix '{}'", index_key, prefix);
+    }
+
+    let (prefix_ref, index_ref) = (prefix.as_str(), index_key.as_str());
+    let count = with_region_retry(config, verbose, |client| async move {
+        let objects: Vec<Object> = list_all(&client, &config.bucket, Some(prefix_ref))
+            .await?
+            .into_iter()
+            .filter(|o| o.key() != Some(index_ref))
+            .collect();
+
+        let html = render_index(prefix_ref, &objects, config);
+        client
+            .put_object()
+            .bucket(&config.bucket)
+            .key(index_ref)
+            .content_type("text/html; charset=utf-8")
+            // Listings change whenever the prefix does, so don't let them go stale
+            .cache_control("no-cache")
+            .body(ByteStream::from(html.into_bytes()))
+            .send()
+            .await?;
+        Ok(objects.len())
+    })
+    .await?;
+
+    if verbose {
+        elog!("✅ Uploaded {} listing {} object(s)", index_key, count);
+    }
+
+    Ok(())
+}
+
+fn render_index(prefix: &str, objects: &[Object], config: &StorageConfig) -> String {
+    let title = if prefix.is_empty() { "/" } else { prefix };
+    let mut rows = String::new();
+    for object in objects {
+        let Some(key) = object.key() else { continue };
+        let name = key.strip_prefix(prefix).unwrap_or(key);
+        // Relative links keep the page working wherever the bucket is served from
+        let href: Vec<_> = name.split('/').map(urlencoding::encode).collect();
+        let size = format_size(object.size().unwrap_or(0).max(0) as u64, config.units);
+        let modified = object
+            .last_modified()
+            .map(|dt| dt.to_string())
+            .unwrap_or_default();
+        rows.push_str(&format!(
+            "      <tr><td><a href=\"{}\">{}</a></td><td>{}</td><td>{}</td></tr>\n",
+            href.join("/"),
+            escape_html(name),
+            size,
+            modified
+        ));
+    }
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+  <meta charset="utf-8">
+  <title>Index of {title}</title>
+  <style>
+    body {{ font-family: sans-serif; margin: 2rem; }}
+    table {{ border-collapse: collapse; }}
+    td, th {{ padding: 0.25rem 1rem; text-align: left; }}
+  </style>
+</head>
+<body>
+  <h1>Index of {title}</h1>
+  <table>
+    <thead><tr><th>Name</th><th>Size</th><th>Modified</th></tr></thead>
+    <tbody>
+{rows}    </tbody>
+  </table>
+</body>
+</html>
+"#,
+        title = escape_html(title),
+        rows = rows
+    )
+}
+
+/// Escapes `s` for HTML text and quoted attribute values.
+pub(crate) fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
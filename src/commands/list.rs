@@ -1,37 +1,168 @@
-use crate::{config::StorageConfig, s3_client::create_client};
-use anyhow::Result;
+use crate::{
+    commands::{bucket_info::unsupported_or, prefetch::HeadCache},
+    config::StorageConfig,
+    error::StorageError,
+    keys::as_dir,
+    s3_client::{create_client, with_region_retry},
+};
+use anyhow::{bail, Result};
+use aws_sdk_s3::types::{Object, ObjectAttributes};
+use aws_sdk_s3::Client;
+use futures::{StreamExt, TryStreamExt};
+use std::io::Write;
 
+#[derive(Debug, Clone, Default)]
+pub struct ListOptions {
+    pub prefix: Option<String>,
+    pub limit: i32,
+    pub show_owner: bool,
+    /// Resume a previous listing from this token
+    pub continuation_token: Option<String>,
+    /// Print the token needed to resume after this page
+    pub show_token: bool,
+    /// Treat the prefix as a folder, adding a trailing `/` if missing
+    pub dir: bool,
+    /// Fetch and show each object's stored checksum
+    pub show_checksum: bool,
+    /// Fetch and show each object's user metadata
+    pub show_metadata: bool,
+    /// Keys requested per `list_objects_v2` call, at most 1000; 0 means 1000
+    pub page_size: i32,
+    /// Only list objects carrying every one of these tags
+    pub tags: Vec<(String, String)>,
+}
+
+/// Most keys S3 returns from one `list_objects_v2` call.
+pub const MAX_PAGE_SIZE: i32 = 1000;
+
+/// Lists up to `limit` objects (every object when `limit` is zero or
+/// negative), following continuation tokens across pages, and writes the
+/// results to `out`.
 pub async fn list_files(
-    prefix: Option<&str>,
-    limit: i32,
+    options: &ListOptions,
     config: &StorageConfig,
     verbose: bool,
+    out: &mut dyn Write,
 ) -> Result<()> {
+    let limit = options.limit;
+    let show_owner = options.show_owner;
+    let prefix = match &options.prefix {
+        Some(p) => Some(config.prefixed(p)),
+        None => config.default_prefix.clone(),
+    };
+    let prefix = match prefix {
+        Some(p) if options.dir => Some(as_dir(&p, &config.delimiter)),
+        p => p,
+    };
+    let prefix = prefix.as_deref();
+
+    let continuation_token = options.continuation_token.as_deref();
+    if let Some(token) = continuation_token {
+        validate_continuation_token(token)?;
+    }
+
     if verbose {
-        println!("📄 Listing files in bucket {}", config.bucket);
+        elog!("📄 Listing files in bucket {}", config.bucket);
         if let Some(p) = prefix {
-            println!("  Prefix: {}", p);
+            elog!("  Prefix: {}", p);
+        }
+        if limit > 0 {
+            elog!("  Limit: {}", limit);
+        } else {
+            elog!("  Limit: none");
+        }
+        if continuation_token.is_some() {
+            elog!("  Resuming from continuation token");
         }
-        println!("  Limit: {}", limit);
     }
 
-    let client = create_client(config, verbose).await?;
-    let mut request = client
-        .list_objects_v2()
-        .bucket(&config.bucket)
-        .max_keys(limit);
+    let page_size = match options.page_size {
+        n if n > 0 => n.min(MAX_PAGE_SIZE),
+        _ => MAX_PAGE_SIZE,
+    };
+    let tags = &options.tags;
+    let (contents, next_token) = with_region_retry(config, verbose, |client| async move {
+        // Matches can be anywhere under the prefix, so the limit applies
+        // after filtering and there's no page to resume from
+        if !tags.is_empty() {
+            let objects = list_all(&client, &config.bucket, prefix).await?;
+            let mut matched =
+                filter_by_tags(&client, &config.bucket, objects, tags, config.concurrency).await?;
+            if limit > 0 {
+                matched.truncate(limit as usize);
+            }
+            return Ok((matched, None));
+        }
 
-    if let Some(prefix) = prefix {
-        request = request.prefix(prefix);
+        let mut contents: Vec<Object> = Vec::new();
+        let mut token = continuation_token.map(str::to_string);
+        loop {
+            let wanted = if limit > 0 {
+                page_size.min(limit - contents.len() as i32)
+            } else {
+                page_size
+            };
+            let response = client
+                .list_objects_v2()
+                .bucket(&config.bucket)
+                .max_keys(wanted)
+                .fetch_owner(show_owner)
+                .set_prefix(prefix.map(str::to_string))
+                .set_continuation_token(token.take())
+                .send()
+                .await
+                .map_err(StorageError::from)?;
+            contents.extend(response.contents().iter().cloned());
+            token = response
+                .next_continuation_token()
+                .filter(|_| response.is_truncated().unwrap_or(false))
+                .map(str::to_string);
+            if verbose {
+                elog!("  Fetched {} object(s) so far", contents.len());
+            }
+            if token.is_none() || (limit > 0 && contents.len() as i32 >= limit) {
+                return Ok((contents, token));
+            }
+        }
+    })
+    .await?;
+
+    let checksums = if options.show_checksum {
+        let client = create_client(config, verbose).await?;
+        let client_ref = &client;
+        futures::stream::iter(&contents)
+            .map(|object| async move {
+                let key = object.key().unwrap_or_default();
+                fetch_checksum(client_ref, &config.bucket, key).await
+            })
+            .buffered(config.concurrency)
+            .try_collect::<Vec<_>>()
+            .await?
+    } else {
+        Vec::new()
+    };
+
+    if config.json {
+        return write_json(out, options, &contents, &checksums, next_token.as_deref());
     }
 
-    let response = request.send().await?;
-    let contents = response.contents();
+    // User metadata is only returned by a HEAD of each object
+    let heads = if options.show_metadata {
+        elog!(
+            "⚠️ Showing metadata takes a HeadObject request per object ({} to fetch)",
+            contents.len()
+        );
+        let client = create_client(config, verbose).await?;
+        let keys = contents.iter().filter_map(|o| o.key()).map(str::to_string);
+        HeadCache::prefetch(&client, &config.bucket, keys, config.concurrency, verbose).await?
+    } else {
+        HeadCache::default()
+    };
 
     if contents.is_empty() {
-        println!("No files found");
+        writeln!(out, "No files found")?;
     } else {
-        println!("Found {} file(s):", contents.len());
+        writeln!(out, "Found {} file(s):", contents.len())?;
         for (i, object) in contents.iter().enumerate() {
             let size = object.size().unwrap_or(0);
             let last_modified = object
@@ -39,15 +170,212 @@ pub async fn list_files(
                 .map(|dt| dt.to_string())
                 .unwrap_or_else(|| "unknown".to_string());
 
-            println!(
-                "{}. {} ({} bytes, modified: {})",
+            // Not every S3-compatible endpoint reports a storage class
+            let class = object
+                .storage_class()
+                .map(|c| format!(", class: {}", c.as_str()))
+                .unwrap_or_default();
+            writeln!(
+                out,
+                "{}. {} ({} bytes, modified: {}{})",
                 i + 1,
                 object.key().unwrap_or("unknown"),
                 size,
-                last_modified
-            );
+                last_modified,
+                class
+            )?;
+
+            if show_owner {
+                // Some S3-compatible endpoints omit owner info entirely
+                let owner = object
+                    .owner()
+                    .and_then(|o| match (o.display_name(), o.id()) {
+                        (Some(name), Some(id)) => Some(format!("{} ({})", name, id)),
+                        (Some(name), None) => Some(name.to_string()),
+                        (None, Some(id)) => Some(id.to_string()),
+                        (None, None) => None,
+                    })
+                    .unwrap_or_else(|| "unknown".to_string());
+                writeln!(out, "   owner: {}", owner)?;
+            }
+            if let Some(checksum) = checksums.get(i) {
+                writeln!(out, "   checksum: {}", checksum)?;
+            }
+            let metadata = object
+                .key()
+                .and_then(|key| heads.get(key))
+                .and_then(|head| head.metadata())
+                .filter(|m| !m.is_empty());
+            if let Some(metadata) = metadata {
+                let mut entries: Vec<String> = metadata
+                    .iter()
+                    .map(|(k, v)| format!("{}={}", k, v))
+                    .collect();
+                entries.sort();
+                writeln!(out, "   metadata: {}", entries.join(", "))?;
+            }
         }
     }
 
+    if options.show_token {
+        match next_token {
+            Some(token) => writeln!(out, "Next continuation token: {}", token)?,
+            None => writeln!(out, "No more results")?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the page as a JSON array of
+/// `{key, size, last_modified, etag, storage_class}`,
+/// plus `owner`/`checksum` when asked for. With `show_token` it is wrapped
+/// as `{objects, next_continuation_token}`.
+fn write_json(
+    out: &mut dyn Write,
+    options: &ListOptions,
+    contents: &[Object],
+    checksums: &[String],
+    next_token: Option<&str>,
+) -> Result<()> {
+    let objects: Vec<serde_json::Value> = contents
+        .iter()
+        .enumerate()
+        .map(|(i, object)| {
+            let mut entry = serde_json::json!({
+                "key": object.key(),
+                "size": object.size(),
+                "last_modified": object.last_modified().map(|dt| dt.to_string()),
+                "etag": object.e_tag().map(|e| e.trim_matches('"')),
+                "storage_class": object.storage_class().map(|c| c.as_str()),
+            });
+            if options.show_owner {
+                entry["owner"] = serde_json::json!(object.owner().map(|o| {
+                    serde_json::json!({ "id": o.id(), "display_name": o.display_name() })
+                }));
+            }
+            if let Some(checksum) = checksums.get(i) {
+                entry["checksum"] = serde_json::json!(checksum);
+            }
+            entry
+        })
+        .collect();
+
+    let value = if options.show_token {
+        serde_json::json!({ "objects": objects, "next_continuation_token": next_token })
+    } else {
+        serde_json::Value::Array(objects)
+    };
+    serde_json::to_writer(&mut *out, &value)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+/// Describes the checksum S3 stored for `key`, e.g. `SHA256 abc= (FULL_OBJECT)`.
+/// Objects uploaded without one, and endpoints without
+/// `GetObjectAttributes`, get a note instead of an error.
+async fn fetch_checksum(client: &Client, bucket: &str, key: &str) -> Result<String> {
+    let attributes = match client
+        .get_object_attributes()
+        .bucket(bucket)
+        .key(key)
+        .object_attributes(ObjectAttributes::Checksum)
+        .send()
+        .await
+    {
+        Ok(attributes) => attributes,
+        Err(e) => return unsupported_or(e),
+    };
+    let Some(checksum) = attributes.checksum() else {
+        return Ok("none stored".to_string());
+    };
+
+    let values = [
+        ("CRC32", checksum.checksum_crc32()),
+        ("CRC32C", checksum.checksum_crc32_c()),
+        ("CRC64NVME", checksum.checksum_crc64_nvme()),
+        ("SHA1", checksum.checksum_sha1()),
+        ("SHA256", checksum.checksum_sha256()),
+    ];
+    let mut described: Vec<String> = values
+        .iter()
+        .filter_map(|(name, value)| value.map(|v| format!("{} {}", name, v)))
+        .collect();
+    if described.is_empty() {
+        return Ok("none stored".to_string());
+    }
+    if let Some(kind) = checksum.checksum_type() {
+        described.push(format!("({})", kind.as_str()));
+    }
+    Ok(described.join(" "))
+}
+
+/// Keeps the objects carrying every `(key, value)` in `tags`.
+///
+/// A listing can't be filtered by tag, so this costs one
+/// `GetObjectTagging` request per object, `concurrency` at a time, and
+/// warns about it first. Objects deleted since being listed are dropped.
+pub async fn filter_by_tags(
+    client: &Client,
+    bucket: &str,
+    objects: Vec<Object>,
+    tags: &[(String, String)],
+    concurrency: usize,
+) -> Result<Vec<Object>> {
+    elog!(
+        "⚠️ Filtering by tag takes a GetObjectTagging request per object ({} to check)",
+        objects.len()
+    );
+    let matched: Vec<Option<Object>> = futures::stream::iter(objects)
+        .map(|object| async move {
+            let key = object.key().unwrap_or_default();
+            let tagging = match client
+                .get_object_tagging()
+                .bucket(bucket)
+                .key(key)
+                .send()
+                .await
+            {
+                Ok(tagging) => tagging,
+                Err(e) if e.raw_response().is_some_and(|r| r.status().as_u16() == 404) => {
+                    return Ok(None);
+                }
+                Err(e) => return Err(StorageError::from(e).into()),
+            };
+            let has_all = tags.iter().all(|(name, value)| {
+                tagging
+                    .tag_set()
+                    .iter()
+                    .any(|tag| tag.key() == name && tag.value() == value)
+            });
+            anyhow::Ok(has_all.then_some(object))
+        })
+        .buffered(concurrency.max(1))
+        .try_collect()
+        .await?;
+    Ok(matched.into_iter().flatten().collect())
+}
+
+/// Fetches every object under `prefix`, following continuation tokens.
+pub async fn list_all(client: &Client, bucket: &str, prefix: Option<&str>) -> Result<Vec<Object>> {
+    let mut objects = Vec::new();
+    let mut pages = client
+        .list_objects_v2()
+        .bucket(bucket)
+        .set_prefix(prefix.map(str::to_string))
+        .into_paginator()
+        .send();
+    while let Some(page) = pages.next().await {
+        objects.extend(page.map_err(StorageError::from)?.contents().iter().cloned());
+    }
+    Ok(objects)
+}
+
+/// Continuation tokens are opaque, but always non-empty printable ASCII with
+/// no whitespace; anything else was mangled while being copied around.
+fn validate_continuation_token(token: &str) -> Result<()> {
+    if token.is_empty() || !token.bytes().all(|b| b.is_ascii_graphic()) {
+        bail!("Malformed continuation token: {:?}", token);
+    }
     Ok(())
 }
@@ -0,0 +1,117 @@
+use crate::{config::StorageConfig, keys::as_dir, s3_client::with_region_retry};
+use anyhow::Result;
+use aws_sdk_s3::types::MultipartUpload;
+use aws_sdk_s3::Client;
+use std::io::Write;
+
+/// Lists in-progress multipart uploads with the number of parts each has so
+/// far, so they can be resumed or aborted.
+pub async fn list_uploads(
+    prefix: Option<&str>,
+    dir: bool,
+    config: &StorageConfig,
+    verbose: bool,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let prefix = match prefix {
+        Some(p) => Some(config.prefixed(p)),
+        None => config.default_prefix.clone(),
+    };
+    let prefix = match prefix {
+        Some(p) if dir => Some(as_dir(&p, &config.delimiter)),
+        p => p,
+    };
+    let prefix = prefix.as_deref();
+
+    if verbose {
+        elog!("🧩 Listing multipart uploads in bucket {}", config.bucket);
+        if let Some(p) = prefix {
+            elog!("  Prefix: {}", p);
+        }
+    }
+
+    let uploads = with_region_retry(config, verbose, |client| async move {
+        let uploads = fetch_uploads(&client, &config.bucket, prefix).await?;
+        let mut counted = Vec::with_capacity(uploads.len());
+        for upload in uploads {
+            let parts = count_parts(&client, &config.bucket, &upload).await?;
+            counted.push((upload, parts));
+        }
+        Ok(counted)
+    })
+    .await?;
+
+    if uploads.is_empty() {
+        writeln!(out, "No multipart uploads in progress")?;
+        return Ok(());
+    }
+
+    writeln!(out, "Found {} upload(s) in progress:", uploads.len())?;
+    for (i, (upload, parts)) in uploads.iter().enumerate() {
+        let initiated = upload
+            .initiated()
+            .map(|dt| dt.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        writeln!(
+            out,
+            "{}. {} (upload id: {}, initiated: {}, {} part(s) uploaded)",
+            i + 1,
+            upload.key().unwrap_or("unknown"),
+            upload.upload_id().unwrap_or("unknown"),
+            initiated,
+            parts
+        )?;
+    }
+
+    Ok(())
+}
+
+async fn fetch_uploads(
+    client: &Client,
+    bucket: &str,
+    prefix: Option<&str>,
+) -> Result<Vec<MultipartUpload>> {
+    let mut uploads = Vec::new();
+    let mut key_marker = None;
+    let mut upload_id_marker = None;
+    loop {
+        let page = client
+            .list_multipart_uploads()
+            .bucket(bucket)
+            .set_prefix(prefix.map(str::to_string))
+            .set_key_marker(key_marker)
+            .set_upload_id_marker(upload_id_marker)
+            .send()
+            .await?;
+        uploads.extend(page.uploads().iter().cloned());
+
+        if !page.is_truncated().unwrap_or(false) {
+            break;
+        }
+        key_marker = page.next_key_marker().map(str::to_string);
+        upload_id_marker = page.next_upload_id_marker().map(str::to_string);
+        // Guard against endpoints that claim truncation without markers
+        if key_marker.is_none() {
+            break;
+        }
+    }
+    Ok(uploads)
+}
+
+async fn count_parts(client: &Client, bucket: &str, upload: &MultipartUpload) -> Result<usize> {
+    let (Some(key), Some(upload_id)) = (upload.key(), upload.upload_id()) else {
+        return Ok(0);
+    };
+    let mut pages = client
+        .list_parts()
+        .bucket(bucket)
+        .key(key)
+        .upload_id(upload_id)
+        .into_paginator()
+        .send();
+    let mut count = 0;
+    while let Some(page) = pages.next().await {
+        count += page?.parts().len();
+    }
+    Ok(count)
+}
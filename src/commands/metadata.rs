@@ -0,0 +1,166 @@
+use crate::{
+    commands::copy::copy_source, config::StorageConfig, error::StorageError,
+    s3_client::with_region_retry,
+};
+use anyhow::{bail, Result};
+use aws_sdk_s3::error::ProvideErrorMetadata;
+use aws_sdk_s3::operation::head_object::HeadObjectOutput;
+use aws_sdk_s3::types::MetadataDirective;
+use std::collections::HashMap;
+
+/// Changes to apply to an object's metadata; anything not mentioned is kept.
+#[derive(Debug, Clone, Default)]
+pub struct MetadataUpdate {
+    /// User metadata entries to add or overwrite
+    pub set: Vec<(String, String)>,
+    /// User metadata entries to drop
+    pub remove: Vec<String>,
+    pub content_type: Option<String>,
+    pub cache_control: Option<String>,
+}
+
+impl MetadataUpdate {
+    fn is_empty(&self) -> bool {
+        self.set.is_empty()
+            && self.remove.is_empty()
+            && self.content_type.is_none()
+            && self.cache_control.is_none()
+    }
+
+    /// Applies the update to the object's current user metadata.
+    fn merge(&self, existing: Option<&HashMap<String, String>>) -> HashMap<String, String> {
+        let mut metadata = existing.cloned().unwrap_or_default();
+        for name in &self.remove {
+            metadata.remove(&name.to_lowercase());
+        }
+        for (name, value) in &self.set {
+            // S3 lowercases user metadata names, so match on that
+            metadata.insert(name.to_lowercase(), value.clone());
+        }
+        metadata
+    }
+}
+
+/// Rewrites an object's metadata in place.
+///
+/// `copy_object` with `MetadataDirective::Replace` replaces every header at
+/// once, so the current ones are read with `head_object` first and carried
+/// over unless the update changes them. The copy is conditional on the ETag
+/// seen by the head, so a concurrent overwrite is not clobbered.
+pub async fn update_metadata(
+    file_name: &str,
+    update: &MetadataUpdate,
+    config: &StorageConfig,
+    verbose: bool,
+) -> Result<()> {
+    if update.is_empty() {
+        bail!("Nothing to update: pass --set, --remove, --content-type, or --cache-control");
+    }
+
+    let key = config.prefixed(file_name);
+    if verbose {
+        elog!("🏷️ Updating metadata for {}", key);
+    }
+
+    let key_ref = &key;
+    let updated = with_region_retry(config, verbose, |client| async move {
+        let head = match client
+            .head_object()
+            .bucket(&config.bucket)
+            .key(key_ref)
+            .send()
+            .await
+        {
+            Ok(head) => head,
+            Err(e) if e.as_service_error().is_some_and(|se| se.is_not_found()) => {
+                return Ok(false);
+            }
+            Err(e) => return Err(StorageError::from(e).into()),
+        };
+
+        let metadata = update.merge(head.metadata());
+        if verbose {
+            print_changes(&head, &metadata, update);
+        }
+
+        let result = client
+            .copy_object()
+            .bucket(&config.bucket)
+            .key(key_ref)
+            .copy_source(copy_source(&config.bucket, key_ref))
+            .set_copy_source_if_match(head.e_tag().map(str::to_string))
+            .metadata_directive(MetadataDirective::Replace)
+            .set_metadata(Some(metadata))
+            .set_content_type(
+                update
+                    .content_type
+                    .clone()
+                    .or_else(|| head.content_type().map(str::to_string)),
+            )
+            .set_cache_control(
+                update
+                    .cache_control
+                    .clone()
+                    .or_else(|| head.cache_control().map(str::to_string)),
+            )
+            .set_content_disposition(head.content_disposition().map(str::to_string))
+            .set_content_encoding(head.content_encoding().map(str::to_string))
+            .set_content_language(head.content_language().map(str::to_string))
+            // Otherwise the copy falls back to the bucket defaults
+            .set_storage_class(head.storage_class().cloned())
+            .set_server_side_encryption(head.server_side_encryption().cloned())
+            .set_ssekms_key_id(head.ssekms_key_id().map(str::to_string))
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(e) if e.code() == Some("PreconditionFailed") => bail!(
+                "Object '{}' changed while its metadata was being updated; nothing was written",
+                key_ref
+            ),
+            Err(e) => Err(StorageError::from(e).into()),
+        }
+    })
+    .await?;
+
+    if !updated {
+        return Err(StorageError::NotFound {
+            key,
+            bucket: config.bucket.clone(),
+        }
+        .into());
+    }
+
+    if verbose {
+        elog!("✅ Updated metadata for {}", key);
+    }
+
+    Ok(())
+}
+
+fn print_changes(
+    head: &HeadObjectOutput,
+    metadata: &HashMap<String, String>,
+    update: &MetadataUpdate,
+) {
+    if let Some(ct) = &update.content_type {
+        elog!(
+            "  Content-Type: {} -> {}",
+            head.content_type().unwrap_or("(none)"),
+            ct
+        );
+    }
+    if let Some(cc) = &update.cache_control {
+        elog!(
+            "  Cache-Control: {} -> {}",
+            head.cache_control().unwrap_or("(none)"),
+            cc
+        );
+    }
+    let mut names: Vec<_> = metadata.keys().collect();
+    names.sort();
+    for name in names {
+        elog!("  x-amz-meta-{}: {}", name, metadata[name]);
+    }
+}
@@ -1,5 +1,19 @@
+pub mod bucket_info;
+pub mod copy;
+pub mod create_bucket;
 pub mod delete;
 pub mod download;
+pub mod dry_run;
+pub mod generate_index;
 pub mod list;
+pub mod list_uploads;
+pub mod metadata;
+pub mod prefetch;
+pub mod purge;
+pub mod rename_regex;
 pub mod server;
+pub mod stat;
+pub mod sync;
+pub mod transfer;
 pub mod upload;
+pub mod upload_url;
@@ -0,0 +1,58 @@
+use anyhow::Result;
+use aws_sdk_s3::operation::head_object::HeadObjectOutput;
+use aws_sdk_s3::Client;
+use futures::StreamExt;
+use std::collections::HashMap;
+
+/// `head_object` results for a set of keys, for comparisons that need more
+/// than a listing reports, such as user metadata.
+#[derive(Debug, Default)]
+pub struct HeadCache {
+    heads: HashMap<String, HeadObjectOutput>,
+}
+
+impl HeadCache {
+    /// Heads every key in `keys`, up to `concurrency` at once. Keys that
+    /// turn out not to exist (deleted since they were listed) are left out;
+    /// any other failure fails the whole prefetch.
+    pub async fn prefetch(
+        client: &Client,
+        bucket: &str,
+        keys: impl IntoIterator<Item = String>,
+        concurrency: usize,
+        verbose: bool,
+    ) -> Result<HeadCache> {
+        let started = std::time::Instant::now();
+        let fetched: Vec<Result<Option<(String, HeadObjectOutput)>>> = futures::stream::iter(keys)
+            .map(|key| async move {
+                match client.head_object().bucket(bucket).key(&key).send().await {
+                    Ok(head) => Ok(Some((key, head))),
+                    Err(e) if e.as_service_error().is_some_and(|se| se.is_not_found()) => Ok(None),
+                    Err(e) => Err(anyhow::Error::from(e)
+                        .context(format!("Failed to read the attributes of {}", key))),
+                }
+            })
+            .buffer_unordered(concurrency.max(1))
+            .collect()
+            .await;
+
+        let mut heads = HashMap::with_capacity(fetched.len());
+        for head in fetched {
+            if let Some((key, head)) = head? {
+                heads.insert(key, head);
+            }
+        }
+        if verbose && !heads.is_empty() {
+            elog!(
+                "  Fetched attributes of {} object(s) in {} ms",
+                heads.len(),
+                started.elapsed().as_millis()
+            );
+        }
+        Ok(HeadCache { heads })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&HeadObjectOutput> {
+        self.heads.get(key)
+    }
+}
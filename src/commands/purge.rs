@@ -0,0 +1,160 @@
+use crate::{
+    commands::{bucket_info::is_unsupported, delete::delete_batch},
+    config::StorageConfig,
+    error::StorageError,
+    s3_client::create_client,
+    utils::format_size,
+};
+use anyhow::{bail, Result};
+use aws_sdk_s3::types::ObjectIdentifier;
+use aws_sdk_s3::Client;
+use std::io::Write;
+
+pub async fn purge_bucket(
+    confirm: &str,
+    delete_bucket: bool,
+    config: &StorageConfig,
+    verbose: bool,
+    out: &mut dyn Write,
+) -> Result<()> {
+    if confirm != config.bucket {
+        bail!(
+            "Confirmation '{}' does not match bucket '{}'; nothing was deleted",
+            confirm,
+            config.bucket
+        );
+    }
+
+    let client = create_client(config, verbose).await?;
+    if verbose {
+        elog!("🔥 Purging every object from bucket {}", config.bucket);
+    }
+
+    let (objects, bytes) = match collect_versions(&client, &config.bucket).await? {
+        Some(found) => found,
+        None => {
+            // Not every S3-compatible endpoint implements ListObjectVersions
+            if verbose {
+                elog!("  Version listing unsupported, listing objects");
+            }
+            collect_objects(&client, &config.bucket).await?
+        }
+    };
+
+    if verbose {
+        elog!(
+            "  Found {} object(s)/version(s), {}",
+            objects.len(),
+            format_size(bytes, config.units)
+        );
+    }
+
+    let total = objects.len();
+    let summary = delete_batch(&client, &config.bucket, objects, verbose).await?;
+
+    for (key, message) in &summary.failed {
+        elog!("❌ Failed to delete {}: {}", key, message);
+    }
+    writeln!(
+        out,
+        "Removed {} of {} object(s), {} from bucket {}",
+        summary.deleted,
+        total,
+        format_size(bytes, config.units),
+        config.bucket
+    )?;
+
+    if !summary.failed.is_empty() {
+        bail!(
+            "{} object(s) could not be deleted; bucket not empty",
+            summary.failed.len()
+        );
+    }
+
+    if delete_bucket {
+        client.delete_bucket().bucket(&config.bucket).send().await?;
+        writeln!(out, "Deleted bucket {}", config.bucket)?;
+    }
+
+    Ok(())
+}
+
+/// Lists every version and delete marker, returning them with the total
+/// size, or `None` if the endpoint doesn't implement version listing. Any
+/// other failure is an error: falling back would leave noncurrent versions
+/// behind.
+async fn collect_versions(
+    client: &Client,
+    bucket: &str,
+) -> Result<Option<(Vec<ObjectIdentifier>, u64)>> {
+    let mut objects = Vec::new();
+    let mut bytes = 0u64;
+    let mut key_marker: Option<String> = None;
+    let mut version_marker: Option<String> = None;
+
+    loop {
+        let first_page = key_marker.is_none();
+        let response = client
+            .list_object_versions()
+            .bucket(bucket)
+            .set_key_marker(key_marker.take())
+            .set_version_id_marker(version_marker.take())
+            .send()
+            .await;
+        let response = match response {
+            Ok(response) => response,
+            Err(e) if first_page && is_unsupported(&e) => return Ok(None),
+            Err(e) => return Err(StorageError::from(e).into()),
+        };
+
+        for version in response.versions() {
+            bytes += version.size().unwrap_or(0) as u64;
+            objects.push(
+                ObjectIdentifier::builder()
+                    .set_key(version.key().map(str::to_string))
+                    .set_version_id(version.version_id().map(str::to_string))
+                    .build()?,
+            );
+        }
+        for marker in response.delete_markers() {
+            objects.push(
+                ObjectIdentifier::builder()
+                    .set_key(marker.key().map(str::to_string))
+                    .set_version_id(marker.version_id().map(str::to_string))
+                    .build()?,
+            );
+        }
+
+        if !response.is_truncated().unwrap_or(false) {
+            break;
+        }
+        key_marker = response.next_key_marker().map(str::to_string);
+        version_marker = response.next_version_id_marker().map(str::to_string);
+    }
+
+    Ok(Some((objects, bytes)))
+}
+
+/// Lists current objects only, for endpoints without version listing.
+async fn collect_objects(client: &Client, bucket: &str) -> Result<(Vec<ObjectIdentifier>, u64)> {
+    let mut objects = Vec::new();
+    let mut bytes = 0u64;
+    let mut pages = client
+        .list_objects_v2()
+        .bucket(bucket)
+        .into_paginator()
+        .send();
+
+    while let Some(page) = pages.next().await {
+        for object in page?.contents() {
+            bytes += object.size().unwrap_or(0) as u64;
+            objects.push(
+                ObjectIdentifier::builder()
+                    .set_key(object.key().map(str::to_string))
+                    .build()?,
+            );
+        }
+    }
+
+    Ok((objects, bytes))
+}
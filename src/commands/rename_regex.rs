@@ -0,0 +1,205 @@
+use crate::{
+    commands::{
+        copy::{copy_file, CopyOptions},
+        list::list_all,
+    },
+    config::StorageConfig,
+    keys::sanitize_key,
+    s3_client::create_client,
+};
+use anyhow::{bail, Context, Result};
+use futures::StreamExt;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::io::{IsTerminal, Write};
+
+#[derive(Debug, Clone, Default)]
+pub struct RenameOptions {
+    /// Regex matched against each key, relative to the default prefix
+    pub pattern: String,
+    /// Replacement for the first match; `$1`, `${name}` refer to groups
+    pub replacement: String,
+    /// Only consider keys under this prefix
+    pub prefix: Option<String>,
+    /// Print the planned renames without performing them
+    pub dry_run: bool,
+    /// Skip the confirmation prompt
+    pub yes: bool,
+}
+
+/// One planned rename, with keys relative to the default prefix.
+struct Rename {
+    from: String,
+    to: String,
+    /// ETag seen when listing, so an object replaced since isn't moved
+    etag: Option<String>,
+}
+
+/// Renames every key matching `options.pattern` by copying it to the
+/// substituted key and deleting the original.
+///
+/// Nothing is renamed if any target is invalid, is claimed by two keys, or
+/// already exists.
+pub async fn rename_regex(
+    options: &RenameOptions,
+    config: &StorageConfig,
+    verbose: bool,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let regex = Regex::new(&options.pattern)
+        .with_context(|| format!("Invalid --match regex: {}", options.pattern))?;
+
+    let base = config.prefixed("");
+    let list_prefix = config.prefixed(options.prefix.as_deref().unwrap_or(""));
+    let client = create_client(config, verbose).await?;
+    let objects = list_all(
+        &client,
+        &config.bucket,
+        (!list_prefix.is_empty()).then_some(list_prefix.as_str()),
+    )
+    .await?;
+    if verbose {
+        elog!(
+            "🔎 Matching {} object(s) under '{}' against /{}/",
+            objects.len(),
+            list_prefix,
+            options.pattern
+        );
+    }
+
+    let existing: HashSet<&str> = objects.iter().filter_map(|o| o.key()).collect();
+    let mut renames = Vec::new();
+    let mut problems = Vec::new();
+    for object in &objects {
+        let Some(relative) = object.key().and_then(|k| k.strip_prefix(&base)) else {
+            continue;
+        };
+        if !regex.is_match(relative) {
+            continue;
+        }
+        let replaced = regex.replace(relative, options.replacement.as_str());
+        if replaced == relative {
+            continue;
+        }
+        match sanitize_key(&replaced) {
+            Ok(to) => renames.push(Rename {
+                from: relative.to_string(),
+                to,
+                etag: object.e_tag().map(str::to_string),
+            }),
+            Err(e) => problems.push(format!("{} -> {:?}: {}", relative, replaced, e)),
+        }
+    }
+
+    let mut targets: HashMap<&str, Vec<&str>> = HashMap::new();
+    for rename in &renames {
+        targets.entry(&rename.to).or_default().push(&rename.from);
+    }
+    for (to, from) in &targets {
+        if from.len() > 1 {
+            problems.push(format!(
+                "{} would all be renamed to {}",
+                from.join(", "),
+                to
+            ));
+        }
+    }
+    for rename in &renames {
+        let target = config.prefixed(&rename.to);
+        let exists = if target.starts_with(&list_prefix) {
+            existing.contains(target.as_str())
+        } else {
+            object_exists(&client, &config.bucket, &target).await?
+        };
+        if exists {
+            problems.push(format!(
+                "{} -> {}: target already exists",
+                rename.from, rename.to
+            ));
+        }
+    }
+
+    if !problems.is_empty() {
+        problems.sort();
+        for problem in &problems {
+            elog!("❌ {}", problem);
+        }
+        bail!("{} conflict(s) found; nothing was renamed", problems.len());
+    }
+
+    renames.sort_by(|a, b| a.from.cmp(&b.from));
+    for rename in &renames {
+        writeln!(out, "rename {} -> {}", rename.from, rename.to)?;
+    }
+    if renames.is_empty() {
+        writeln!(out, "No keys match; nothing to rename")?;
+        return Ok(());
+    }
+    if options.dry_run {
+        writeln!(out, "Dry run: would rename {} object(s)", renames.len())?;
+        return Ok(());
+    }
+
+    if !options.yes {
+        if !std::io::stdin().is_terminal() {
+            bail!("Refusing to rename without confirmation; pass --yes to skip the prompt");
+        }
+        let confirmed = dialoguer::Confirm::new()
+            .with_prompt(format!(
+                "Rename {} object(s) in bucket {}?",
+                renames.len(),
+                config.bucket
+            ))
+            .default(false)
+            .interact()?;
+        if !confirmed {
+            writeln!(out, "Cancelled; nothing was renamed")?;
+            return Ok(());
+        }
+    }
+
+    let results: Vec<(&Rename, Result<()>)> = futures::stream::iter(&renames)
+        .map(|rename| async move {
+            let copy_options = CopyOptions {
+                if_match: rename.etag.clone(),
+                move_source: true,
+                ..Default::default()
+            };
+            let result = copy_file(&rename.from, &rename.to, &copy_options, config, verbose).await;
+            (rename, result)
+        })
+        .buffer_unordered(config.concurrency)
+        .collect()
+        .await;
+
+    let mut failed = 0;
+    for (rename, result) in &results {
+        if let Err(e) = result {
+            elog!(
+                "❌ Failed to rename {} -> {}: {:#}",
+                rename.from,
+                rename.to,
+                e
+            );
+            failed += 1;
+        }
+    }
+    writeln!(
+        out,
+        "Renamed {} object(s), {} failed",
+        results.len() - failed,
+        failed
+    )?;
+    if failed > 0 {
+        bail!("{} rename(s) failed", failed);
+    }
+    Ok(())
+}
+
+async fn object_exists(client: &aws_sdk_s3::Client, bucket: &str, key: &str) -> Result<bool> {
+    match client.head_object().bucket(bucket).key(key).send().await {
+        Ok(_) => Ok(true),
+        Err(e) if e.as_service_error().is_some_and(|se| se.is_not_found()) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -1,36 +1,218 @@
-use crate::config::StorageConfig;
-use anyhow::Result;
+use crate::{
+    commands::generate_index::escape_html, config::StorageConfig, s3_client::with_region_retry,
+    utils::longest_prefix_match,
+};
+use anyhow::{bail, Result};
 use axum::{
     extract::DefaultBodyLimit,
-    response::Html,
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Response},
     routing::{get, post},
     Router,
 };
 use axum_extra::extract::Multipart;
 use bytes::Bytes;
 use futures::StreamExt;
-use std::sync::Arc;
+use md5::{Digest, Md5};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
 use tokio::net::TcpListener;
 
-pub async fn start_server(config: StorageConfig, verbose: bool, port: u16) -> Result<()> {
-    println!("Starting server on 0.0.0.0:{}", port);
-    let shared_config = Arc::new(config);
+/// How the `identifier` form field names the uploaded files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum IdentifierMode {
+    /// `id.ext` for one file; `id-1.ext`, `id-2.ext`, ... for several
+    #[default]
+    Index,
+    /// `id-<original name>` for every file
+    Prefix,
+}
+
+impl IdentifierMode {
+    /// Name for the `index`th (0-based) of `count` uploaded files.
+    fn file_name(self, identifier: &str, original: &str, index: usize, count: usize) -> String {
+        match self {
+            IdentifierMode::Prefix => format!("{}-{}", identifier, original),
+            IdentifierMode::Index => {
+                let stem = if count == 1 {
+                    identifier.to_string()
+                } else {
+                    format!("{}-{}", identifier, index + 1)
+                };
+                match std::path::Path::new(original).extension() {
+                    Some(ext) => format!("{}.{}", stem, ext.to_string_lossy()),
+                    None => stem,
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ServerOptions {
+    /// `(content-type prefix, key prefix)` pairs used to file uploads by type
+    pub type_routes: Vec<(String, String)>,
+    /// Key prefix for types no route matches (only used when routes are set)
+    pub default_route: String,
+    pub identifier_mode: IdentifierMode,
+    /// How often to pick up rotated credentials from the environment
+    pub credentials_refresh: Option<Duration>,
+    /// Where uploads are staged before going to S3
+    pub temp_dir: std::path::PathBuf,
+    /// File size in bytes at which uploads switch to multipart
+    pub multipart_threshold: u64,
+    /// Multipart part size in bytes; chosen from the file size when unset
+    pub part_size: Option<u64>,
+    /// Make download links save the file rather than display it
+    pub force_download: bool,
+    /// Canned ACL for uploaded objects, e.g. `public-read`
+    pub upload_acl: Option<String>,
+}
+
+/// Counters exposed at `/metrics`.
+#[derive(Debug, Default)]
+struct Metrics {
+    /// Files uploaded to the bucket
+    uploads: AtomicU64,
+    /// Bytes of those files
+    bytes_uploaded: AtomicU64,
+    /// Files that failed to reach the bucket
+    failures: AtomicU64,
+    /// Upload requests being handled right now
+    in_flight: AtomicU64,
+}
+
+impl Metrics {
+    /// Renders the counters in the Prometheus text exposition format.
+    fn render(&self) -> String {
+        let metrics = [
+            (
+                "s3_uploader_uploads_total",
+                "counter",
+                "Files uploaded to the bucket.",
+                &self.uploads,
+            ),
+            (
+                "s3_uploader_uploaded_bytes_total",
+                "counter",
+                "Bytes uploaded to the bucket.",
+                &self.bytes_uploaded,
+            ),
+            (
+                "s3_uploader_upload_failures_total",
+                "counter",
+                "Files that failed to upload.",
+                &self.failures,
+            ),
+            (
+                "s3_uploader_uploads_in_flight",
+                "gauge",
+                "Upload requests currently being handled.",
+                &self.in_flight,
+            ),
+        ];
+        metrics
+            .iter()
+            .map(|(name, kind, help, value)| {
+                format!(
+                    "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {}\n",
+                    value.load(Ordering::Relaxed)
+                )
+            })
+            .collect()
+    }
+}
+
+/// Counts a request as in flight until it's dropped, whichever way the
+/// handler returns.
+struct InFlight(Arc<Metrics>);
+
+impl InFlight {
+    fn start(metrics: Arc<Metrics>) -> Self {
+        metrics.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight(metrics)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl ServerOptions {
+    /// Picks the key prefix for `content_type`; the longest matching route wins.
+    fn route_for(&self, content_type: &str) -> Option<&str> {
+        if self.type_routes.is_empty() {
+            return None;
+        }
+        let route = longest_prefix_match(&self.type_routes, content_type);
+        Some(route.unwrap_or(&self.default_route))
+    }
+}
+
+pub async fn start_server(
+    config: StorageConfig,
+    verbose: bool,
+    port: u16,
+    options: ServerOptions,
+) -> Result<()> {
+    check_startup(&config, &options, verbose).await?;
+
+    olog!("Starting server on 0.0.0.0:{}", port);
+    // Requests take a snapshot, so a credential reload never changes the
+    // config under an upload already in progress
+    let shared_config = Arc::new(RwLock::new(Arc::new(config)));
+    let shared_options = Arc::new(options);
+    let metrics = Arc::new(Metrics::default());
+
+    if let Some(interval) = shared_options.credentials_refresh.filter(|d| !d.is_zero()) {
+        tokio::spawn(refresh_credentials(
+            shared_config.clone(),
+            interval,
+            verbose,
+        ));
+    }
 
     let app = Router::new()
         .route("/", get(index))
+        .route(
+            "/metrics",
+            get({
+                let metrics = metrics.clone();
+                move || async move {
+                    (
+                        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+                        metrics.render(),
+                    )
+                }
+            }),
+        )
         .route(
             "/upload",
             post({
                 let cfg = shared_config.clone();
-                move |multipart: Multipart| handle_upload(multipart, cfg.clone(), verbose)
+                let opts = shared_options.clone();
+                move |headers: HeaderMap, multipart: Multipart| {
+                    let config = cfg.read().unwrap().clone();
+                    handle_upload(
+                        headers,
+                        multipart,
+                        config,
+                        opts.clone(),
+                        metrics.clone(),
+                        verbose,
+                    )
+                }
             }),
         )
         .layer(DefaultBodyLimit::max(1024 * 1024 * 1024));
 
     if verbose {
-        println!("🚀 Server running at http://0.0.0.0:{}", port);
+        olog!("🚀 Server running at http://0.0.0.0:{}", port);
     }
 
     let addr = format!("0.0.0.0:{}", port);
@@ -40,6 +222,67 @@ pub async fn start_server(config: StorageConfig, verbose: bool, port: u16) -> Re
     Ok(())
 }
 
+/// Checks everything an upload depends on before the server starts
+/// listening, so a missing password or unreachable bucket fails at startup
+/// instead of on every request. All problems are reported together.
+async fn check_startup(
+    config: &StorageConfig,
+    options: &ServerOptions,
+    verbose: bool,
+) -> Result<()> {
+    if verbose {
+        olog!("🔎 Checking server configuration");
+    }
+    let mut problems = Vec::new();
+
+    match std::env::var("PASSWORD") {
+        Ok(password) if !password.is_empty() => {}
+        Ok(_) => {
+            problems.push("PASSWORD is empty; set it to the upload form's password".to_string())
+        }
+        Err(_) => {
+            problems.push("PASSWORD is not set; set it to the upload form's password".to_string())
+        }
+    }
+
+    let reachable = with_region_retry(config, verbose, |client| async move {
+        client.head_bucket().bucket(&config.bucket).send().await?;
+        Ok(())
+    })
+    .await;
+    if let Err(e) = reachable {
+        problems.push(format!("cannot access bucket '{}': {:#}", config.bucket, e));
+    }
+
+    if let Err(e) = check_temp_dir(&options.temp_dir).await {
+        problems.push(format!(
+            "temp dir {} is not writable: {}",
+            options.temp_dir.display(),
+            e
+        ));
+    }
+
+    if !problems.is_empty() {
+        bail!(
+            "Refusing to start the server:\n  - {}",
+            problems.join("\n  - ")
+        );
+    }
+    if verbose {
+        olog!("✅ Password set, bucket reachable, temp dir writable");
+    }
+    Ok(())
+}
+
+/// Stages and removes a file the way an upload would.
+async fn check_temp_dir(base: &std::path::Path) -> std::io::Result<()> {
+    let dir = unique_temp_dir(base).await?;
+    let path = dir.join("startup-check");
+    let written = tokio::fs::write(&path, b"ok").await;
+    remove_temp_file(&path).await;
+    written
+}
+
 async fn index() -> Html<&'static str> {
     Html(
         r#"
@@ -70,6 +313,13 @@ async fn index() -> Html<&'static str> {
                     class="block w-full text-gray-700 border border-gray-300 rounded-lg px-3 py-2 focus:outline-none focus:ring-2 focus:ring-blue-500" />
             </div>
 
+            <!-- Content type override -->
+            <div>
+                <label class="block text-gray-700 font-medium mb-1" for="content_type">Content type</label>
+                <input type="text" name="content_type" id="content_type" placeholder="Detected automatically"
+                    class="block w-full text-gray-700 border border-gray-300 rounded-lg px-3 py-2 focus:outline-none focus:ring-2 focus:ring-blue-500" />
+            </div>
+
             <!-- TTL selector -->
             <div>
                 <label class="block text-gray-700 font-medium mb-1">Expiration (TTL)</label>
@@ -104,16 +354,52 @@ async fn index() -> Html<&'static str> {
     )
 }
 
+/// Periodically re-reads `.env` and the config file profile and swaps in new
+/// credentials when they change, so short-lived credentials rotated on disk
+/// don't break a running server.
+async fn refresh_credentials(
+    config: Arc<RwLock<Arc<StorageConfig>>>,
+    interval: Duration,
+    verbose: bool,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let current = config.read().unwrap().clone();
+        match current.with_reloaded_credentials() {
+            Ok(Some(updated)) => {
+                *config.write().unwrap() = Arc::new(updated);
+                olog!("🔑 Reloaded rotated credentials");
+            }
+            Ok(None) => {
+                if verbose {
+                    olog!("🔑 Credentials unchanged");
+                }
+            }
+            Err(e) => elog!(
+                "⚠️ Failed to reload credentials, keeping the current ones: {:#}",
+                e
+            ),
+        }
+    }
+}
+
 async fn handle_upload(
+    headers: HeaderMap,
     mut multipart: Multipart,
     config: Arc<StorageConfig>,
+    options: Arc<ServerOptions>,
+    metrics: Arc<Metrics>,
     verbose: bool,
-) -> Html<String> {
+) -> Response {
     use std::env;
     use std::str::FromStr;
 
+    let _in_flight = InFlight::start(metrics.clone());
+
     if verbose {
-        println!("🚀 Starting upload handler");
+        olog!("🚀 Starting upload handler");
     }
 
     let mut uploaded_files: Vec<std::path::PathBuf> = Vec::new();
@@ -121,12 +407,19 @@ async fn handle_upload(
     let mut ttl_value: u64 = 1;
     let mut ttl_unit = "hours".to_string();
     let mut password = String::new();
+    let mut content_type_override: Option<String> = None;
+    let mut file_digests: Vec<[u8; 16]> = Vec::new();
+    // A `content_md5` form field takes precedence over the header
+    let mut expected_md5 = headers
+        .get("content-md5")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
 
     while let Some(mut field) = multipart.next_field().await.ok().flatten() {
         let name = field.name().map(|s| s.to_string());
 
         if verbose {
-            println!("🔹 Processing field: {:?}", name);
+            olog!("🔹 Processing field: {:?}", name);
         }
 
         if let Some(n) = name {
@@ -134,56 +427,75 @@ async fn handle_upload(
                 "file" => {
                     let filename = field
                         .file_name()
-                        .map(|s| s.to_string())
+                        .and_then(final_component)
                         .unwrap_or_else(|| "unnamed".to_string());
 
                     if verbose {
-                        println!("📄 Uploading file: {}", filename);
+                        olog!("📄 Uploading file: {}", filename);
                     }
 
-                    let temp_path = std::env::temp_dir().join(&filename);
+                    let temp_path = match unique_temp_dir(&options.temp_dir).await {
+                        Ok(dir) => dir.join(&filename),
+                        Err(e) => {
+                            elog!("❌ Failed to create temp dir: {:?}", e);
+                            return Html(escape_html(&format!(
+                                "Failed to create temp file: {:?}",
+                                e
+                            )))
+                            .into_response();
+                        }
+                    };
 
                     let mut file = match File::create(&temp_path).await {
                         Ok(f) => f,
                         Err(e) => {
-                            eprintln!("❌ Failed to create temp file: {:?}", e);
-                            return Html(format!("Failed to create temp file: {:?}", e));
+                            elog!("❌ Failed to create temp file: {:?}", e);
+                            return Html(escape_html(&format!(
+                                "Failed to create temp file: {:?}",
+                                e
+                            )))
+                            .into_response();
                         }
                     };
 
+                    let mut hasher = Md5::new();
                     while let Some(chunk_res) = field.next().await {
                         let chunk: Bytes = match chunk_res {
                             Ok(c) => c,
                             Err(e) => {
-                                eprintln!("❌ Error in stream: {:?}", e);
-                                return Html(format!("Error reading file: {:?}", e));
+                                elog!("❌ Error in stream: {:?}", e);
+                                return Html(escape_html(&format!("Error reading file: {:?}", e)))
+                                    .into_response();
                             }
                         };
 
                         if verbose {
-                            println!("⬇️ Writing chunk: {} bytes", chunk.len());
+                            olog!("⬇️ Writing chunk: {} bytes", chunk.len());
                         }
+                        hasher.update(&chunk);
                         if let Err(e) = file.write_all(&chunk).await {
-                            eprintln!("❌ Failed writing chunk: {:?}", e);
-                            return Html(format!("Failed to write file: {:?}", e));
+                            elog!("❌ Failed writing chunk: {:?}", e);
+                            return Html(escape_html(&format!("Failed to write file: {:?}", e)))
+                                .into_response();
                         }
                     }
 
                     uploaded_files.push(temp_path.clone());
+                    file_digests.push(hasher.finalize().into());
 
                     if verbose {
-                        println!("✅ File saved successfully: {:?}", temp_path);
+                        olog!("✅ File saved successfully: {:?}", temp_path);
                     }
                 }
                 "identifier" => match field.text().await {
                     Ok(text) => {
                         identifier = text;
                         if verbose {
-                            println!("🆔 Identifier set: {}", identifier);
+                            olog!("🆔 Identifier set: {}", identifier);
                         }
                     }
                     Err(e) => {
-                        eprintln!("❌ Failed to read identifier: {:?}", e);
+                        elog!("❌ Failed to read identifier: {:?}", e);
                     }
                 },
                 "ttl_value" => match field.text().await {
@@ -191,39 +503,62 @@ async fn handle_upload(
                         if let Ok(val) = u64::from_str(&text) {
                             ttl_value = val;
                             if verbose {
-                                println!("⏱ TTL value set: {}", ttl_value);
+                                olog!("⏱ TTL value set: {}", ttl_value);
                             }
                         }
                     }
                     Err(e) => {
-                        eprintln!("❌ Failed to read ttl_value: {:?}", e);
+                        elog!("❌ Failed to read ttl_value: {:?}", e);
                     }
                 },
                 "ttl_unit" => match field.text().await {
                     Ok(text) => {
                         ttl_unit = text;
                         if verbose {
-                            println!("⏱ TTL unit set: {}", ttl_unit);
+                            olog!("⏱ TTL unit set: {}", ttl_unit);
+                        }
+                    }
+                    Err(e) => {
+                        elog!("❌ Failed to read ttl_unit: {:?}", e);
+                    }
+                },
+                "content_md5" => match field.text().await {
+                    Ok(text) => {
+                        expected_md5 = Some(text);
+                        if verbose {
+                            olog!("🔒 Content-MD5 received");
+                        }
+                    }
+                    Err(e) => {
+                        elog!("❌ Failed to read content_md5: {:?}", e);
+                    }
+                },
+                "content_type" => match field.text().await {
+                    Ok(text) if text.trim().is_empty() => {}
+                    Ok(text) => {
+                        if verbose {
+                            olog!("🏷 Content type set: {}", text.trim());
                         }
+                        content_type_override = Some(text.trim().to_string());
                     }
                     Err(e) => {
-                        eprintln!("❌ Failed to read ttl_unit: {:?}", e);
+                        elog!("❌ Failed to read content_type: {:?}", e);
                     }
                 },
                 "password" => match field.text().await {
                     Ok(text) => {
                         password = text;
                         if verbose {
-                            println!("🔑 Password received");
+                            olog!("🔑 Password received");
                         }
                     }
                     Err(e) => {
-                        eprintln!("❌ Failed to read password: {:?}", e);
+                        elog!("❌ Failed to read password: {:?}", e);
                     }
                 },
                 other => {
                     if verbose {
-                        println!("⚠️ Ignored field: {:?}", other);
+                        olog!("⚠️ Ignored field: {:?}", other);
                     }
                 }
             }
@@ -233,20 +568,20 @@ async fn handle_upload(
     let expected_password = match env::var("PASSWORD") {
         Ok(p) => p,
         Err(e) => {
-            eprintln!("❌ Failed to read PASSWORD env var: {:?}", e);
-            return Html("Server configuration error".to_string());
+            elog!("❌ Failed to read PASSWORD env var: {:?}", e);
+            return Html("Server configuration error".to_string()).into_response();
         }
     };
 
     if password != expected_password {
         if verbose {
-            println!("❌ Invalid password");
+            olog!("❌ Invalid password");
         }
-        return Html("Invalid password".to_string());
+        return Html("Invalid password".to_string()).into_response();
     }
 
     if verbose {
-        println!("✅ Password validated");
+        olog!("✅ Password validated");
     }
 
     let mut ttl_seconds: u64 = ttl_value;
@@ -257,80 +592,321 @@ async fn handle_upload(
     };
 
     if verbose {
-        println!("⏱ TTL calculated: {} seconds", ttl_seconds);
-    }
-
-    // Handle identifier for single file (rename temp file if provided)
-    if !identifier.is_empty() && uploaded_files.len() == 1 {
-        let old_path = uploaded_files[0].clone();
-        let ext = old_path
-            .extension()
-            .map(|e| e.to_string_lossy().to_string());
-        let new_filename = match ext {
-            Some(e) => format!("{}.{}", identifier, e),
-            None => identifier.clone(),
-        };
-        let new_path = old_path.with_file_name(new_filename);
+        olog!("⏱ TTL calculated: {} seconds", ttl_seconds);
+    }
 
-        if let Err(e) = tokio::fs::rename(&old_path, &new_path).await {
-            eprintln!("❌ Failed to rename file: {:?}", e);
-            return Html(format!("Failed to rename file: {:?}", e));
+    if let Err(message) = validate_identifier(&identifier) {
+        elog!("❌ {}", message);
+        for path in &uploaded_files {
+            remove_temp_file(path).await;
         }
+        return (StatusCode::BAD_REQUEST, message).into_response();
+    }
 
-        uploaded_files[0] = new_path.clone();
+    if let Some(content_type) = &content_type_override {
+        if let Err(e) = crate::commands::upload::validate_content_type(content_type) {
+            elog!("❌ {}", e);
+            for path in &uploaded_files {
+                remove_temp_file(path).await;
+            }
+            return (StatusCode::BAD_REQUEST, e.to_string()).into_response();
+        }
+    }
 
+    if let Some(expected) = &expected_md5 {
+        if let Err(message) = verify_md5(expected, &file_digests) {
+            elog!("❌ {}", message);
+            for path in &uploaded_files {
+                remove_temp_file(path).await;
+            }
+            return (StatusCode::BAD_REQUEST, message).into_response();
+        }
         if verbose {
-            println!("🔄 File renamed to: {:?}", new_path);
+            olog!("✅ Content-MD5 verified");
+        }
+    }
+
+    // Name the files after the identifier, if one was given
+    if !identifier.is_empty() {
+        let count = uploaded_files.len();
+        let mut escaped = false;
+        for (index, path) in uploaded_files.iter_mut().enumerate() {
+            let original = path.file_name().unwrap_or_default().to_string_lossy();
+            let new_filename =
+                options
+                    .identifier_mode
+                    .file_name(&identifier, &original, index, count);
+            let new_path = path.with_file_name(&new_filename);
+            // The file must stay in its own staging directory, which is
+            // removed along with it after the upload
+            if final_component(&new_filename).as_deref() != Some(new_filename.as_str())
+                || new_path.parent() != path.parent()
+            {
+                elog!("❌ Refusing to move upload to {:?}", new_path);
+                escaped = true;
+                break;
+            }
+
+            if let Err(e) = tokio::fs::rename(&*path, &new_path).await {
+                elog!("❌ Failed to rename file: {:?}", e);
+                return Html(escape_html(&format!("Failed to rename file: {:?}", e)))
+                    .into_response();
+            }
+
+            if verbose {
+                olog!("🔄 File renamed to: {:?}", new_path);
+            }
+            *path = new_path;
+        }
+        if escaped {
+            for path in &uploaded_files {
+                remove_temp_file(path).await;
+            }
+            return (StatusCode::BAD_REQUEST, "Invalid identifier".to_string()).into_response();
         }
     }
 
     if uploaded_files.is_empty() {
-        eprintln!("❌ No files uploaded");
-        return Html("No file uploaded".to_string());
+        elog!("❌ No files uploaded");
+        return Html("No file uploaded".to_string()).into_response();
     }
 
     // Upload each file to S3
     let mut results = Vec::new();
     for path in uploaded_files {
         if verbose {
-            println!("🚀 Uploading to S3: {:?}", path);
+            olog!("🚀 Uploading to S3: {:?}", path);
         }
 
+        let content_type = content_type_override
+            .clone()
+            .unwrap_or_else(|| detect_content_type(&path));
+        let key = options.route_for(&content_type).map(|prefix| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            crate::keys::join_prefix(prefix, &name)
+        });
+        if verbose {
+            if let Some(k) = &key {
+                olog!("🧭 Routed {} upload to {}", content_type, k);
+            }
+        }
+
+        let upload_options = crate::commands::upload::UploadOptions {
+            key,
+            expires_seconds: Some(ttl_seconds),
+            delete_after_expiry: true,
+            multipart_threshold: options.multipart_threshold,
+            part_size: options.part_size,
+            force_download: options.force_download,
+            content_type: Some(content_type),
+            acl: options.upload_acl.clone(),
+            ..Default::default()
+        };
         let upload_result = crate::commands::upload::upload_file(
-            &path.to_string_lossy().to_string(),
+            path.to_string_lossy().as_ref(),
             &config,
             verbose,
-            Some(ttl_seconds),
+            &upload_options,
         )
         .await;
 
         match upload_result {
             Ok(info) => {
+                metrics.uploads.fetch_add(1, Ordering::Relaxed);
+                metrics
+                    .bytes_uploaded
+                    .fetch_add(info.size, Ordering::Relaxed);
                 if verbose {
-                    println!("✅ Upload completed: {}", info.download_url);
+                    olog!("✅ Upload completed: {}", info.download_url);
                 }
+                let url = escape_html(&info.download_url);
                 results.push(format!(
                 "<p>File: {} uploaded successfully! <br>Download: <a href='{}'>{}</a> <br>Expires in: {} seconds</p>",
-                info.file_name, info.download_url, info.download_url, ttl_seconds
+                escape_html(&info.file_name), url, url, ttl_seconds
             ));
             }
             Err(e) => {
-                eprintln!("❌ Upload failed for {:?}: {:?}", path, e);
+                metrics.failures.fetch_add(1, Ordering::Relaxed);
+                elog!("❌ Upload failed for {:?}: {:?}", path, e);
                 results.push(format!(
-                    "<p>Upload failed for {}: {:?}</p>",
-                    path.file_name().unwrap_or_default().to_string_lossy(),
-                    e
+                    "<p>Upload failed for {}: {}</p>",
+                    escape_html(&path.file_name().unwrap_or_default().to_string_lossy()),
+                    escape_html(&format!("{:?}", e))
                 ));
             }
         }
 
         // Always delete the temp file immediately after upload attempt
         if let Err(e) = tokio::fs::remove_file(&path).await {
-            eprintln!("❌ Failed to delete temp file {:?}: {:?}", path, e);
+            elog!("❌ Failed to delete temp file {:?}: {:?}", path, e);
         } else if verbose {
-            println!("🗑️ Temp file deleted: {:?}", path);
+            olog!("🗑️ Temp file deleted: {:?}", path);
         }
+        remove_temp_dir(&path).await;
+    }
+
+    Html(results.join("<hr>")).into_response()
+}
+
+/// The last component of a client-supplied file name, so a crafted name
+/// can't escape the temp dir. `None` when nothing usable is left.
+fn final_component(name: &str) -> Option<String> {
+    std::path::Path::new(name)
+        .file_name()
+        .map(|s| s.to_string_lossy().to_string())
+}
+
+/// Checks the `identifier` field, which becomes part of the staged file's
+/// name: it must be a single path component with no control characters.
+fn validate_identifier(identifier: &str) -> std::result::Result<(), String> {
+    let bad_char = identifier
+        .chars()
+        .any(|c| c == '/' || c == '\\' || c.is_control());
+    if bad_char || identifier == "." || identifier == ".." {
+        return Err(format!(
+            "Identifier {:?} must be a plain name without '/', '\\', '..' or control characters",
+            identifier
+        ));
+    }
+    Ok(())
+}
+
+/// Sniffs the content type from the file's magic bytes, falling back to its
+/// extension when the bytes aren't recognised.
+fn detect_content_type(path: &std::path::Path) -> String {
+    match infer::get_from_path(path) {
+        Ok(Some(kind)) => kind.mime_type().to_string(),
+        _ => mime_guess::from_path(path)
+            .first_or_octet_stream()
+            .to_string(),
     }
+}
 
-    Html(results.join("<hr>"))
+/// Creates a fresh directory for one uploaded file.
+///
+/// Each file gets its own directory rather than a renamed file because the
+/// file name becomes the object key, and concurrent uploads of the same name
+/// must not share a path.
+async fn unique_temp_dir(base: &std::path::Path) -> std::io::Result<std::path::PathBuf> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let dir = base.join(format!("s3-uploader-{}-{}", std::process::id(), n));
+    tokio::fs::create_dir_all(&dir).await?;
+    Ok(dir)
+}
+
+async fn remove_temp_file(path: &std::path::Path) {
+    let _ = tokio::fs::remove_file(path).await;
+    remove_temp_dir(path).await;
+}
+
+/// Removes the per-file directory made by `unique_temp_dir`.
+async fn remove_temp_dir(path: &std::path::Path) {
+    if let Some(dir) = path.parent() {
+        let _ = tokio::fs::remove_dir(dir).await;
+    }
+}
+
+/// Checks the single uploaded file against a client-supplied MD5, given as
+/// base64 (as in the `Content-MD5` header) or hex.
+fn verify_md5(expected: &str, digests: &[[u8; 16]]) -> std::result::Result<(), String> {
+    use base64::Engine;
+
+    let expected = expected.trim();
+    let decoded = if expected.len() == 32 && expected.is_ascii() {
+        (0..32)
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&expected[i..i + 2], 16))
+            .collect::<std::result::Result<Vec<u8>, _>>()
+            .ok()
+    } else {
+        base64::engine::general_purpose::STANDARD
+            .decode(expected)
+            .ok()
+    };
+    let Some(decoded) = decoded.filter(|d| d.len() == 16) else {
+        return Err(format!(
+            "Content-MD5 '{}' is not a base64 or hex MD5 digest",
+            expected
+        ));
+    };
+
+    match digests {
+        [digest] if digest[..] == decoded[..] => Ok(()),
+        [_] => Err("Content-MD5 mismatch: the file was corrupted in transit".to_string()),
+        _ => Err(format!(
+            "Content-MD5 applies to a single file, but {} were uploaded",
+            digests.len()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identifier_must_be_one_path_component() {
+        for bad in [
+            "..",
+            ".",
+            "../../etc/x",
+            "/tmp/victim",
+            "a\\b",
+            "a\0b",
+            "a\nb",
+        ] {
+            assert!(validate_identifier(bad).is_err(), "{:?} accepted", bad);
+        }
+        for good in ["", "invoice-42", "report.v2", "ünïcode"] {
+            assert!(validate_identifier(good).is_ok(), "{:?} rejected", good);
+        }
+    }
+
+    #[tokio::test]
+    async fn rejected_form_values_come_back_as_plain_text() {
+        use axum::extract::FromRequest;
+
+        std::env::set_var("PASSWORD", "test-password");
+        let value = "<img src=x onerror=alert(1)>";
+        let body = format!(
+            "--b\r\nContent-Disposition: form-data; name=\"password\"\r\n\r\ntest-password\r\n\
+             --b\r\nContent-Disposition: form-data; name=\"content_type\"\r\n\r\n{}\r\n--b--\r\n",
+            value
+        );
+        let request = axum::http::Request::builder()
+            .method("POST")
+            .header(header::CONTENT_TYPE, "multipart/form-data; boundary=b")
+            .body(axum::body::Body::from(body))
+            .unwrap();
+        let multipart = Multipart::from_request(request, &()).await.unwrap();
+
+        let response = handle_upload(
+            HeaderMap::new(),
+            multipart,
+            Arc::new(StorageConfig::new("bucket", "us-east-1", "key", "secret")),
+            Arc::new(ServerOptions::default()),
+            Arc::new(Metrics::default()),
+            false,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let content_type = response.headers()[header::CONTENT_TYPE].to_str().unwrap();
+        assert!(content_type.starts_with("text/plain"), "{}", content_type);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(String::from_utf8_lossy(&body).contains(value));
+    }
+
+    #[test]
+    fn final_component_drops_directories() {
+        assert_eq!(
+            final_component("../../etc/passwd").as_deref(),
+            Some("passwd")
+        );
+        assert_eq!(final_component("/tmp/victim").as_deref(), Some("victim"));
+        assert_eq!(final_component(".."), None);
+        assert_eq!(final_component(""), None);
+    }
 }
@@ -0,0 +1,184 @@
+use crate::{
+    config::StorageConfig,
+    error::StorageError,
+    s3_client::with_region_retry,
+    utils::{format_size, hex, OutputFormat, Units},
+};
+use anyhow::{bail, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// Largest `--peek` allowed; enough to recognise any file signature.
+pub const MAX_PEEK_BYTES: u64 = 4096;
+
+/// Prints an object's metadata, and with `peek` a hex dump of its first
+/// `peek` bytes fetched with a ranged `get_object`, in
+/// `config.output_format`.
+pub async fn stat_file(
+    file_name: &str,
+    peek: Option<u64>,
+    config: &StorageConfig,
+    verbose: bool,
+    out: &mut dyn Write,
+) -> Result<()> {
+    if let Some(n) = peek {
+        if n == 0 || n > MAX_PEEK_BYTES {
+            bail!("--peek must be between 1 and {} bytes", MAX_PEEK_BYTES);
+        }
+    }
+
+    let key = config.prefixed(file_name);
+    if verbose {
+        elog!("🔎 Inspecting {}", key);
+    }
+
+    let key_ref = &key;
+    let found = with_region_retry(config, verbose, |client| async move {
+        let head = match client
+            .head_object()
+            .bucket(&config.bucket)
+            .key(key_ref)
+            .send()
+            .await
+        {
+            Ok(head) => head,
+            Err(e) if e.as_service_error().is_some_and(|se| se.is_not_found()) => {
+                return Ok(None);
+            }
+            Err(e) => return Err(StorageError::from(e).into()),
+        };
+
+        let peeked = match peek {
+            // An empty object has no satisfiable range
+            Some(n) if head.content_length().unwrap_or(0) > 0 => {
+                let object = client
+                    .get_object()
+                    .bucket(&config.bucket)
+                    .key(key_ref)
+                    .range(format!("bytes=0-{}", n - 1))
+                    .send()
+                    .await
+                    .map_err(StorageError::from)?;
+                let bytes = object.body.collect().await?.into_bytes();
+                // Endpoints that ignore Range send the whole object
+                Some(bytes.slice(..bytes.len().min(n as usize)))
+            }
+            Some(_) => Some(bytes::Bytes::new()),
+            None => None,
+        };
+        Ok(Some((head, peeked)))
+    })
+    .await?;
+
+    let Some((head, peeked)) = found else {
+        return Err(StorageError::NotFound {
+            key,
+            bucket: config.bucket.clone(),
+        }
+        .into());
+    };
+
+    let stat = ObjectStat {
+        key,
+        size: head.content_length().unwrap_or(0).max(0) as u64,
+        content_type: head.content_type().map(str::to_string),
+        last_modified: head.last_modified().map(|dt| dt.to_string()),
+        etag: head.e_tag().map(str::to_string),
+        storage_class: head.storage_class().map(|c| c.as_str().to_string()),
+        cache_control: head.cache_control().map(str::to_string),
+        metadata: head
+            .metadata()
+            .map(|m| m.clone().into_iter().collect())
+            .unwrap_or_default(),
+        peek: peeked.as_deref().map(hex),
+    };
+    match config.output_format {
+        OutputFormat::Json => {
+            serde_json::to_writer(&mut *out, &stat)?;
+            writeln!(out)?;
+        }
+        OutputFormat::Yaml => serde_yaml::to_writer(&mut *out, &stat)?,
+        OutputFormat::Text => {
+            write_table(out, &stat, config.units)?;
+            if let Some(bytes) = peeked {
+                writeln!(out, "First {} byte(s):", bytes.len())?;
+                write_hex_dump(out, &bytes)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// What `stat` reports about an object. Every format has the same fields;
+/// JSON and YAML show missing ones as `null`.
+#[derive(Debug, Serialize)]
+struct ObjectStat {
+    key: String,
+    size: u64,
+    content_type: Option<String>,
+    last_modified: Option<String>,
+    etag: Option<String>,
+    storage_class: Option<String>,
+    cache_control: Option<String>,
+    /// User metadata, sorted by name
+    metadata: BTreeMap<String, String>,
+    /// Hex of the `--peek` bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    peek: Option<String>,
+}
+
+/// Writes `stat` as `Field: value` lines with the values aligned, `-`
+/// standing in for fields the endpoint didn't return.
+fn write_table(out: &mut dyn Write, stat: &ObjectStat, units: Units) -> std::io::Result<()> {
+    let optional = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".to_string());
+    let mut rows = vec![
+        ("Key".to_string(), stat.key.clone()),
+        (
+            "Size".to_string(),
+            format!("{} ({} bytes)", format_size(stat.size, units), stat.size),
+        ),
+        ("Content-Type".to_string(), optional(&stat.content_type)),
+        ("Last modified".to_string(), optional(&stat.last_modified)),
+        ("ETag".to_string(), optional(&stat.etag)),
+        ("Storage class".to_string(), optional(&stat.storage_class)),
+        ("Cache-Control".to_string(), optional(&stat.cache_control)),
+    ];
+    rows.extend(
+        stat.metadata
+            .iter()
+            .map(|(name, value)| (format!("x-amz-meta-{}", name), value.clone())),
+    );
+
+    let width = rows.iter().map(|(label, _)| label.len()).max().unwrap_or(0) + 1;
+    for (label, value) in rows {
+        writeln!(out, "{:<width$} {}", format!("{}:", label), value)?;
+    }
+    Ok(())
+}
+
+/// Writes `bytes` in `xxd` layout: offset, hex in pairs, then printable ASCII.
+fn write_hex_dump(out: &mut dyn Write, bytes: &[u8]) -> std::io::Result<()> {
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        let mut hex = String::with_capacity(40);
+        for (i, byte) in chunk.iter().enumerate() {
+            if i > 0 && i % 2 == 0 {
+                hex.push(' ');
+            }
+            hex.push_str(&format!("{:02x}", byte));
+        }
+        let ascii: String = chunk
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        writeln!(out, "{:08x}: {:<39}  {}", line * 16, hex, ascii)?;
+    }
+    Ok(())
+}
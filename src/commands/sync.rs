@@ -0,0 +1,290 @@
+use crate::{
+    commands::{
+        delete::delete_batch,
+        list::list_all,
+        prefetch::HeadCache,
+        upload::{collect_files, upload_file, UploadOptions},
+    },
+    config::StorageConfig,
+    keys::{as_dir, derive_key_from_path, join_prefix, sanitize_key, SHA256_METADATA},
+    s3_client::create_client,
+    utils::{hash_file, hex},
+};
+use anyhow::{bail, Result};
+use aws_sdk_s3::types::{Object, ObjectIdentifier};
+use futures::StreamExt;
+use md5::Md5;
+use sha2::Sha256;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+#[derive(Debug, Clone, Default)]
+pub struct SyncOptions {
+    /// Delete remote objects under the prefix that aren't present locally
+    pub delete: bool,
+    /// Print the planned actions without performing them
+    pub dry_run: bool,
+}
+
+/// A local file and where it belongs in the bucket.
+struct LocalFile {
+    path: PathBuf,
+    /// Key relative to the default prefix, e.g. `site/images/logo.png`
+    key: String,
+    sha256: String,
+}
+
+/// Why a file needs uploading.
+#[derive(Clone, Copy)]
+enum Change {
+    New,
+    Changed,
+}
+
+/// Uploads the files under `local_dir` that are missing from `prefix` or
+/// differ from the copy there, optionally deleting remote extras.
+///
+/// Objects whose size matches are compared by their `x-amz-meta-sha256`.
+/// Objects without it (uploaded by other tools) are compared with the local
+/// MD5 when the ETag is a plain MD5, and by modification time otherwise.
+pub async fn sync_dir(
+    local_dir: &str,
+    prefix: Option<&str>,
+    options: &SyncOptions,
+    config: &StorageConfig,
+    verbose: bool,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let dir = Path::new(local_dir);
+    if !dir.is_dir() {
+        bail!("{} is not a directory", local_dir);
+    }
+    let folder = match prefix {
+        Some(prefix) => as_dir(&sanitize_key(prefix)?, &config.delimiter),
+        None => String::new(),
+    };
+    let remote_prefix = config.prefixed(&folder);
+    if options.delete && remote_prefix.is_empty() {
+        bail!(
+            "Refusing to sync with --delete to the whole bucket, which would delete every \
+             object missing locally; give a prefix"
+        );
+    }
+
+    let mut paths = Vec::new();
+    collect_files(dir, false, &mut HashSet::new(), &mut paths)?;
+    paths.sort();
+    let mut local = Vec::with_capacity(paths.len());
+    for path in paths {
+        let key = join_prefix(
+            &folder,
+            &derive_key_from_path(&path, Some(dir), &config.delimiter)?,
+        );
+        let hashed = path.clone();
+        let sha256 = tokio::task::spawn_blocking(move || file_sha256(&hashed)).await??;
+        local.push(LocalFile { path, key, sha256 });
+    }
+
+    let client = create_client(config, verbose).await?;
+    let remote: HashMap<String, Object> = list_all(
+        &client,
+        &config.bucket,
+        (!remote_prefix.is_empty()).then_some(remote_prefix.as_str()),
+    )
+    .await?
+    .into_iter()
+    .filter_map(|o| Some((o.key()?.to_string(), o)))
+    .collect();
+    if verbose {
+        elog!(
+            "🔄 Comparing {} local file(s) with {} object(s) under '{}'",
+            local.len(),
+            remote.len(),
+            remote_prefix
+        );
+    }
+
+    // Only same-sized objects need their stored hash, fetched all at once
+    let mut same_size = Vec::new();
+    for file in &local {
+        let key = config.prefixed(&file.key);
+        let size = std::fs::metadata(&file.path)?.len();
+        if remote.get(&key).and_then(Object::size) == Some(size as i64) {
+            same_size.push(key);
+        }
+    }
+    let heads = HeadCache::prefetch(
+        &client,
+        &config.bucket,
+        same_size,
+        config.concurrency,
+        verbose,
+    )
+    .await?;
+
+    let (heads_ref, remote_ref) = (&heads, &remote);
+    let compared: Vec<Result<Option<Change>>> = futures::stream::iter(&local)
+        .map(|file| async move {
+            let key = config.prefixed(&file.key);
+            match remote_ref.get(&key) {
+                None => Ok(Some(Change::New)),
+                Some(object) => {
+                    let changed = differs(heads_ref, &key, file, object).await?;
+                    Ok(changed.then_some(Change::Changed))
+                }
+            }
+        })
+        .buffered(config.concurrency)
+        .collect()
+        .await;
+
+    let mut uploads = Vec::new();
+    for (file, change) in local.iter().zip(compared) {
+        if let Some(change) = change? {
+            uploads.push((file, change));
+        }
+    }
+    let unchanged = local.len() - uploads.len();
+
+    let local_keys: HashSet<String> = local.iter().map(|f| config.prefixed(&f.key)).collect();
+    let mut deletions: Vec<&str> = if options.delete {
+        remote
+            .keys()
+            .filter(|k| !local_keys.contains(*k))
+            .map(String::as_str)
+            .collect()
+    } else {
+        Vec::new()
+    };
+    deletions.sort();
+
+    for (file, change) in &uploads {
+        let reason = match change {
+            Change::New => "new",
+            Change::Changed => "changed",
+        };
+        writeln!(
+            out,
+            "upload {} -> {} ({})",
+            file.path.display(),
+            config.prefixed(&file.key),
+            reason
+        )?;
+    }
+    for key in &deletions {
+        writeln!(out, "delete {}", key)?;
+    }
+
+    if options.dry_run {
+        writeln!(
+            out,
+            "Dry run: would upload {}, delete {}, leave {} unchanged",
+            uploads.len(),
+            deletions.len(),
+            unchanged
+        )?;
+        return Ok(());
+    }
+
+    // Files upload in parallel, so each sends its parts one at a time
+    let file_config = &StorageConfig {
+        concurrency: 1,
+        ..config.clone()
+    };
+    let uploaded: Vec<(&LocalFile, Result<()>)> = futures::stream::iter(&uploads)
+        .map(|(file, _)| async move {
+            let upload_options = UploadOptions {
+                key: Some(file.key.clone()),
+                ..Default::default()
+            };
+            let result = upload_file(
+                &file.path.to_string_lossy(),
+                file_config,
+                verbose,
+                &upload_options,
+            )
+            .await
+            .map(|_| ());
+            (*file, result)
+        })
+        .buffered(config.concurrency)
+        .collect()
+        .await;
+
+    let mut failed = 0;
+    for (file, result) in &uploaded {
+        if let Err(e) = result {
+            elog!("❌ Failed to upload {}: {:#}", file.path.display(), e);
+            failed += 1;
+        }
+    }
+
+    let mut deleted = 0;
+    if !deletions.is_empty() {
+        let objects = deletions
+            .iter()
+            .map(|key| ObjectIdentifier::builder().key(*key).build())
+            .collect::<Result<Vec<_>, _>>()?;
+        let summary = delete_batch(&client, &config.bucket, objects, verbose).await?;
+        for (key, message) in &summary.failed {
+            elog!("❌ Failed to delete {}: {}", key, message);
+        }
+        failed += summary.failed.len();
+        deleted = summary.deleted;
+    }
+
+    writeln!(
+        out,
+        "Synced: {} uploaded, {} deleted, {} unchanged",
+        uploaded.len() - uploaded.iter().filter(|(_, r)| r.is_err()).count(),
+        deleted,
+        unchanged
+    )?;
+    if failed > 0 {
+        bail!("{} sync action(s) failed", failed);
+    }
+    Ok(())
+}
+
+/// Whether the local file differs from a same-keyed object, whose
+/// attributes are in `heads` if its size matched.
+async fn differs(heads: &HeadCache, key: &str, file: &LocalFile, object: &Object) -> Result<bool> {
+    let local_size = std::fs::metadata(&file.path)?.len();
+    if object.size() != Some(local_size as i64) {
+        return Ok(true);
+    }
+
+    // Gone since it was listed, so it needs uploading again
+    let Some(head) = heads.get(key) else {
+        return Ok(true);
+    };
+    if let Some(remote) = head.metadata().and_then(|m| m.get(SHA256_METADATA)) {
+        return Ok(!remote.eq_ignore_ascii_case(&file.sha256));
+    }
+
+    // Without a stored hash, a single-part ETag is the object's MD5
+    let etag = object.e_tag().unwrap_or_default().trim_matches('"');
+    if !etag.is_empty() && !etag.contains('-') {
+        let path = file.path.clone();
+        let md5 = tokio::task::spawn_blocking(move || file_md5(&path)).await??;
+        return Ok(!etag.eq_ignore_ascii_case(&md5));
+    }
+
+    // Multipart ETags aren't a content hash; fall back to timestamps
+    let local_modified = std::fs::metadata(&file.path)?
+        .modified()?
+        .duration_since(UNIX_EPOCH)?
+        .as_secs() as i64;
+    let remote_modified = object.last_modified().map(|t| t.secs()).unwrap_or(0);
+    Ok(local_modified > remote_modified)
+}
+
+fn file_sha256(path: &Path) -> Result<String> {
+    Ok(hex(&hash_file::<Sha256>(path)?))
+}
+
+fn file_md5(path: &Path) -> Result<String> {
+    Ok(hex(&hash_file::<Md5>(path)?))
+}
@@ -0,0 +1,330 @@
+use crate::{
+    commands::{
+        copy::{copy_file, CopyOptions},
+        download::{
+            download_file, download_planned, plan_prefix_download, DownloadOptions,
+            RecursiveOptions,
+        },
+        list::list_all,
+        sync::{sync_dir, SyncOptions},
+        upload::{upload_file, write_upload_info, UploadOptions},
+    },
+    config::StorageConfig,
+    keys::{as_dir, is_dir_key, last_segment, parse_s3_uri},
+    s3_client::create_client,
+    utils::report_batch,
+};
+use anyhow::{bail, Result};
+use futures::StreamExt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Default)]
+pub struct TransferOptions {
+    /// When syncing a directory, delete remote objects missing locally
+    pub delete: bool,
+    /// Print what would be transferred without doing it
+    pub dry_run: bool,
+}
+
+/// One side of a transfer.
+enum Location {
+    Local(PathBuf),
+    /// A bucket and key; a key that is empty or ends in the delimiter is a
+    /// prefix
+    Remote {
+        bucket: String,
+        key: String,
+    },
+}
+
+impl Location {
+    fn parse(arg: &str) -> Result<Location> {
+        if arg.starts_with("s3://") {
+            let (bucket, key) = parse_s3_uri(arg)?;
+            return Ok(Location::Remote { bucket, key });
+        }
+        Ok(Location::Local(PathBuf::from(arg)))
+    }
+}
+
+/// Copies `src` to `dst`, where each is a local path or an `s3://` URI,
+/// choosing the operation from their shapes:
+///
+/// - file → s3: upload; directory → s3: sync to the prefix
+/// - s3 object → local: download; s3 prefix → local: download every object
+/// - s3 → s3: server-side copy of the object, or of every object under the prefix
+///
+/// `s3://` URIs name their bucket and bypass the default prefix. A
+/// destination ending in `/` (or an existing local directory) receives the
+/// source under its own name.
+pub async fn transfer(
+    src: &str,
+    dst: &str,
+    options: &TransferOptions,
+    config: &StorageConfig,
+    verbose: bool,
+    out: &mut dyn Write,
+) -> Result<()> {
+    match (Location::parse(src)?, Location::parse(dst)?) {
+        (Location::Local(_), Location::Local(_)) => {
+            bail!("At least one side of a transfer must be an s3:// URI")
+        }
+        (Location::Local(path), Location::Remote { bucket, key }) => {
+            let config = bucket_config(config, bucket);
+            let delimiter = config.delimiter.as_str();
+            if path.is_dir() {
+                let prefix = as_dir(&key, delimiter);
+                let sync = SyncOptions {
+                    delete: options.delete,
+                    dry_run: options.dry_run,
+                };
+                let prefix = (!prefix.is_empty()).then_some(prefix.as_str());
+                return sync_dir(src, prefix, &sync, &config, verbose, out).await;
+            }
+            if options.delete {
+                bail!("--delete only applies when uploading a directory");
+            }
+            let key = if is_dir_key(&key, delimiter) {
+                format!("{}{}", key, file_name(&path)?)
+            } else {
+                key
+            };
+            if options.dry_run {
+                writeln!(out, "upload {} -> s3://{}/{}", src, config.bucket, key)?;
+                return Ok(());
+            }
+            let upload = UploadOptions {
+                key: Some(key),
+                ..Default::default()
+            };
+            let info = upload_file(src, &config, verbose, &upload).await?;
+            write_upload_info(out, &info, config.json)?;
+            Ok(())
+        }
+        (Location::Remote { bucket, key }, Location::Local(path)) => {
+            if options.delete {
+                bail!("--delete only applies when uploading a directory");
+            }
+            let config = bucket_config(config, bucket);
+            if is_dir_key(&key, &config.delimiter) {
+                download_prefix(&key, &path, options, &config, verbose, out).await
+            } else {
+                let object_name = last_segment(&key, &config.delimiter);
+                let output = if path.is_dir() || dst.ends_with('/') || dst.ends_with('\\') {
+                    path.join(object_name)
+                } else {
+                    path
+                };
+                download_one(&key, &output, options, &config, verbose, out).await
+            }
+        }
+        (
+            Location::Remote { bucket, key },
+            Location::Remote {
+                bucket: dest_bucket,
+                key: dest_key,
+            },
+        ) => {
+            if options.delete {
+                bail!("--delete only applies when uploading a directory");
+            }
+            let config = bucket_config(config, bucket);
+            let delimiter = config.delimiter.as_str();
+            if is_dir_key(&key, delimiter) {
+                copy_prefix(
+                    &key,
+                    &dest_bucket,
+                    &as_dir(&dest_key, delimiter),
+                    options,
+                    &config,
+                    verbose,
+                    out,
+                )
+                .await
+            } else {
+                let dest_key = if is_dir_key(&dest_key, delimiter) {
+                    format!("{}{}", dest_key, last_segment(&key, delimiter))
+                } else {
+                    dest_key
+                };
+                copy_one(
+                    &key,
+                    &dest_bucket,
+                    &dest_key,
+                    options,
+                    &config,
+                    verbose,
+                    out,
+                )
+                .await
+            }
+        }
+    }
+}
+
+/// The config for working in `bucket` with keys taken as given.
+fn bucket_config(config: &StorageConfig, bucket: String) -> StorageConfig {
+    StorageConfig {
+        bucket,
+        default_prefix: None,
+        ..config.clone()
+    }
+}
+
+fn file_name(path: &Path) -> Result<String> {
+    match path.file_name() {
+        Some(name) => Ok(name.to_string_lossy().into_owned()),
+        None => bail!("{} has no file name", path.display()),
+    }
+}
+
+async fn download_one(
+    key: &str,
+    output: &Path,
+    options: &TransferOptions,
+    config: &StorageConfig,
+    verbose: bool,
+    out: &mut dyn Write,
+) -> Result<()> {
+    if options.dry_run {
+        writeln!(
+            out,
+            "download s3://{}/{} -> {}",
+            config.bucket,
+            key,
+            output.display()
+        )?;
+        return Ok(());
+    }
+    let download = DownloadOptions {
+        output: Some(output.to_string_lossy().into_owned()),
+        ..Default::default()
+    };
+    download_file(
+        &format!("s3://{}/{}", config.bucket, key),
+        &download,
+        config,
+        verbose,
+        out,
+    )
+    .await
+}
+
+/// Downloads every object under `prefix` into `dir`, keeping the key
+/// structure below the prefix.
+async fn download_prefix(
+    prefix: &str,
+    dir: &Path,
+    options: &TransferOptions,
+    config: &StorageConfig,
+    verbose: bool,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let planned =
+        plan_prefix_download(prefix, dir, &RecursiveOptions::default(), config, verbose).await?;
+    if options.dry_run {
+        for (key, path) in &planned {
+            writeln!(
+                out,
+                "download s3://{}/{} -> {}",
+                config.bucket,
+                key,
+                path.display()
+            )?;
+        }
+        writeln!(out, "Dry run: would download {} object(s)", planned.len())?;
+        return Ok(());
+    }
+    download_planned(&planned, &DownloadOptions::default(), config, verbose, out).await
+}
+
+async fn copy_one(
+    key: &str,
+    dest_bucket: &str,
+    dest_key: &str,
+    options: &TransferOptions,
+    config: &StorageConfig,
+    verbose: bool,
+    out: &mut dyn Write,
+) -> Result<()> {
+    if options.dry_run {
+        writeln!(
+            out,
+            "copy s3://{}/{} -> s3://{}/{}",
+            config.bucket, key, dest_bucket, dest_key
+        )?;
+        return Ok(());
+    }
+    let copy = CopyOptions {
+        dest_bucket: Some(dest_bucket.to_string()),
+        ..Default::default()
+    };
+    copy_file(key, dest_key, &copy, config, verbose).await?;
+    writeln!(
+        out,
+        "Copied: s3://{}/{} -> s3://{}/{}",
+        config.bucket, key, dest_bucket, dest_key
+    )?;
+    Ok(())
+}
+
+/// Copies every object under `prefix` to the same relative key under
+/// `dest_prefix` in `dest_bucket`.
+async fn copy_prefix(
+    prefix: &str,
+    dest_bucket: &str,
+    dest_prefix: &str,
+    options: &TransferOptions,
+    config: &StorageConfig,
+    verbose: bool,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let client = create_client(config, verbose).await?;
+    let objects = list_all(
+        &client,
+        &config.bucket,
+        (!prefix.is_empty()).then_some(prefix),
+    )
+    .await?;
+    let planned: Vec<(String, String)> = objects
+        .iter()
+        .filter_map(|o| o.key())
+        .map(|key| {
+            (
+                key.to_string(),
+                format!("{}{}", dest_prefix, &key[prefix.len()..]),
+            )
+        })
+        .collect();
+
+    if options.dry_run {
+        for (key, dest_key) in &planned {
+            copy_one(key, dest_bucket, dest_key, options, config, verbose, out).await?;
+        }
+        writeln!(out, "Dry run: would copy {} object(s)", planned.len())?;
+        return Ok(());
+    }
+
+    let results: Vec<(&String, Result<Vec<u8>>)> = futures::stream::iter(&planned)
+        .map(|(key, dest_key)| async move {
+            let mut report = Vec::new();
+            let result = copy_one(
+                key,
+                dest_bucket,
+                dest_key,
+                options,
+                config,
+                verbose,
+                &mut report,
+            )
+            .await
+            .map(|_| report);
+            (key, result)
+        })
+        .buffer_unordered(config.concurrency)
+        .collect()
+        .await;
+
+    report_batch(results, "copy", "Copied", out)
+}
@@ -1,95 +1,2293 @@
-use crate::{config::StorageConfig, s3_client::create_client, utils::format_size};
-use anyhow::Result;
+use crate::{
+    config::StorageConfig,
+    error::StorageError,
+    keys::{
+        as_dir, attachment_disposition, derive_key_from_path, join_prefix, last_segment,
+        sanitize_key, validate_object_key, ORIGINAL_FILENAME_METADATA, SHA256_METADATA,
+    },
+    s3_client::{
+        charge_retry, create_client, is_retryable, retry_budget_spent, retry_delay,
+        with_region_retry, with_retry,
+    },
+    utils::{
+        draws_bars, format_size, hash_file, hex, log_progress, progress_style, ProgressMode, Units,
+    },
+};
+use anyhow::{bail, Context, Result};
+use aws_sdk_s3::config::http::HttpResponse;
+use aws_sdk_s3::config::retry::RetryConfig;
+use aws_sdk_s3::error::{ProvideErrorMetadata, SdkError};
+use aws_sdk_s3::operation::create_multipart_upload::builders::CreateMultipartUploadFluentBuilder;
+use aws_sdk_s3::operation::list_parts::ListPartsError;
+use aws_sdk_s3::operation::put_object::builders::PutObjectFluentBuilder;
+use aws_sdk_s3::operation::upload_part::UploadPartError;
 use aws_sdk_s3::presigning::PresigningConfig;
-use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::primitives::{ByteStream, Length};
+use aws_sdk_s3::types::{
+    ChecksumAlgorithm, CompletedMultipartUpload, CompletedPart, ObjectCannedAcl,
+    ServerSideEncryption, StorageClass,
+};
+use aws_sdk_s3::Client;
+use futures::StreamExt;
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
 use std::{fs, path::Path, time::Duration};
 
+pub const DEFAULT_MULTIPART_THRESHOLD: u64 = 16 * 1024 * 1024;
+pub const DEFAULT_PART_SIZE: u64 = 8 * 1024 * 1024;
+
+// S3 multipart limits
+const MIN_PART_SIZE: u64 = 5 * 1024 * 1024;
+const MAX_PART_SIZE: u64 = 5 * 1024 * 1024 * 1024;
+const MAX_PARTS: u64 = 10_000;
+
+#[derive(Debug, Serialize)]
 pub struct UploadInfo {
     pub file_name: String,
+    pub bucket: String,
+    pub key: String,
+    pub size: u64,
     pub download_url: String,
 }
 
+/// Writes the summary printed for each uploaded file: one line, or a JSON
+/// object with `json`.
+pub fn write_upload_info(
+    out: &mut dyn std::io::Write,
+    info: &UploadInfo,
+    json: bool,
+) -> std::io::Result<()> {
+    if json {
+        serde_json::to_writer(&mut *out, info)?;
+        return writeln!(out);
+    }
+    writeln!(out, "Uploaded: {} -> {}", info.file_name, info.download_url)
+}
+
+/// Writes each uploaded file's line, then a count of successes and failures.
+/// The failures themselves go to stderr. With `json`, writes one object
+/// holding both lists instead.
+pub fn write_batch_summary(
+    out: &mut dyn std::io::Write,
+    summary: &BatchUploadSummary,
+    json: bool,
+) -> std::io::Result<()> {
+    for (file, message) in &summary.failed {
+        elog!("❌ Failed to upload {}: {}", file, message);
+    }
+    if json {
+        let failed: Vec<_> = summary
+            .failed
+            .iter()
+            .map(|(file, message)| serde_json::json!({ "file": file, "error": message }))
+            .collect();
+        let value = serde_json::json!({ "uploaded": summary.uploaded, "failed": failed });
+        serde_json::to_writer(&mut *out, &value)?;
+        return writeln!(out);
+    }
+    for info in &summary.uploaded {
+        write_upload_info(out, info, false)?;
+    }
+    writeln!(
+        out,
+        "Uploaded {} file(s), {} failed",
+        summary.uploaded.len(),
+        summary.failed.len()
+    )
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MultipartMode {
+    /// Use multipart once the file reaches the threshold
+    #[default]
+    Auto,
+    /// Always use a single `put_object`
+    Never,
+    /// Always use multipart, regardless of size
+    Always,
+}
+
+#[derive(Debug, Clone)]
+pub struct UploadOptions {
+    /// Object key, relative to the default prefix (defaults to the basename)
+    pub key: Option<String>,
+    /// Folder the key is placed under, e.g. `site` gives `site/<key>`
+    pub prefix: Option<String>,
+    pub expires_seconds: Option<u64>,
+    /// Delete the object once its download link expires. The deletion runs
+    /// on a task in this process, so it only happens if the process is
+    /// still running then; off unless set
+    pub delete_after_expiry: bool,
+    pub multipart: MultipartMode,
+    pub multipart_threshold: u64,
+    /// Part size in bytes; chosen from the file size when unset
+    pub part_size: Option<u64>,
+    /// Explicit `Cache-Control`, overriding the content-type defaults
+    pub cache_control: Option<String>,
+    /// Apply the content-type `Cache-Control` defaults from the config
+    pub auto_cache_control: bool,
+    /// Advanced by the bytes sent as the upload progresses
+    pub progress: Option<ProgressBar>,
+    /// Wait up to this long for the object to become readable
+    pub wait: Option<Duration>,
+    pub encryption: Encryption,
+    /// User metadata stored as `x-amz-meta-*` headers
+    pub metadata: HashMap<String, String>,
+    /// Object tags, as `(key, value)` pairs
+    pub tags: Vec<(String, String)>,
+    /// Record multipart progress in a `.s3upload-state` sidecar so an
+    /// interrupted upload can be resumed by running it again
+    pub resumable: bool,
+    /// Ignore (and abort) any saved multipart upload and start over
+    pub restart: bool,
+    /// Presign the download URL with `Content-Disposition: attachment`
+    pub force_download: bool,
+    /// Content type to store, instead of guessing from the extension
+    pub content_type: Option<String>,
+    /// Canned ACL to apply, e.g. `public-read`
+    pub acl: Option<String>,
+    /// Storage class, e.g. `STANDARD_IA`; the bucket default when unset
+    pub storage_class: Option<String>,
+    /// After uploading, check the key shows up in a listing with the right size
+    pub verify_listed: bool,
+    /// Encrypt client-side with a key derived from this passphrase
+    pub passphrase: Option<String>,
+    /// Record the file's Unix mode, uid and gid in the metadata
+    pub preserve_permissions: bool,
+}
+
+/// Server-side encryption requested for uploaded objects.
+#[derive(Debug, Clone, Default)]
+pub struct Encryption {
+    /// `AES256`, `aws:kms`, or `aws:kms:dsse`, in any case; the bucket
+    /// default when unset
+    pub sse: Option<String>,
+    /// Without one, `aws:kms` uses the bucket's default KMS key
+    pub kms_key_id: Option<String>,
+    /// Base64-encoded JSON encryption context for KMS
+    pub kms_context: Option<String>,
+    /// Use an S3 Bucket Key to cut KMS request costs
+    pub bucket_key: bool,
+}
+
+impl Encryption {
+    /// Whether KMS was requested explicitly (the bucket default may still be KMS).
+    pub fn uses_kms(&self) -> bool {
+        matches!(self.canonical_sse(), Some(sse) if sse.starts_with("aws:kms"))
+    }
+
+    /// The requested algorithm spelled the way S3 expects, so `aes256`
+    /// works as well as `AES256`.
+    fn canonical_sse(&self) -> Option<&'static str> {
+        let sse = self.sse.as_deref()?;
+        ServerSideEncryption::values()
+            .iter()
+            .copied()
+            .find(|value| value.eq_ignore_ascii_case(sse))
+    }
+
+    fn requested(&self) -> Option<ServerSideEncryption> {
+        self.canonical_sse().map(ServerSideEncryption::from)
+    }
+
+    /// Compares the encryption S3 reports having applied with what was
+    /// requested. Some S3-compatible backends accept the headers and
+    /// ignore them, so a mismatch is always warned about.
+    fn check_applied(&self, applied: Option<&ServerSideEncryption>, verbose: bool) {
+        if verbose {
+            match applied {
+                Some(sse) => elog!("  Server-side encryption: {}", sse.as_str()),
+                None => elog!("  Server-side encryption: none reported"),
+            }
+        }
+        let Some(requested) = self.requested() else {
+            return;
+        };
+        match applied {
+            Some(sse) if *sse == requested => {}
+            Some(sse) => elog!(
+                "⚠️ Requested --sse {} but the object was stored with {}",
+                requested.as_str(),
+                sse.as_str()
+            ),
+            None => elog!(
+                "⚠️ Requested --sse {} but the backend didn't report encrypting the object; it may have ignored the request",
+                requested.as_str()
+            ),
+        }
+    }
+
+    /// Checks the settings before anything is sent, so a typo fails fast
+    /// instead of partway through a multipart upload.
+    pub fn validate(&self) -> Result<()> {
+        let uses_kms = self.uses_kms();
+        if let Some(sse) = &self.sse {
+            if self.canonical_sse().is_none() {
+                bail!(StorageError::Config(format!(
+                    "Unknown --sse value '{}' (expected one of: {})",
+                    sse,
+                    ServerSideEncryption::values().join(", ")
+                )));
+            }
+        }
+        if !uses_kms && (self.kms_key_id.is_some() || self.kms_context.is_some()) {
+            bail!(StorageError::Config(
+                "--sse-kms-key-id and --sse-kms-context require --sse aws:kms".to_string()
+            ));
+        }
+        if let Some(context) = &self.kms_context {
+            validate_kms_context(context)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks `acl` is a canned ACL S3 knows, so a typo fails before upload.
+pub fn validate_acl(acl: &str) -> Result<()> {
+    if !ObjectCannedAcl::values().contains(&acl) {
+        bail!(StorageError::Config(format!(
+            "Unknown ACL '{}' (expected one of: {})",
+            acl,
+            ObjectCannedAcl::values().join(", ")
+        )));
+    }
+    Ok(())
+}
+
+/// Checks `class` is a storage class S3 knows, so a typo fails before upload.
+pub fn validate_storage_class(class: &str) -> Result<()> {
+    if !StorageClass::values().contains(&class) {
+        bail!(StorageError::Config(format!(
+            "Unknown storage class '{}' (expected one of: {})",
+            class,
+            StorageClass::values().join(", ")
+        )));
+    }
+    Ok(())
+}
+
+/// Loosely checks an explicit content type: it's sent verbatim, so this
+/// only catches values that are clearly not a `type/subtype`.
+pub fn validate_content_type(content_type: &str) -> Result<()> {
+    if !content_type.contains('/') || content_type.chars().any(char::is_control) {
+        bail!(StorageError::Config(format!(
+            "Invalid content type '{}' (expected type/subtype, e.g. application/geo+json)",
+            content_type.escape_debug()
+        )));
+    }
+    Ok(())
+}
+
+/// Records `file_name` in the metadata when the key's last segment differs
+/// from it, so downloads can recover the name. An explicit entry wins.
+fn with_original_filename(
+    metadata: &HashMap<String, String>,
+    key: &str,
+    delimiter: &str,
+    file_name: &str,
+) -> HashMap<String, String> {
+    let mut metadata = metadata.clone();
+    if last_segment(key, delimiter) != file_name {
+        metadata
+            .entry(ORIGINAL_FILENAME_METADATA.to_string())
+            .or_insert_with(|| urlencoding::encode(file_name).into_owned());
+    }
+    metadata
+}
+
+/// Combines metadata read from `file` with `inline` entries, the inline ones
+/// winning on conflicts.
+///
+/// The file is either a JSON object of string values or `key=value` lines,
+/// where blank lines and lines starting with `#` are ignored.
+pub fn resolve_metadata(
+    file: Option<&Path>,
+    inline: &[(String, String)],
+) -> Result<HashMap<String, String>> {
+    let mut entries = Vec::new();
+    if let Some(file) = file {
+        let contents = fs::read_to_string(file)
+            .with_context(|| format!("Failed to read metadata file {}", file.display()))?;
+        entries = parse_metadata_file(&contents)
+            .with_context(|| format!("Invalid metadata file {}", file.display()))?;
+    }
+    entries.extend(inline.iter().cloned());
+
+    // S3 stores names lowercased, so duplicates are found by that name
+    let mut seen = std::collections::HashSet::new();
+    for (name, _) in inline {
+        if !seen.insert(name.to_lowercase()) {
+            bail!(StorageError::Config(format!(
+                "Metadata key '{}' is given more than once",
+                name
+            )));
+        }
+    }
+
+    let mut metadata = HashMap::new();
+    for (name, value) in entries {
+        validate_metadata_key(&name)?;
+        metadata.insert(name.to_lowercase(), value);
+    }
+    Ok(metadata)
+}
+
+/// Most tags S3 allows on one object.
+const MAX_TAGS: usize = 10;
+
+/// Validates `tags` against the S3 tagging rules and encodes them as the
+/// `x-amz-tagging` query string, or `None` when there are none.
+pub fn tagging(tags: &[(String, String)]) -> Result<Option<String>> {
+    if tags.is_empty() {
+        return Ok(None);
+    }
+    if tags.len() > MAX_TAGS {
+        bail!(StorageError::Config(format!(
+            "At most {} tags are allowed, got {}",
+            MAX_TAGS,
+            tags.len()
+        )));
+    }
+    let allowed = |c: char| c.is_alphanumeric() || c.is_whitespace() || "_.:/=+-@".contains(c);
+    let mut seen = std::collections::HashSet::new();
+    for (name, value) in tags {
+        if name.is_empty() || name.chars().count() > 128 || !name.chars().all(allowed) {
+            bail!(StorageError::Config(format!(
+                "Invalid tag key '{}': use 1-128 letters, digits, spaces, or _ . : / = + - @",
+                name
+            )));
+        }
+        if name.to_ascii_lowercase().starts_with("aws:") {
+            bail!(StorageError::Config(format!(
+                "Tag key '{}' uses the reserved aws: prefix",
+                name
+            )));
+        }
+        if value.chars().count() > 256 || !value.chars().all(allowed) {
+            bail!(StorageError::Config(format!(
+                "Invalid value for tag '{}': use up to 256 letters, digits, spaces, or _ . : / = + - @",
+                name
+            )));
+        }
+        if !seen.insert(name.as_str()) {
+            bail!(StorageError::Config(format!(
+                "Tag key '{}' is given more than once",
+                name
+            )));
+        }
+    }
+    let encoded = tags
+        .iter()
+        .map(|(name, value)| {
+            format!(
+                "{}={}",
+                urlencoding::encode(name),
+                urlencoding::encode(value)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("&");
+    Ok(Some(encoded))
+}
+
+fn parse_metadata_file(contents: &str) -> Result<Vec<(String, String)>> {
+    if contents.trim_start().starts_with('{') {
+        let map: HashMap<String, serde_json::Value> = serde_json::from_str(contents)?;
+        return map
+            .into_iter()
+            .map(|(name, value)| match value {
+                serde_json::Value::String(s) => Ok((name, s)),
+                other => bail!(StorageError::Config(format!(
+                    "Value for '{}' must be a string, got {}",
+                    name, other
+                ))),
+            })
+            .collect();
+    }
+
+    let mut entries = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (name, value) = line.split_once('=').ok_or_else(|| {
+            StorageError::Config(format!(
+                "Line {}: expected key=value, got '{}'",
+                i + 1,
+                line
+            ))
+        })?;
+        entries.push((name.trim().to_string(), value.trim().to_string()));
+    }
+    Ok(entries)
+}
+
+/// Metadata names travel as HTTP header names, so keep to token characters.
+fn validate_metadata_key(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'));
+    if !valid {
+        bail!(StorageError::Config(format!(
+            "Invalid metadata key '{}': use letters, digits, '-', '_', or '.'",
+            name
+        )));
+    }
+    Ok(())
+}
+
+/// The encryption context must be base64 of a JSON object with string values.
+fn validate_kms_context(context: &str) -> Result<()> {
+    use base64::Engine;
+
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(context)
+        .context("--sse-kms-context is not valid base64")?;
+    let value: serde_json::Value = serde_json::from_slice(&decoded)
+        .context("--sse-kms-context does not decode to valid JSON")?;
+    match value.as_object() {
+        Some(map) if map.values().all(serde_json::Value::is_string) => Ok(()),
+        _ => bail!(StorageError::Config(
+            "--sse-kms-context must be a JSON object of string values".to_string()
+        )),
+    }
+}
+
+/// Settings for uploading a whole directory.
+#[derive(Debug, Clone)]
+pub struct BatchOptions {
+    /// Key by basename instead of the path relative to the directory
+    pub flatten: bool,
+    /// Files uploaded at once
+    pub concurrency: usize,
+    /// Show a bar per in-flight file under the aggregate bar
+    pub per_file_progress: bool,
+    /// Descend into symlinked directories and upload symlinked files
+    pub follow_symlinks: bool,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        BatchOptions {
+            flatten: false,
+            concurrency: 4,
+            per_file_progress: false,
+            follow_symlinks: false,
+        }
+    }
+}
+
+/// Headers applied to the object whichever upload path is taken.
+#[derive(Debug, Clone)]
+struct ObjectAttributes {
+    content_type: String,
+    cache_control: Option<String>,
+    encryption: Encryption,
+    metadata: HashMap<String, String>,
+    /// Encoded `x-amz-tagging` value
+    tagging: Option<String>,
+    acl: Option<ObjectCannedAcl>,
+    storage_class: Option<StorageClass>,
+    /// SHA-256 of the whole file
+    sha256: Vec<u8>,
+}
+
+impl ObjectAttributes {
+    /// Prints the metadata and tags about to be set, for verbose output.
+    fn log(&self) {
+        let mut metadata: Vec<_> = self.metadata.iter().collect();
+        metadata.sort();
+        for (name, value) in metadata {
+            elog!("  Metadata: {} = {}", name, value);
+        }
+        if let Some(tagging) = &self.tagging {
+            elog!("  Tags: {}", tagging);
+        }
+        if let Some(class) = &self.storage_class {
+            elog!("  Storage class: {}", class.as_str());
+        }
+    }
+
+    fn sse(&self) -> Option<ServerSideEncryption> {
+        self.encryption.requested()
+    }
+
+    fn apply_put(&self, request: PutObjectFluentBuilder) -> PutObjectFluentBuilder {
+        use base64::Engine;
+
+        request
+            .content_type(&self.content_type)
+            .set_cache_control(self.cache_control.clone())
+            .set_server_side_encryption(self.sse())
+            .set_ssekms_key_id(self.encryption.kms_key_id.clone())
+            .set_ssekms_encryption_context(self.encryption.kms_context.clone())
+            .set_bucket_key_enabled(self.encryption.bucket_key.then_some(true))
+            .set_metadata((!self.metadata.is_empty()).then(|| self.metadata.clone()))
+            .set_tagging(self.tagging.clone())
+            .set_acl(self.acl.clone())
+            .set_storage_class(self.storage_class.clone())
+            .checksum_sha256(base64::engine::general_purpose::STANDARD.encode(&self.sha256))
+    }
+
+    /// Multipart parts each carry their own SHA-256, which S3 combines into
+    /// a checksum of checksums; the whole-file hash is kept in the metadata.
+    fn apply_create(
+        &self,
+        request: CreateMultipartUploadFluentBuilder,
+    ) -> CreateMultipartUploadFluentBuilder {
+        request
+            .content_type(&self.content_type)
+            .set_cache_control(self.cache_control.clone())
+            .set_server_side_encryption(self.sse())
+            .set_ssekms_key_id(self.encryption.kms_key_id.clone())
+            .set_ssekms_encryption_context(self.encryption.kms_context.clone())
+            .set_bucket_key_enabled(self.encryption.bucket_key.then_some(true))
+            .set_metadata((!self.metadata.is_empty()).then(|| self.metadata.clone()))
+            .set_tagging(self.tagging.clone())
+            .set_acl(self.acl.clone())
+            .set_storage_class(self.storage_class.clone())
+            .checksum_algorithm(ChecksumAlgorithm::Sha256)
+    }
+}
+
+impl Default for UploadOptions {
+    fn default() -> Self {
+        UploadOptions {
+            key: None,
+            prefix: None,
+            expires_seconds: None,
+            delete_after_expiry: false,
+            multipart: MultipartMode::Auto,
+            multipart_threshold: DEFAULT_MULTIPART_THRESHOLD,
+            part_size: None,
+            cache_control: None,
+            auto_cache_control: false,
+            progress: None,
+            wait: None,
+            encryption: Encryption::default(),
+            metadata: HashMap::new(),
+            tags: Vec::new(),
+            resumable: false,
+            restart: false,
+            force_download: false,
+            content_type: None,
+            acl: None,
+            storage_class: None,
+            verify_listed: false,
+            passphrase: None,
+            preserve_permissions: false,
+        }
+    }
+}
+
+/// A `file_path` of `-` uploads standard input.
+pub const STDIN_PATH: &str = "-";
+
 pub async fn upload_file(
     file_path: &str,
     config: &StorageConfig,
     verbose: bool,
-    expires_seconds: Option<u64>,
+    options: &UploadOptions,
+) -> Result<UploadInfo> {
+    if file_path == STDIN_PATH {
+        return upload_stdin(config, verbose, options).await;
+    }
+
+    // Read from the original, since an encrypted upload sends a temporary copy
+    let with_permissions;
+    let options = if options.preserve_permissions {
+        let mut metadata = options.metadata.clone();
+        metadata.extend(crate::permissions::capture(Path::new(file_path))?);
+        with_permissions = UploadOptions {
+            metadata,
+            preserve_permissions: false,
+            ..options.clone()
+        };
+        &with_permissions
+    } else {
+        options
+    };
+
+    match &options.passphrase {
+        Some(passphrase) => {
+            upload_encrypted(Path::new(file_path), passphrase, config, verbose, options).await
+        }
+        None => upload_plain(file_path, config, verbose, options).await,
+    }
+}
+
+/// Encrypts the file into a temporary copy under the same name, so the key
+/// and content type come out as they would for the original, and uploads
+/// that with the encryption parameters in its metadata.
+async fn upload_encrypted(
+    path: &Path,
+    passphrase: &str,
+    config: &StorageConfig,
+    verbose: bool,
+    options: &UploadOptions,
+) -> Result<UploadInfo> {
+    static COUNTER: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+    let name = path
+        .file_name()
+        .ok_or_else(|| StorageError::Config(format!("Not a file: {}", path.display())))?;
+    let dir = std::env::temp_dir().join(format!(
+        "s3-uploader-encrypt-{}-{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+    ));
+    fs::create_dir_all(&dir)?;
+    let encrypted = dir.join(name);
+
+    let result = async {
+        if verbose {
+            elog!("🔒 Encrypting {}", path.display());
+        }
+        let (source, dest, passphrase) = (
+            path.to_path_buf(),
+            encrypted.clone(),
+            passphrase.to_string(),
+        );
+        let crypto_metadata = tokio::task::spawn_blocking(move || {
+            crate::crypto::encrypt_file(&source, &dest, &passphrase)
+        })
+        .await?
+        .with_context(|| format!("Failed to encrypt {}", path.display()))?;
+
+        let mut metadata = options.metadata.clone();
+        metadata.extend(crypto_metadata);
+        // Every run encrypts differently, so saved progress could never match
+        let options = UploadOptions {
+            metadata,
+            passphrase: None,
+            resumable: false,
+            ..options.clone()
+        };
+        upload_plain(&encrypted.to_string_lossy(), config, verbose, &options).await
+    }
+    .await;
+
+    let _ = fs::remove_file(&encrypted);
+    let _ = fs::remove_dir(&dir);
+    result
+}
+
+async fn upload_plain(
+    file_path: &str,
+    config: &StorageConfig,
+    verbose: bool,
+    options: &UploadOptions,
 ) -> Result<UploadInfo> {
     if verbose {
-        println!("📤 Uploading file: {}", file_path);
-        println!("  Max size allowed: {}", format_size(config.max_size));
+        elog!("📤 Uploading file: {}", file_path);
+        match config.size_limit() {
+            Some(limit) => elog!("  Max size allowed: {}", format_size(limit, config.units)),
+            None => elog!("  Max size allowed: unlimited"),
+        }
+    }
+
+    options.encryption.validate()?;
+    if let Some(acl) = &options.acl {
+        validate_acl(acl)?;
+    }
+    if let Some(class) = &options.storage_class {
+        validate_storage_class(class)?;
+    }
+    if let Some(content_type) = &options.content_type {
+        validate_content_type(content_type)?;
     }
 
     let path = Path::new(file_path);
     if !path.exists() {
-        anyhow::bail!("File does not exist: {}", file_path);
+        bail!(StorageError::Io(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("File does not exist: {}", file_path)
+        )));
     }
 
     let metadata = fs::metadata(path)?;
-    if metadata.len() > config.max_size {
-        anyhow::bail!(
-            "File exceeds max size {} (file size: {})",
-            format_size(config.max_size),
-            format_size(metadata.len())
+    if let Some(limit) = config.size_limit() {
+        if metadata.len() > limit {
+            if verbose {
+                elog!(
+                    "  File is {}, over the {} limit",
+                    format_size(metadata.len(), config.units),
+                    format_size(limit, config.units)
+                );
+            }
+            return Err(StorageError::FileTooLarge {
+                size: metadata.len(),
+                limit,
+            }
+            .into());
+        }
+    }
+
+    if verbose {
+        elog!("  File size: {}", format_size(metadata.len(), config.units));
+    }
+
+    let file_name = derive_key_from_path(path, None, &config.delimiter)?;
+    let key = match &options.key {
+        // An explicit key is used verbatim
+        Some(key) => {
+            validate_object_key(key)?;
+            key.clone()
+        }
+        None => file_name.clone(),
+    };
+    let key = match &options.prefix {
+        Some(prefix) => join_prefix(&as_dir(&sanitize_key(prefix)?, &config.delimiter), &key),
+        None => key,
+    };
+    let key = config.prefixed(&key);
+    let content_type = match &options.content_type {
+        Some(ct) => ct.clone(),
+        None => mime_guess::from_path(path)
+            .first_or_octet_stream()
+            .to_string(),
+    };
+    let cache_control = resolve_cache_control(options, config, &content_type, verbose);
+
+    let hashed = path.to_path_buf();
+    let sha256 = tokio::task::spawn_blocking(move || hash_file::<Sha256>(&hashed))
+        .await?
+        .with_context(|| format!("Failed to read {}", file_path))?;
+    if verbose {
+        elog!("  SHA-256: {}", hex(&sha256));
+    }
+    let mut object_metadata =
+        with_original_filename(&options.metadata, &key, &config.delimiter, &file_name);
+    object_metadata.insert(SHA256_METADATA.to_string(), hex(&sha256));
+
+    let attributes = ObjectAttributes {
+        content_type,
+        cache_control,
+        encryption: options.encryption.clone(),
+        metadata: object_metadata,
+        tagging: tagging(&options.tags)?,
+        acl: options.acl.as_deref().map(ObjectCannedAcl::from),
+        storage_class: options.storage_class.as_deref().map(StorageClass::from),
+        sha256,
+    };
+    if verbose {
+        attributes.log();
+    }
+    let size = metadata.len();
+
+    let use_multipart = match options.multipart {
+        MultipartMode::Auto => size >= options.multipart_threshold,
+        MultipartMode::Never => false,
+        MultipartMode::Always => true,
+    };
+
+    let part_size = if use_multipart {
+        resolve_part_size(size, options.part_size, config.units)?
+    } else {
+        DEFAULT_PART_SIZE
+    };
+    if verbose {
+        if use_multipart {
+            elog!(
+                "  File is {} bytes, using multipart (threshold {}, part size {}, {} parts)",
+                size,
+                options.multipart_threshold,
+                part_size,
+                size.div_ceil(part_size).max(1)
+            );
+        } else {
+            elog!(
+                "  File is {} bytes, using single-part upload (threshold {})",
+                size,
+                options.multipart_threshold
+            );
+        }
+    }
+
+    let state_path = (use_multipart && options.resumable).then(|| state_file_path(&key));
+    let (key_ref, attributes_ref) = (&key, &attributes);
+    let state_path_ref = state_path.as_deref();
+    let progress = options.progress.as_ref();
+    let applied = with_region_retry(config, verbose, |client| async move {
+        // A region retry starts the upload over
+        if let Some(pb) = progress {
+            pb.set_position(0);
+        }
+        if use_multipart {
+            return upload_multipart(
+                &client,
+                &config.bucket,
+                key_ref,
+                path,
+                attributes_ref,
+                size,
+                part_size,
+                progress,
+                config.units,
+                config.concurrency,
+                config.max_retries,
+                config.unsigned_payload,
+                config.retry_budget,
+                state_path_ref,
+                options.restart,
+                verbose,
+            )
+            .await;
+        }
+
+        let body = ByteStream::from_path(path).await?;
+        let request = attributes_ref
+            .apply_put(client.put_object())
+            .bucket(&config.bucket)
+            .key(key_ref)
+            .body(body);
+        let output = if config.unsigned_payload {
+            request.customize().disable_payload_signing().send().await
+        } else {
+            request.send().await
+        }
+        .map_err(StorageError::from)?;
+        if let Some(pb) = progress {
+            pb.inc(size);
+        }
+        Ok(output.server_side_encryption().cloned())
+    })
+    .await?;
+
+    if verbose {
+        elog!("  ✅ Upload completed");
+    }
+    attributes
+        .encryption
+        .check_applied(applied.as_ref(), verbose);
+
+    finish_upload(key, file_name, size, config, verbose, options).await
+}
+
+/// Streams standard input to `options.key`. The size isn't known up front,
+/// so this is always a multipart upload: parts are read one after another
+/// while up to `config.concurrency` of them are in flight, which keeps
+/// memory bounded. The size limit is a hard cap; passing it aborts the
+/// upload.
+async fn upload_stdin(
+    config: &StorageConfig,
+    verbose: bool,
+    options: &UploadOptions,
+) -> Result<UploadInfo> {
+    let Some(key) = &options.key else {
+        bail!(StorageError::Config(
+            "Uploading from stdin needs --key, since there is no file name".to_string()
+        ));
+    };
+    if options.multipart == MultipartMode::Never {
+        bail!(StorageError::Config(
+            "Uploading from stdin always uses multipart; drop --no-multipart".to_string()
+        ));
+    }
+    if options.passphrase.is_some() {
+        bail!(StorageError::Config(
+            "--encrypt can't be used when uploading from stdin".to_string()
+        ));
+    }
+    if options.preserve_permissions {
+        bail!(StorageError::Config(
+            "--preserve-permissions can't be used when uploading from stdin".to_string()
+        ));
+    }
+    options.encryption.validate()?;
+    if let Some(acl) = &options.acl {
+        validate_acl(acl)?;
+    }
+    if let Some(class) = &options.storage_class {
+        validate_storage_class(class)?;
+    }
+    if let Some(content_type) = &options.content_type {
+        validate_content_type(content_type)?;
+    }
+
+    validate_object_key(key)?;
+    let key = key.to_string();
+    let key = match &options.prefix {
+        Some(prefix) => join_prefix(&as_dir(&sanitize_key(prefix)?, &config.delimiter), &key),
+        None => key,
+    };
+    let key = config.prefixed(&key);
+    let part_size = options.part_size.unwrap_or(DEFAULT_PART_SIZE);
+    check_part_size(part_size, config.units)?;
+    let content_type = options
+        .content_type
+        .clone()
+        .unwrap_or_else(|| "application/octet-stream".to_string());
+    let cache_control = resolve_cache_control(options, config, &content_type, verbose);
+    if verbose {
+        elog!("📤 Uploading stdin to {}", key);
+        match config.size_limit() {
+            Some(limit) => elog!("  Max size allowed: {}", format_size(limit, config.units)),
+            None => elog!("  Max size allowed: unlimited"),
+        }
+        elog!(
+            "  Part size {}, so at most {} can be sent",
+            format_size(part_size, config.units),
+            format_size(part_size * MAX_PARTS, config.units)
         );
     }
 
+    // The whole-stream hash isn't known until the end, after the metadata
+    // is fixed, so only the per-part checksums protect the data
+    let attributes = ObjectAttributes {
+        content_type,
+        cache_control,
+        encryption: options.encryption.clone(),
+        metadata: options.metadata.clone(),
+        tagging: tagging(&options.tags)?,
+        acl: options.acl.as_deref().map(ObjectCannedAcl::from),
+        storage_class: options.storage_class.as_deref().map(StorageClass::from),
+        sha256: Vec::new(),
+    };
     if verbose {
-        println!("  File size: {}", format_size(metadata.len()));
+        attributes.log();
+    }
+
+    // Stdin can't be replayed, so only creating the upload is retried
+    // against a corrected region
+    let (key_ref, attributes_ref) = (&key, &attributes);
+    let upload_id = with_region_retry(config, verbose, |client| async move {
+        let created = attributes_ref
+            .apply_create(client.create_multipart_upload())
+            .bucket(&config.bucket)
+            .key(key_ref)
+            .send()
+            .await
+            .map_err(StorageError::from)?;
+        let upload_id = created.upload_id().ok_or_else(|| {
+            StorageError::backend(format!(
+                "Multipart upload for {} returned no upload id",
+                key_ref
+            ))
+        })?;
+        Ok(upload_id.to_string())
+    })
+    .await?;
+    if verbose {
+        elog!("  Started multipart upload {}", upload_id);
     }
 
     let client = create_client(config, verbose).await?;
-    let file_name = path.file_name().unwrap().to_string_lossy().to_string();
-    let content_type = mime_guess::from_path(path)
-        .first_or_octet_stream()
-        .to_string();
-    let body = ByteStream::from_path(path).await?;
-
-    client
-        .put_object()
-        .bucket(&config.bucket)
-        .key(&file_name)
-        .content_type(content_type)
-        .body(body)
-        .send()
+    let target = MultipartTarget {
+        client: &client,
+        bucket: &config.bucket,
+        key: &key,
+        upload_id: &upload_id,
+        path: Path::new(STDIN_PATH),
+        progress: options.progress.as_ref(),
+        units: config.units,
+        concurrency: config.concurrency,
+        max_retries: config.max_retries,
+        unsigned_payload: config.unsigned_payload,
+        retry_budget: config.retry_budget,
+    };
+    let result = async {
+        let (parts, size) = upload_stream_parts(
+            &target,
+            tokio::io::stdin(),
+            part_size,
+            config.size_limit(),
+            verbose,
+        )
         .await?;
+        let applied = complete_with_retry(&target, parts, verbose).await?;
+        anyhow::Ok((size, applied))
+    }
+    .await;
+    let (size, applied) = match result {
+        Ok(done) => done,
+        Err(e) => {
+            if verbose {
+                elog!("  Aborting multipart upload {}", upload_id);
+            }
+            abort_upload(&client, &config.bucket, &key, &upload_id).await;
+            return Err(e);
+        }
+    };
+
+    if verbose {
+        elog!(
+            "  ✅ Upload completed ({})",
+            format_size(size, config.units)
+        );
+    }
+    attributes
+        .encryption
+        .check_applied(applied.as_ref(), verbose);
+
+    let file_name = last_segment(&key, &config.delimiter).to_string();
+    finish_upload(key, file_name, size, config, verbose, options).await
+}
+
+/// Reads `input` (stdin) in `part_size` chunks and uploads each as a part,
+/// returning the parts in order and the total bytes read. Fails with
+/// `FileTooLarge` as soon as more than `limit` bytes have arrived.
+async fn upload_stream_parts<R: tokio::io::AsyncRead + Unpin>(
+    target: &MultipartTarget<'_>,
+    input: R,
+    part_size: u64,
+    limit: Option<u64>,
+    verbose: bool,
+) -> Result<(Vec<CompletedPart>, u64)> {
+    use tokio::io::AsyncReadExt;
+
+    struct Reader<R> {
+        input: R,
+        next_part: i32,
+        total: u64,
+        done: bool,
+    }
+
+    let reader = Reader {
+        input,
+        next_part: 1,
+        total: 0,
+        done: false,
+    };
+    // Yields `(part number, bytes)`; buffer_unordered only pulls the next
+    // chunk when a slot is free, which bounds what's held in memory
+    let chunks = futures::stream::unfold(reader, move |mut reader| async move {
+        if reader.done {
+            return None;
+        }
+        let mut chunk = Vec::with_capacity(part_size as usize);
+        if let Err(e) = (&mut reader.input)
+            .take(part_size)
+            .read_to_end(&mut chunk)
+            .await
+        {
+            reader.done = true;
+            return Some((
+                Err(anyhow::Error::from(e).context("Failed to read stdin")),
+                reader,
+            ));
+        }
+        // An empty read ends the stream, except that an empty input still
+        // needs one (empty) part
+        if chunk.is_empty() && reader.next_part > 1 {
+            return None;
+        }
+        reader.done = (chunk.len() as u64) < part_size;
+        reader.total += chunk.len() as u64;
+        if let Some(limit) = limit.filter(|&limit| reader.total > limit) {
+            reader.done = true;
+            let err = StorageError::FileTooLarge {
+                size: reader.total,
+                limit,
+            };
+            return Some((Err(err.into()), reader));
+        }
+        if reader.next_part as u64 > MAX_PARTS {
+            reader.done = true;
+            let err = StorageError::Config(format!(
+                "Input needs more than {} parts; use a larger --part-size",
+                MAX_PARTS
+            ));
+            return Some((Err(err.into()), reader));
+        }
+        let part_number = reader.next_part;
+        reader.next_part += 1;
+        Some((Ok((part_number, bytes::Bytes::from(chunk))), reader))
+    });
+
+    let uploads = chunks
+        .map(|chunk| async move {
+            let (part_number, data) = chunk?;
+            let length = data.len() as u64;
+            let part = with_retry(
+                target.max_retries,
+                target.retry_budget,
+                &format!("Part {}", part_number),
+                verbose,
+                || send_part(target, part_number, ByteStream::from(data.clone())),
+            )
+            .await
+            .map_err(StorageError::from)
+            .with_context(|| format!("Part {} failed", part_number))?;
+            anyhow::Ok((part, length))
+        })
+        .buffer_unordered(target.concurrency.max(1));
+    let mut uploads = std::pin::pin!(uploads);
+
+    let mut parts = Vec::new();
+    let mut total = 0;
+    while let Some(uploaded) = uploads.next().await {
+        let (part, length) = uploaded?;
+        total += length;
+        if let Some(pb) = target.progress {
+            pb.inc_length(length);
+            pb.inc(length);
+        }
+        if verbose {
+            elog!(
+                "  Uploaded part {} ({})",
+                part.part_number().unwrap_or_default(),
+                format_size(length, target.units)
+            );
+        }
+        parts.push(part);
+    }
+
+    parts.sort_by_key(|p| p.part_number());
+    Ok((parts, total))
+}
 
+/// The `Cache-Control` to store: the explicit one, else the config default
+/// for `content_type` when `auto_cache_control` is set.
+fn resolve_cache_control(
+    options: &UploadOptions,
+    config: &StorageConfig,
+    content_type: &str,
+    verbose: bool,
+) -> Option<String> {
+    let cache_control = match &options.cache_control {
+        Some(cc) => Some(cc.clone()),
+        None if options.auto_cache_control => {
+            config.cache_control_for(content_type).map(str::to_string)
+        }
+        None => None,
+    };
     if verbose {
-        println!("  ✅ Upload completed");
+        if let Some(cc) = &cache_control {
+            elog!("  Cache-Control: {}", cc);
+        }
+    }
+    cache_control
+}
+
+/// Runs the post-upload checks, presigns the download URL, and schedules
+/// the object's deletion after the URL expires.
+async fn finish_upload(
+    key: String,
+    file_name: String,
+    size: u64,
+    config: &StorageConfig,
+    verbose: bool,
+    options: &UploadOptions,
+) -> Result<UploadInfo> {
+    // Any region correction from the upload is picked up here
+    let client = create_client(config, verbose).await?;
+
+    if let Some(timeout) = options.wait {
+        wait_until_visible(&client, &config.bucket, &key, timeout, verbose).await?;
+    }
+    if options.verify_listed {
+        verify_listed(&client, &config.bucket, &key, size, verbose).await?;
     }
 
     // Generate presigned URL
-    let expires = Duration::from_secs(expires_seconds.unwrap_or(3600));
+    let expires = Duration::from_secs(options.expires_seconds.unwrap_or(3600));
     let presign_config = PresigningConfig::expires_in(expires)?;
     let presigned_req = client
         .get_object()
         .bucket(&config.bucket)
-        .key(&file_name)
+        .key(&key)
+        .set_response_content_disposition(
+            options.force_download.then(|| attachment_disposition(&key)),
+        )
         .presigned(presign_config)
         .await?;
 
-    // Clone file_name for the async task so we can still return the original
-    let file_name_clone = file_name.clone();
-    let bucket = config.bucket.clone();
-    let client_clone = client.clone();
-    tokio::spawn(async move {
-        if expires.as_secs() > 0 {
+    let key_for_info = key.clone();
+    if options.delete_after_expiry && expires.as_secs() > 0 {
+        let bucket = config.bucket.clone();
+        let client_clone = client.clone();
+        tokio::spawn(async move {
             tokio::time::sleep(expires).await;
             match client_clone
                 .delete_object()
                 .bucket(&bucket)
-                .key(&file_name_clone)
+                .key(&key)
                 .send()
                 .await
             {
-                Ok(_) => println!("🗑️ File {} deleted after TTL", file_name_clone),
-                Err(e) => eprintln!("❌ Failed to delete {}: {:?}", file_name_clone, e),
+                Ok(_) => elog!("🗑️ File {} deleted after TTL", key),
+                Err(e) => elog!("❌ Failed to delete {}: {:?}", key, e),
             }
-        }
-    });
+        });
+    }
 
     Ok(UploadInfo {
         file_name,
+        bucket: config.bucket.clone(),
+        key: key_for_info,
+        size,
         download_url: presigned_req.uri().to_string(),
     })
 }
+
+/// Polls `head_object` until the object is readable, for gateways that
+/// don't offer read-after-write consistency.
+async fn wait_until_visible(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    timeout: Duration,
+    verbose: bool,
+) -> Result<()> {
+    let started = std::time::Instant::now();
+    let mut delay = Duration::from_millis(100);
+    loop {
+        match client.head_object().bucket(bucket).key(key).send().await {
+            Ok(_) => break,
+            Err(e) if e.as_service_error().is_some_and(|se| se.is_not_found()) => {}
+            Err(e) => return Err(StorageError::from(e).into()),
+        }
+        if started.elapsed() >= timeout {
+            bail!(StorageError::backend(format!(
+                "Uploaded {} but it was still not visible after {}s",
+                key,
+                timeout.as_secs()
+            )));
+        }
+        tokio::time::sleep(delay.min(timeout.saturating_sub(started.elapsed()))).await;
+        delay = (delay * 2).min(Duration::from_secs(2));
+    }
+
+    if verbose {
+        elog!(
+            "  Object visible after {} ms",
+            started.elapsed().as_millis()
+        );
+    }
+    Ok(())
+}
+
+/// Lists `key` and checks it's there with `size` bytes, to catch backends
+/// that accept an upload but don't keep it.
+async fn verify_listed(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    size: u64,
+    verbose: bool,
+) -> Result<()> {
+    // A key sorts before every longer key it prefixes, so one result is enough
+    let listing = client
+        .list_objects_v2()
+        .bucket(bucket)
+        .prefix(key)
+        .max_keys(1)
+        .send()
+        .await
+        .map_err(StorageError::from)?;
+    let Some(object) = listing.contents().iter().find(|o| o.key() == Some(key)) else {
+        bail!(StorageError::backend(format!(
+            "Uploaded {} but it does not appear in the bucket listing",
+            key
+        )));
+    };
+    let listed = object.size().unwrap_or(0) as u64;
+    if listed != size {
+        bail!(StorageError::backend(format!(
+            "Uploaded {} ({} bytes) but the listing shows {} bytes",
+            key, size, listed
+        )));
+    }
+    if verbose {
+        elog!("  Verified {} is listed with {} bytes", key, size);
+    }
+    Ok(())
+}
+
+/// Checks a part size is within the S3 limits for files needing more than
+/// one part, for settings fixed before any file is seen.
+pub fn check_part_size(part_size: u64, units: Units) -> Result<()> {
+    if !(MIN_PART_SIZE..=MAX_PART_SIZE).contains(&part_size) {
+        bail!(StorageError::Config(format!(
+            "Part size {} must be between {} and {}",
+            format_size(part_size, units),
+            format_size(MIN_PART_SIZE, units),
+            format_size(MAX_PART_SIZE, units)
+        )));
+    }
+    Ok(())
+}
+
+/// Picks a part size for a `size`-byte upload, validating a requested one
+/// against the S3 limits: at most 10,000 parts of 5 MiB to 5 GiB each (only
+/// the last part may be smaller).
+fn resolve_part_size(size: u64, requested: Option<u64>, units: Units) -> Result<u64> {
+    let Some(part_size) = requested else {
+        // Smallest whole-MiB size that keeps within the part limit
+        let needed = size.div_ceil(MAX_PARTS).div_ceil(1024 * 1024) * 1024 * 1024;
+        return Ok(needed.max(DEFAULT_PART_SIZE));
+    };
+
+    if part_size > MAX_PART_SIZE {
+        bail!(StorageError::Config(format!(
+            "Part size {} exceeds the S3 maximum of {}",
+            format_size(part_size, units),
+            format_size(MAX_PART_SIZE, units)
+        )));
+    }
+    // A single part may be any size, since it is also the last part
+    if part_size < MIN_PART_SIZE && size > part_size {
+        bail!(StorageError::Config(format!(
+            "Part size {} is below the S3 minimum of {}",
+            format_size(part_size, units),
+            format_size(MIN_PART_SIZE, units)
+        )));
+    }
+    let parts = size.div_ceil(part_size.max(1));
+    if parts > MAX_PARTS {
+        bail!(StorageError::Config(format!(
+            "Part size {} would split {} into {} parts; S3 allows at most {} (use at least {} or omit --part-size)",
+            format_size(part_size, units),
+            format_size(size, units),
+            parts,
+            MAX_PARTS,
+            format_size(size.div_ceil(MAX_PARTS), units)
+        )));
+    }
+    Ok(part_size.max(1))
+}
+
+/// Outcome of a directory upload.
+#[derive(Default)]
+pub struct BatchUploadSummary {
+    /// Uploaded files, in path order
+    pub uploaded: Vec<UploadInfo>,
+    /// `(path, message)` for every file that failed
+    pub failed: Vec<(String, String)>,
+}
+
+/// Uploads every regular file under `dir`, keyed by its path relative to
+/// `dir`, or by basename alone when `flatten` is set.
+///
+/// Up to `concurrency` files are in flight at once, each sending its parts
+/// one at a time when more than one is. A failed file doesn't stop the
+/// others; failures are collected in the summary.
+pub async fn upload_dir(
+    dir: &Path,
+    batch: &BatchOptions,
+    config: &StorageConfig,
+    verbose: bool,
+    options: &UploadOptions,
+) -> Result<BatchUploadSummary> {
+    let flatten = batch.flatten;
+    let mut files = Vec::new();
+    let mut visited = std::collections::HashSet::new();
+    collect_files(dir, batch.follow_symlinks, &mut visited, &mut files)?;
+    files.sort();
+
+    let mut keyed: Vec<(String, PathBuf, u64)> = Vec::with_capacity(files.len());
+    for file in files {
+        let key = derive_key_from_path(&file, (!flatten).then_some(dir), &config.delimiter)?;
+        let size = fs::metadata(&file)?.len();
+        keyed.push((key, file, size));
+    }
+
+    if flatten {
+        let mut seen: HashMap<&str, &Path> = HashMap::new();
+        for (key, file, _) in &keyed {
+            if let Some(previous) = seen.insert(key, file) {
+                bail!(StorageError::Config(format!(
+                    "Cannot flatten: {} and {} would both be uploaded as {}",
+                    previous.display(),
+                    file.display(),
+                    key
+                )));
+            }
+        }
+    }
+
+    let concurrency = batch.concurrency.max(1);
+    // With files already in parallel, each sends its parts one at a time, so
+    // at most `concurrency` requests are in flight rather than its square
+    let file_config = if concurrency > 1 {
+        StorageConfig {
+            concurrency: 1,
+            ..config.clone()
+        }
+    } else {
+        config.clone()
+    };
+    let file_config = &file_config;
+    let total_bytes: u64 = keyed.iter().map(|(_, _, size)| size).sum();
+    if verbose {
+        elog!(
+            "📁 Uploading {} file(s) ({}) from {}, {} at a time",
+            keyed.len(),
+            format_size(total_bytes, config.units),
+            dir.display(),
+            concurrency
+        );
+    }
+
+    // Verbose output is line-based and would tear through the bars
+    let bars = draws_bars(config.progress);
+    let multi = if verbose || !bars {
+        MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+    } else {
+        MultiProgress::new()
+    };
+    let overall = multi.add(ProgressBar::new(total_bytes));
+    overall.set_style(progress_style(
+        "{msg} [{bar:40}] {bytes}/{total_bytes} ({eta})",
+        config.units,
+    ));
+    let file_count = keyed.len();
+    overall.set_message(format!("0/{} files", file_count));
+    if !bars && config.progress != ProgressMode::Never {
+        log_progress(&overall, config.units);
+    }
+
+    let (multi_ref, overall_ref) = (&multi, &overall);
+    let completed = std::sync::atomic::AtomicUsize::new(0);
+    let completed_ref = &completed;
+    let uploaded = futures::stream::iter(keyed)
+        .map(|(key, file, size)| async move {
+            let file_bar = batch.per_file_progress.then(|| {
+                let pb = multi_ref.insert_before(overall_ref, ProgressBar::new(size));
+                pb.set_style(progress_style(
+                    "  {msg:30!} [{bar:30}] {bytes}/{total_bytes}",
+                    config.units,
+                ));
+                pb.set_message(key.clone());
+                pb
+            });
+            let file_options = UploadOptions {
+                key: Some(key),
+                progress: file_bar.clone(),
+                ..options.clone()
+            };
+            let result =
+                upload_file(&file.to_string_lossy(), file_config, verbose, &file_options).await;
+            if let Some(pb) = file_bar {
+                pb.finish_and_clear();
+            }
+
+            let done = completed_ref.fetch_add(1, std::sync::atomic::Ordering::Relaxed) + 1;
+            overall_ref.inc(size);
+            overall_ref.set_message(format!("{}/{} files", done, file_count));
+            (file, result)
+        })
+        .buffered(concurrency)
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut summary = BatchUploadSummary::default();
+    for (file, result) in uploaded {
+        match result {
+            Ok(info) => summary.uploaded.push(info),
+            Err(e) => summary
+                .failed
+                .push((file.display().to_string(), format!("{:#}", e))),
+        }
+    }
+    if summary.failed.is_empty() {
+        overall.finish();
+    } else {
+        overall.abandon();
+    }
+    Ok(summary)
+}
+
+/// Recursively gathers regular files. Symlinks are skipped unless
+/// `follow_symlinks` is set; `visited` holds the canonical directories seen
+/// so far, so a link back up the tree isn't followed forever. Empty
+/// directories contribute nothing.
+pub(crate) fn collect_files(
+    dir: &Path,
+    follow_symlinks: bool,
+    visited: &mut std::collections::HashSet<PathBuf>,
+    files: &mut Vec<PathBuf>,
+) -> Result<()> {
+    if !visited.insert(fs::canonicalize(dir)?) {
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let mut file_type = fs::symlink_metadata(&path)?.file_type();
+        if file_type.is_symlink() && follow_symlinks {
+            match fs::metadata(&path) {
+                Ok(target) => file_type = target.file_type(),
+                // A dangling link has nothing to upload
+                Err(_) => continue,
+            }
+        }
+        if file_type.is_dir() {
+            collect_files(&path, follow_symlinks, visited, files)?;
+        } else if file_type.is_file() {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn upload_multipart(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    path: &Path,
+    attributes: &ObjectAttributes,
+    size: u64,
+    part_size: u64,
+    progress: Option<&ProgressBar>,
+    units: Units,
+    concurrency: usize,
+    max_retries: u32,
+    unsigned_payload: bool,
+    retry_budget: Option<Duration>,
+    state_path: Option<&Path>,
+    restart: bool,
+    verbose: bool,
+) -> Result<Option<ServerSideEncryption>> {
+    let modified = modified_secs(path);
+    let sha256 = hex(&attributes.sha256);
+    let mut saved = state_path.and_then(|p| UploadState::load(p, verbose));
+
+    // A sidecar left by a different file or part layout can't be resumed
+    if let Some(state) = &saved {
+        if restart || !state.matches(bucket, key, size, part_size, modified, &sha256) {
+            if verbose {
+                elog!("  Discarding saved multipart upload {}", state.upload_id);
+            }
+            abort_upload(client, &state.bucket, &state.key, &state.upload_id).await;
+            saved = None;
+        }
+    }
+
+    let mut state = match saved {
+        Some(mut state) => match uploaded_parts(client, bucket, key, &state.upload_id).await {
+            Ok(present) => {
+                // Only trust parts the backend still has with the same ETag
+                state
+                    .parts
+                    .retain(|p| present.get(&p.part_number) == Some(&p.etag));
+                if verbose {
+                    elog!(
+                        "  Resuming multipart upload {} ({} part(s) already uploaded)",
+                        state.upload_id,
+                        state.parts.len()
+                    );
+                }
+                Some(state)
+            }
+            Err(e) if e.code() == Some("NoSuchUpload") => {
+                if verbose {
+                    elog!(
+                        "  Saved multipart upload {} no longer exists, starting over",
+                        state.upload_id
+                    );
+                }
+                None
+            }
+            Err(e) => return Err(StorageError::from(e).into()),
+        },
+        None => None,
+    };
+
+    if state.is_none() {
+        let created = attributes
+            .apply_create(client.create_multipart_upload())
+            .bucket(bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(StorageError::from)?;
+        let upload_id = created
+            .upload_id()
+            .ok_or_else(|| {
+                StorageError::backend(format!(
+                    "Multipart upload for {} returned no upload id",
+                    key
+                ))
+            })?
+            .to_string();
+        if verbose {
+            elog!("  Started multipart upload {}", upload_id);
+        }
+        state = Some(UploadState {
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            upload_id,
+            size,
+            part_size,
+            modified,
+            sha256: Some(sha256),
+            parts: Vec::new(),
+        });
+    }
+    let mut state = state.expect("multipart state was just set");
+    let upload_id = state.upload_id.clone();
+
+    let target = MultipartTarget {
+        client,
+        bucket,
+        key,
+        upload_id: &upload_id,
+        path,
+        progress,
+        units,
+        concurrency,
+        max_retries,
+        unsigned_payload,
+        retry_budget,
+    };
+
+    let result = async {
+        if let Some(p) = state_path {
+            state.save(p)?;
+        }
+        let parts = upload_parts(&target, &mut state, state_path, verbose).await?;
+        complete_with_retry(&target, parts, verbose).await
+    }
+    .await;
+
+    match result {
+        Ok(applied) => {
+            if let Some(p) = state_path {
+                UploadState::remove(p);
+            }
+            Ok(applied)
+        }
+        Err(e) if state_path.is_some_and(Path::exists) => {
+            elog!(
+                "⚠️ Multipart upload {} was kept; run the same upload again to resume it, or add --restart to start over",
+                upload_id
+            );
+            Err(e)
+        }
+        Err(e) => {
+            // Don't leave orphaned parts accruing storage charges
+            if verbose {
+                elog!("  Aborting multipart upload {}", upload_id);
+            }
+            abort_upload(client, bucket, key, &upload_id).await;
+            Err(e)
+        }
+    }
+}
+
+async fn abort_upload(client: &Client, bucket: &str, key: &str, upload_id: &str) {
+    if let Err(abort_err) = client
+        .abort_multipart_upload()
+        .bucket(bucket)
+        .key(key)
+        .upload_id(upload_id)
+        .send()
+        .await
+    {
+        // Already gone is as good as aborted
+        if abort_err.code() != Some("NoSuchUpload") {
+            elog!(
+                "❌ Failed to abort multipart upload {}: {:?}",
+                upload_id,
+                abort_err
+            );
+        }
+    }
+}
+
+/// The parts the backend holds for an upload, by part number, with their ETags.
+async fn uploaded_parts(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    upload_id: &str,
+) -> Result<HashMap<i32, Option<String>>, SdkError<ListPartsError, HttpResponse>> {
+    let mut present = HashMap::new();
+    let mut pages = client
+        .list_parts()
+        .bucket(bucket)
+        .key(key)
+        .upload_id(upload_id)
+        .into_paginator()
+        .send();
+    while let Some(page) = pages.next().await {
+        for part in page?.parts() {
+            if let Some(number) = part.part_number() {
+                present.insert(number, part.e_tag().map(str::to_string));
+            }
+        }
+    }
+    Ok(present)
+}
+
+/// Directory, relative to the working directory, holding resumable upload state.
+const STATE_DIR: &str = ".s3upload-state";
+
+/// The sidecar recording progress of a resumable upload to `key`. The key is
+/// percent-encoded so it maps to a single file name.
+fn state_file_path(key: &str) -> PathBuf {
+    Path::new(STATE_DIR).join(format!("{}.json", urlencoding::encode(key)))
+}
+
+/// Seconds since the epoch the file was last modified, to spot a source
+/// file that changed between attempts.
+fn modified_secs(path: &Path) -> Option<u64> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_secs())
+}
+
+/// Progress of a multipart upload, saved after every part so an interrupted
+/// upload can skip the parts already sent.
+#[derive(Debug, Serialize, Deserialize)]
+struct UploadState {
+    bucket: String,
+    key: String,
+    upload_id: String,
+    size: u64,
+    part_size: u64,
+    modified: Option<u64>,
+    /// Hex SHA-256 of the file; absent in sidecars from before uploads were
+    /// checksummed, whose uploads can't take checksummed parts
+    #[serde(default)]
+    sha256: Option<String>,
+    parts: Vec<SavedPart>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SavedPart {
+    part_number: i32,
+    etag: Option<String>,
+    #[serde(default)]
+    checksum_sha256: Option<String>,
+}
+
+impl UploadState {
+    /// Reads a sidecar, treating a missing or unreadable one as absent.
+    fn load(path: &Path, verbose: bool) -> Option<UploadState> {
+        let contents = fs::read_to_string(path).ok()?;
+        match serde_json::from_str(&contents) {
+            Ok(state) => Some(state),
+            Err(e) => {
+                if verbose {
+                    elog!(
+                        "  Ignoring unreadable upload state {}: {}",
+                        path.display(),
+                        e
+                    );
+                }
+                None
+            }
+        }
+    }
+
+    fn matches(
+        &self,
+        bucket: &str,
+        key: &str,
+        size: u64,
+        part_size: u64,
+        modified: Option<u64>,
+        sha256: &str,
+    ) -> bool {
+        self.bucket == bucket
+            && self.key == key
+            && self.size == size
+            && self.part_size == part_size
+            && self.modified == modified
+            && self.sha256.as_deref() == Some(sha256)
+    }
+
+    fn completed(&self, part_number: i32) -> Option<CompletedPart> {
+        let part = self.parts.iter().find(|p| p.part_number == part_number)?;
+        Some(
+            CompletedPart::builder()
+                .part_number(part_number)
+                .set_e_tag(part.etag.clone())
+                .set_checksum_sha256(part.checksum_sha256.clone())
+                .build(),
+        )
+    }
+
+    /// Writes the sidecar through a temporary file, so a crash mid-write
+    /// can't leave it truncated.
+    fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let temp = path.with_extension("json.tmp");
+        fs::write(&temp, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(&temp, path)
+            .with_context(|| format!("Failed to save upload state to {}", path.display()))
+    }
+
+    /// Deletes the sidecar, and the state directory once it's empty.
+    fn remove(path: &Path) {
+        let _ = fs::remove_file(path);
+        if let Some(parent) = path.parent() {
+            let _ = fs::remove_dir(parent);
+        }
+    }
+}
+
+/// Config override for requests `with_retry` or `complete_with_retry`
+/// already repeat, so the client's own retries don't multiply their attempts.
+fn without_sdk_retries() -> aws_sdk_s3::config::Builder {
+    aws_sdk_s3::config::Builder::default().retry_config(RetryConfig::disabled())
+}
+
+/// Attempts at completing the upload once every part is in place.
+const COMPLETE_ATTEMPTS: u32 = 3;
+
+/// Completes the upload, retrying separately from the part retries: some
+/// gateways briefly reject completion right after the last part lands.
+/// Returns the server-side encryption the object was stored with.
+async fn complete_with_retry(
+    target: &MultipartTarget<'_>,
+    parts: Vec<CompletedPart>,
+    verbose: bool,
+) -> Result<Option<ServerSideEncryption>> {
+    verify_parts_present(target, &parts).await?;
+
+    let upload = CompletedMultipartUpload::builder()
+        .set_parts(Some(parts))
+        .build();
+    let mut attempt = 1;
+    loop {
+        let result = target
+            .client
+            .complete_multipart_upload()
+            .bucket(target.bucket)
+            .key(target.key)
+            .upload_id(target.upload_id)
+            .multipart_upload(upload.clone())
+            .customize()
+            .config_override(without_sdk_retries())
+            .send()
+            .await;
+
+        match result {
+            Ok(output) => return Ok(output.server_side_encryption().cloned()),
+            Err(e)
+                if attempt < COMPLETE_ATTEMPTS
+                    && !retry_budget_spent(target.retry_budget)
+                    && (is_retryable(&e) || e.code() == Some("InvalidPart")) =>
+            {
+                if verbose {
+                    elog!(
+                        "  ⚠️ Completing upload failed (attempt {}/{}): {}",
+                        attempt,
+                        COMPLETE_ATTEMPTS,
+                        e
+                    );
+                }
+                let delay = retry_delay(&e, Duration::from_secs(1));
+                tokio::time::sleep(delay).await;
+                charge_retry(delay);
+                attempt += 1;
+            }
+            Err(e) => return Err(StorageError::from(e).into()),
+        }
+    }
+}
+
+/// Checks the backend has every part we uploaded before asking it to
+/// assemble them.
+async fn verify_parts_present(target: &MultipartTarget<'_>, parts: &[CompletedPart]) -> Result<()> {
+    let mut present = std::collections::HashSet::new();
+    let mut pages = target
+        .client
+        .list_parts()
+        .bucket(target.bucket)
+        .key(target.key)
+        .upload_id(target.upload_id)
+        .into_paginator()
+        .send();
+    while let Some(page) = pages.next().await {
+        present.extend(
+            page.map_err(StorageError::from)?
+                .parts()
+                .iter()
+                .filter_map(|p| p.part_number()),
+        );
+    }
+
+    let missing: Vec<i32> = parts
+        .iter()
+        .filter_map(|p| p.part_number())
+        .filter(|n| !present.contains(n))
+        .collect();
+    if !missing.is_empty() {
+        bail!(StorageError::backend(format!(
+            "Backend is missing uploaded part(s) {:?}",
+            missing
+        )));
+    }
+    Ok(())
+}
+
+/// Everything needed to address the parts of one multipart upload.
+struct MultipartTarget<'a> {
+    client: &'a Client,
+    bucket: &'a str,
+    key: &'a str,
+    upload_id: &'a str,
+    /// File the parts are read from (`-` for stdin, whose parts are sent
+    /// from memory)
+    path: &'a Path,
+    progress: Option<&'a ProgressBar>,
+    units: Units,
+    /// Parts uploaded at once
+    concurrency: usize,
+    /// Times a failed part is retried before the upload gives up
+    max_retries: u32,
+    /// Send parts with `UNSIGNED-PAYLOAD` instead of signing their bodies
+    unsigned_payload: bool,
+    /// Time retries may take across the run, after which failures are final
+    retry_budget: Option<Duration>,
+}
+
+/// Uploads the parts not already in `state`, up to `target.concurrency` at
+/// once. Parts finish in any order; the result is sorted by part number as
+/// `complete_multipart_upload` requires.
+async fn upload_parts(
+    target: &MultipartTarget<'_>,
+    state: &mut UploadState,
+    state_path: Option<&Path>,
+    verbose: bool,
+) -> Result<Vec<CompletedPart>> {
+    let (size, part_size) = (state.size, state.part_size);
+    let part_count = size.div_ceil(part_size).max(1);
+    let mut parts = Vec::with_capacity(part_count as usize);
+    let mut pending = Vec::new();
+
+    for index in 0..part_count {
+        let offset = index * part_size;
+        let length = part_size.min(size - offset);
+        let part_number = index as i32 + 1;
+
+        match state.completed(part_number) {
+            Some(part) => {
+                if let Some(pb) = target.progress {
+                    pb.inc(length);
+                }
+                if verbose {
+                    elog!(
+                        "  Skipped part {}/{} (already uploaded)",
+                        part_number,
+                        part_count
+                    );
+                }
+                parts.push(part);
+            }
+            None => pending.push((part_number, offset, length)),
+        }
+    }
+
+    // Dropping the stream on the first error cancels the parts still in flight
+    let mut uploads = futures::stream::iter(pending)
+        .map(|(part_number, offset, length)| async move {
+            // Retried on its own so one flaky part doesn't restart the others
+            let part = with_retry(
+                target.max_retries,
+                target.retry_budget,
+                &format!("Part {}", part_number),
+                verbose,
+                || upload_part(target, part_number, offset, length),
+            )
+            .await
+            .map_err(StorageError::from)
+            .with_context(|| {
+                format!(
+                    "Part {} (bytes {}-{}) failed",
+                    part_number,
+                    offset,
+                    (offset + length).saturating_sub(1)
+                )
+            })?;
+            anyhow::Ok((part, length))
+        })
+        .buffer_unordered(target.concurrency.max(1));
+
+    while let Some(uploaded) = uploads.next().await {
+        let (part, length) = uploaded?;
+        let part_number = part.part_number().unwrap_or_default();
+
+        if let Some(pb) = target.progress {
+            pb.inc(length);
+        }
+        if verbose {
+            elog!(
+                "  Uploaded part {}/{} ({})",
+                part_number,
+                part_count,
+                format_size(length, target.units)
+            );
+        }
+
+        if let Some(p) = state_path {
+            state.parts.push(SavedPart {
+                part_number,
+                etag: part.e_tag().map(str::to_string),
+                checksum_sha256: part.checksum_sha256().map(str::to_string),
+            });
+            state.save(p)?;
+        }
+        parts.push(part);
+    }
+
+    parts.sort_by_key(|p| p.part_number());
+    Ok(parts)
+}
+
+async fn upload_part(
+    target: &MultipartTarget<'_>,
+    part_number: i32,
+    offset: u64,
+    length: u64,
+) -> Result<CompletedPart, SdkError<UploadPartError, HttpResponse>> {
+    // The body is rebuilt on every attempt since a sent stream can't be
+    // replayed; failing to read it isn't worth retrying
+    let body = ByteStream::read_from()
+        .path(target.path)
+        .offset(offset)
+        .length(Length::Exact(length))
+        .build()
+        .await
+        .map_err(SdkError::construction_failure)?;
+    send_part(target, part_number, body).await
+}
+
+async fn send_part(
+    target: &MultipartTarget<'_>,
+    part_number: i32,
+    body: ByteStream,
+) -> Result<CompletedPart, SdkError<UploadPartError, HttpResponse>> {
+    let request = target
+        .client
+        .upload_part()
+        .bucket(target.bucket)
+        .key(target.key)
+        .upload_id(target.upload_id)
+        .part_number(part_number)
+        .checksum_algorithm(ChecksumAlgorithm::Sha256)
+        .body(body)
+        .customize()
+        .config_override(without_sdk_retries());
+    let uploaded = if target.unsigned_payload {
+        request.disable_payload_signing().send().await?
+    } else {
+        request.send().await?
+    };
+
+    Ok(CompletedPart::builder()
+        .part_number(part_number)
+        .set_e_tag(uploaded.e_tag().map(str::to_string))
+        .set_checksum_sha256(uploaded.checksum_sha256().map(str::to_string))
+        .build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::s3_client::stub_client;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    /// A client whose `upload_part` fails with 503 `failures` times, then
+    /// succeeds, and a count of the requests it received.
+    fn flaky_client(max_retries: u32, failures: usize) -> (Client, Arc<AtomicUsize>) {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let seen = attempts.clone();
+        let client = stub_client(max_retries, move |_request| {
+            let status = if seen.fetch_add(1, Ordering::SeqCst) < failures {
+                503
+            } else {
+                200
+            };
+            http::Response::builder()
+                .status(status)
+                .header("ETag", "\"part-etag\"")
+                .body(String::new())
+                .unwrap()
+        });
+        (client, attempts)
+    }
+
+    fn target<'a>(client: &'a Client, max_retries: u32) -> MultipartTarget<'a> {
+        MultipartTarget {
+            client,
+            bucket: "bucket",
+            key: "key",
+            upload_id: "upload-id",
+            path: Path::new("-"),
+            progress: None,
+            units: Units::Binary,
+            concurrency: 1,
+            max_retries,
+            unsigned_payload: false,
+            retry_budget: None,
+        }
+    }
+
+    async fn send_with_retry(
+        target: &MultipartTarget<'_>,
+    ) -> Result<CompletedPart, SdkError<UploadPartError, HttpResponse>> {
+        with_retry(target.max_retries, None, "Part 1", false, || {
+            send_part(target, 1, ByteStream::from_static(b"part data"))
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn part_succeeds_after_two_failures() {
+        let (client, attempts) = flaky_client(3, 2);
+        let part = send_with_retry(&target(&client, 3)).await.unwrap();
+        assert_eq!(part.e_tag(), Some("\"part-etag\""));
+        assert_eq!(part.part_number(), Some(1));
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn part_retries_are_not_multiplied_by_sdk_retries() {
+        let (client, attempts) = flaky_client(1, usize::MAX);
+        let err = send_with_retry(&target(&client, 1)).await.unwrap_err();
+        assert_eq!(err.raw_response().map(|r| r.status().as_u16()), Some(503));
+        // One attempt and one retry, not (1 + 1)² with the client's retries
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
+
+    /// What a fake multipart backend received.
+    #[derive(Default)]
+    struct Received {
+        /// `(part number, bytes)` of each uploaded part
+        parts: Vec<(i32, usize)>,
+        /// Body of the `CompleteMultipartUpload` request
+        completion: Option<String>,
+    }
+
+    /// A client for a backend that accepts parts and lists them back, except
+    /// those in `lost`, as if they never arrived.
+    fn multipart_client(lost: &'static [i32]) -> (Client, Arc<Mutex<Received>>) {
+        let received = Arc::new(Mutex::new(Received::default()));
+        let seen = received.clone();
+        let client = stub_client(0, move |request| {
+            let query = request.uri().query().unwrap_or_default().to_string();
+            let param = |name: &str| {
+                query
+                    .split('&')
+                    .find_map(|pair| pair.strip_prefix(&format!("{}=", name)))
+                    .map(str::to_string)
+            };
+            let body = request
+                .body()
+                .bytes()
+                .map(<[u8]>::to_vec)
+                .unwrap_or_default();
+            let mut seen = seen.lock().unwrap();
+            let response = http::Response::builder().status(200);
+            match (request.method().as_str(), param("partNumber")) {
+                ("PUT", Some(number)) => {
+                    let number: i32 = number.parse().unwrap();
+                    seen.parts.push((number, body.len()));
+                    response
+                        .header("ETag", format!("\"etag-{}\"", number))
+                        .body(String::new())
+                }
+                ("GET", None) => {
+                    let listed: String = seen
+                        .parts
+                        .iter()
+                        .filter(|(number, _)| !lost.contains(number))
+                        .map(|(number, size)| {
+                            format!(
+                                "<Part><PartNumber>{}</PartNumber><ETag>\"etag-{}\"</ETag><Size>{}</Size></Part>",
+                                number, number, size
+                            )
+                        })
+                        .collect();
+                    response.body(format!(
+                        "<ListPartsResult><Bucket>bucket</Bucket><Key>key</Key><UploadId>upload-id</UploadId><IsTruncated>false</IsTruncated>{}</ListPartsResult>",
+                        listed
+                    ))
+                }
+                ("POST", None) => {
+                    seen.completion = Some(String::from_utf8(body).unwrap());
+                    response.body(
+                        "<CompleteMultipartUploadResult><Bucket>bucket</Bucket><Key>key</Key><ETag>\"done\"</ETag></CompleteMultipartUploadResult>"
+                            .to_string(),
+                    )
+                }
+                (method, _) => panic!("unexpected {} ?{}", method, query),
+            }
+            .unwrap()
+        });
+        (client, received)
+    }
+
+    const STREAM_SIZE: usize = 50 * 1024 * 1024;
+
+    fn stream_data() -> Vec<u8> {
+        (0..STREAM_SIZE).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[tokio::test]
+    async fn streamed_upload_completes_with_every_part() {
+        let (client, received) = multipart_client(&[]);
+        let target = MultipartTarget {
+            concurrency: 4,
+            ..target(&client, 0)
+        };
+        let data = stream_data();
+        let (parts, total) =
+            upload_stream_parts(&target, data.as_slice(), DEFAULT_PART_SIZE, None, false)
+                .await
+                .unwrap();
+        assert_eq!(total, STREAM_SIZE as u64);
+        // 50 MiB in 8 MiB parts: six full parts and a 2 MiB tail
+        let numbers: Vec<i32> = parts.iter().filter_map(|p| p.part_number()).collect();
+        assert_eq!(numbers, (1..=7).collect::<Vec<_>>());
+        for part in &parts {
+            let number = part.part_number().unwrap();
+            assert_eq!(part.e_tag(), Some(format!("\"etag-{}\"", number).as_str()));
+        }
+
+        complete_with_retry(&target, parts, false).await.unwrap();
+
+        let received = received.lock().unwrap();
+        let mut sent = received.parts.clone();
+        sent.sort();
+        let sizes: Vec<usize> = sent.iter().map(|&(_, size)| size).collect();
+        assert_eq!(sizes[..6], [DEFAULT_PART_SIZE as usize; 6]);
+        assert_eq!(sizes.iter().sum::<usize>(), STREAM_SIZE);
+        // The completion lists every part, in order, with its own ETag
+        let expected: String = (1..=7)
+            .map(|n| {
+                format!(
+                    "<Part><ETag>&quot;etag-{}&quot;</ETag><PartNumber>{}</PartNumber></Part>",
+                    n, n
+                )
+            })
+            .collect();
+        let completion = received.completion.as_deref().unwrap();
+        assert!(completion.contains(&expected), "{}", completion);
+    }
+
+    #[tokio::test]
+    async fn completion_refused_when_a_part_is_missing() {
+        let (client, received) = multipart_client(&[3]);
+        let target = target(&client, 0);
+        let data = stream_data();
+        let (parts, _) =
+            upload_stream_parts(&target, data.as_slice(), DEFAULT_PART_SIZE, None, false)
+                .await
+                .unwrap();
+
+        let err = complete_with_retry(&target, parts, false)
+            .await
+            .unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("missing uploaded part(s) [3]"),
+            "{:#}",
+            err
+        );
+        assert!(received.lock().unwrap().completion.is_none());
+    }
+}
@@ -0,0 +1,323 @@
+use crate::{
+    commands::upload::{upload_file, UploadInfo, UploadOptions},
+    config::StorageConfig,
+    error::StorageError,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use futures::StreamExt;
+use reqwest::{redirect::Policy, Url};
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use tokio::io::AsyncWriteExt;
+
+#[derive(Debug, Clone)]
+pub struct UrlUploadOptions {
+    /// Object key, relative to the default prefix (defaults to the URL's file name)
+    pub key: Option<String>,
+    /// Redirects followed before giving up
+    pub max_redirects: usize,
+}
+
+impl Default for UrlUploadOptions {
+    fn default() -> Self {
+        UrlUploadOptions {
+            key: None,
+            max_redirects: 5,
+        }
+    }
+}
+
+/// Fetches `url` and uploads the response body.
+///
+/// Only http and https URLs are fetched, and every hop, redirects included,
+/// must resolve to public addresses only. The connection is pinned to the
+/// addresses that were checked, so a second DNS answer can't point it
+/// somewhere private. The body is streamed to a temporary file, subject to
+/// the upload size limit, and then uploaded like a local file.
+pub async fn upload_url(
+    url: &str,
+    options: &UrlUploadOptions,
+    config: &StorageConfig,
+    verbose: bool,
+) -> Result<UploadInfo> {
+    let mut url = Url::parse(url).with_context(|| format!("Invalid URL: {}", url))?;
+    let mut visited = HashSet::new();
+
+    let response = loop {
+        let addrs = check_target(&url).await?;
+        if !visited.insert(url.clone()) {
+            bail!("Redirect loop at {}", url);
+        }
+        if verbose {
+            elog!("🌐 Fetching {}", url);
+        }
+
+        let host = url.host_str().unwrap_or_default().to_string();
+        // A proxy would resolve the host itself, skipping the check above
+        let client = reqwest::Client::builder()
+            .redirect(Policy::none())
+            .no_proxy()
+            .resolve_to_addrs(&host, &addrs)
+            .build()?;
+        let response = client.get(url.clone()).send().await?;
+
+        if !response.status().is_redirection() {
+            break response;
+        }
+        let location = response
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|l| l.to_str().ok())
+            .ok_or_else(|| anyhow!("{} redirected without a Location header", url))?;
+        let next = url
+            .join(location)
+            .with_context(|| format!("Invalid redirect target: {}", location))?;
+        if visited.len() > options.max_redirects {
+            bail!(
+                "Too many redirects fetching {} (limit {})",
+                url,
+                options.max_redirects
+            );
+        }
+        if verbose {
+            elog!("  ↪ {} redirected to {}", response.status(), next);
+        }
+        url = next;
+    };
+
+    if !response.status().is_success() {
+        bail!("Fetching {} failed: HTTP {}", url, response.status());
+    }
+
+    let file_name = url_file_name(&url);
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|ct| ct.to_str().ok())
+        .map(str::to_string);
+
+    let dir = std::env::temp_dir().join(format!("s3-uploader-url-{}", std::process::id()));
+    tokio::fs::create_dir_all(&dir).await?;
+    let path = dir.join(&file_name);
+
+    let result = async {
+        download_body(response, &path, config.size_limit()).await?;
+        let upload_options = UploadOptions {
+            key: Some(options.key.clone().unwrap_or(file_name)),
+            content_type,
+            ..Default::default()
+        };
+        upload_file(&path.to_string_lossy(), config, verbose, &upload_options).await
+    }
+    .await;
+
+    let _ = tokio::fs::remove_file(&path).await;
+    let _ = tokio::fs::remove_dir(&dir).await;
+    result
+}
+
+/// Rejects URLs that aren't http(s) or whose host resolves to anything
+/// other than public addresses, returning the addresses to connect to.
+async fn check_target(url: &Url) -> Result<Vec<SocketAddr>> {
+    if !matches!(url.scheme(), "http" | "https") {
+        bail!(
+            "Refusing to fetch {}: only http and https URLs are allowed",
+            url
+        );
+    }
+    let host = url
+        .host_str()
+        .ok_or_else(|| anyhow!("{} has no host", url))?;
+    let port = url
+        .port_or_known_default()
+        .ok_or_else(|| anyhow!("{} has no port", url))?;
+
+    // IPv6 literals come back bracketed
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .with_context(|| format!("Cannot resolve {}", host))?
+        .collect();
+    if addrs.is_empty() {
+        bail!("{} did not resolve to any address", host);
+    }
+    if let Some(addr) = addrs.iter().find(|a| !is_public(a.ip())) {
+        bail!(
+            "Refusing to fetch {}: {} resolves to non-public address {}",
+            url,
+            host,
+            addr.ip()
+        );
+    }
+    Ok(addrs)
+}
+
+/// Whether `ip` is a globally routable address, excluding loopback,
+/// private, link-local, shared (CGNAT), benchmarking, reserved, multicast
+/// and unspecified ranges. IPv6 addresses that embed an IPv4 address
+/// (mapped, compatible, NAT64, 6to4) are judged by that address.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, c, _] = v4.octets();
+            !(v4.is_loopback()
+                || v4.is_private()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || v4.is_multicast()
+                || v4.is_documentation()
+                || a == 0
+                || a >= 240
+                || (a == 100 && (64..128).contains(&b))
+                || (a == 198 && (b & 0xfe) == 18)
+                || (a == 192 && b == 0 && c == 0))
+        }
+        IpAddr::V6(v6) => {
+            if let Some(v4) = embedded_v4(v6) {
+                return is_public(IpAddr::V4(v4));
+            }
+            let [first, second, ..] = v6.segments();
+            !(v6.is_loopback()
+                || v6.is_unspecified()
+                || v6.is_multicast()
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80
+                || (first & 0xffc0) == 0xfec0
+                || (first == 0x2001 && second == 0x0db8)
+                // Local-use NAT64 (64:ff9b:1::/48) can reach anything
+                || (first == 0x64 && second == 0xff9b))
+        }
+    }
+}
+
+/// The IPv4 address carried by an IPv4-mapped (`::ffff:a.b.c.d`),
+/// IPv4-compatible (`::a.b.c.d`), NAT64 (`64:ff9b::/96`) or 6to4
+/// (`2002::/16`) address.
+fn embedded_v4(v6: Ipv6Addr) -> Option<Ipv4Addr> {
+    let segments = v6.segments();
+    let from_halves =
+        |hi: u16, lo: u16| Ipv4Addr::new((hi >> 8) as u8, hi as u8, (lo >> 8) as u8, lo as u8);
+    match segments {
+        [0, 0, 0, 0, 0, 0xffff, hi, lo] | [0, 0, 0, 0, 0, 0, hi, lo] => Some(from_halves(hi, lo)),
+        [0x64, 0xff9b, 0, 0, 0, 0, hi, lo] => Some(from_halves(hi, lo)),
+        [0x2002, hi, lo, ..] => Some(from_halves(hi, lo)),
+        _ => None,
+    }
+}
+
+/// The last path segment of `url`, decoded, or `download` if there isn't one.
+fn url_file_name(url: &Url) -> String {
+    url.path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .and_then(|s| urlencoding::decode(s).ok())
+        .and_then(|s| {
+            Path::new(s.as_ref())
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+        })
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| "download".to_string())
+}
+
+/// Streams the response body to `path`, stopping as soon as it passes `limit`.
+async fn download_body(response: reqwest::Response, path: &Path, limit: Option<u64>) -> Result<()> {
+    if let (Some(limit), Some(size)) = (limit, response.content_length()) {
+        if size > limit {
+            return Err(StorageError::FileTooLarge { size, limit }.into());
+        }
+    }
+
+    let mut file = tokio::fs::File::create(path).await?;
+    let mut written = 0u64;
+    let mut body = response.bytes_stream();
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk?;
+        written += chunk.len() as u64;
+        if let Some(limit) = limit.filter(|l| written > *l) {
+            return Err(StorageError::FileTooLarge {
+                size: written,
+                limit,
+            }
+            .into());
+        }
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn public(ip: &str) -> bool {
+        is_public(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn public_addresses_are_allowed() {
+        for ip in [
+            "93.184.216.34",
+            "8.8.8.8",
+            "2606:2800:220:1::1",
+            "2002:5db8:d822::1",
+        ] {
+            assert!(public(ip), "{} refused", ip);
+        }
+    }
+
+    #[test]
+    fn internal_ipv4_ranges_are_refused() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "198.18.0.1",
+            "198.19.255.255",
+            "240.0.0.1",
+            "255.255.255.255",
+            "224.0.0.1",
+            "192.0.0.8",
+        ] {
+            assert!(!public(ip), "{} allowed", ip);
+        }
+    }
+
+    #[test]
+    fn internal_ipv6_ranges_are_refused() {
+        for ip in [
+            "::1",
+            "::",
+            "fc00::1",
+            "fe80::1",
+            "fec0::1",
+            "ff02::1",
+            "2001:db8::1",
+            "64:ff9b:1::a00:1",
+        ] {
+            assert!(!public(ip), "{} allowed", ip);
+        }
+    }
+
+    #[test]
+    fn embedded_ipv4_addresses_are_judged_by_the_ipv4_address() {
+        for ip in [
+            "::ffff:127.0.0.1",
+            "::127.0.0.1",
+            "::10.0.0.1",
+            "64:ff9b::127.0.0.1",
+            "64:ff9b::a00:1",
+            "2002:7f00:1::1",
+            "2002:a00:1::",
+        ] {
+            assert!(!public(ip), "{} allowed", ip);
+        }
+        assert!(public("64:ff9b::8.8.8.8"));
+        assert!(public("::ffff:8.8.8.8"));
+    }
+}
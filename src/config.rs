@@ -1,50 +1,588 @@
-use anyhow::{bail, Result};
-use std::env;
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+use std::{env, fmt, fs, io};
 
-#[derive(Debug, Clone)]
+use crate::error::StorageError;
+use crate::utils::{OutputFormat, ProgressMode, Units};
+
+#[derive(Clone)]
 pub struct StorageConfig {
     pub bucket: String,
     pub region: String,
     pub access_key: String,
     pub secret_key: String,
+    /// Session token for temporary (e.g. STS) credentials
+    pub session_token: Option<String>,
     pub endpoint: Option<String>,
+    /// Largest file upload accepts, in bytes; 0 disables the check
     pub max_size: u64,
+    pub default_prefix: Option<String>,
+    /// Separates "folders" in keys, `/` unless configured otherwise
+    pub delimiter: String,
+    /// `(content-type prefix, Cache-Control)` defaults for uploads
+    pub cache_rules: Vec<(String, String)>,
+    /// Check the endpoint is reachable before the first request
+    pub probe: bool,
+    /// How sizes are displayed
+    pub units: Units,
+    /// Transfers (multipart parts, batch files) run at once
+    pub concurrency: usize,
+    /// Write command results as JSON instead of text
+    pub json: bool,
+    /// Format of command results; `json` is set when this is `Json`
+    pub output_format: OutputFormat,
+    /// When transfer progress is shown
+    pub progress: ProgressMode,
+    /// Passphrase for client-side encryption
+    pub passphrase: Option<String>,
+    /// Retries of a request that failed with a transient error
+    pub max_retries: u32,
+    /// Upload bodies with `UNSIGNED-PAYLOAD` rather than signing them,
+    /// leaving their integrity to TLS
+    pub unsigned_payload: bool,
+    /// Total time retries may take over the whole run; unlimited when unset
+    pub retry_budget: Option<Duration>,
+    /// IAM role to assume through STS, using the keys above as the base
+    /// credentials
+    pub assume_role: Option<AssumeRole>,
+    /// Config file profile the settings were read from, which credential
+    /// reloads read again
+    pub profile: Option<String>,
+}
+
+/// An IAM role the S3 client acts as, e.g. for access to another account.
+#[derive(Clone)]
+pub struct AssumeRole {
+    pub role_arn: String,
+    /// Shown in CloudTrail; `s3-uploader-<unix time>` when unset
+    pub session_name: Option<String>,
+    /// Required by roles whose trust policy asks for one
+    pub external_id: Option<String>,
+}
+
+// Credentials are redacted so an accidental `{:?}` never leaks them.
+impl fmt::Debug for StorageConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StorageConfig")
+            .field("bucket", &self.bucket)
+            .field("region", &self.region)
+            .field("access_key", &"****")
+            .field("secret_key", &"****")
+            .field(
+                "session_token",
+                &self.session_token.as_ref().map(|_| "****"),
+            )
+            .field("endpoint", &self.endpoint)
+            .field("max_size", &self.max_size)
+            .field("default_prefix", &self.default_prefix)
+            .field("delimiter", &self.delimiter)
+            .field("cache_rules", &self.cache_rules)
+            .field("probe", &self.probe)
+            .field("units", &self.units)
+            .field("concurrency", &self.concurrency)
+            .field("json", &self.json)
+            .field("output_format", &self.output_format)
+            .field("progress", &self.progress)
+            .field("passphrase", &self.passphrase.as_ref().map(|_| "****"))
+            .field("max_retries", &self.max_retries)
+            .field("unsigned_payload", &self.unsigned_payload)
+            .field("retry_budget", &self.retry_budget)
+            .field(
+                "assume_role",
+                &self.assume_role.as_ref().map(|r| &r.role_arn),
+            )
+            .field("profile", &self.profile)
+            .finish()
+    }
+}
+
+const DEFAULT_MAX_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// Settings from a `[profile.<name>]` section of the config file. Each
+/// fills in for a flag and environment variable that weren't given. Not
+/// `Debug`, since it holds the secret key.
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Profile {
+    bucket: Option<String>,
+    region: Option<String>,
+    endpoint: Option<String>,
+    access_key: Option<String>,
+    secret_key: Option<String>,
+    session_token: Option<String>,
+    default_prefix: Option<String>,
+    delimiter: Option<String>,
+    max_size: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(default)]
+    profile: HashMap<String, Profile>,
+}
+
+/// Where profiles are read from: `$STORAGE_CONFIG_FILE`, otherwise
+/// `s3-storage/config.toml` under `$XDG_CONFIG_HOME` or `~/.config`.
+fn config_file_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("STORAGE_CONFIG_FILE").filter(|p| !p.is_empty()) {
+        return Some(PathBuf::from(path));
+    }
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|p| !p.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config_dir.join("s3-storage").join("config.toml"))
+}
+
+/// The profile `name` from the config file, which must exist. Without a
+/// name, the `default` profile if the file has one.
+fn load_profile(name: Option<&str>) -> Result<Profile> {
+    let missing = |path: Option<&Path>| match name {
+        Some(name) => Err(StorageError::Config(format!(
+            "Profile '{}' was requested but there is no config file{}",
+            name,
+            path.map(|p| format!(" at {}", p.display()))
+                .unwrap_or_default()
+        ))
+        .into()),
+        None => Ok(Profile::default()),
+    };
+    let Some(path) = config_file_path() else {
+        return missing(None);
+    };
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return missing(Some(&path)),
+        Err(e) => {
+            return Err(StorageError::Io(e))
+                .with_context(|| format!("Failed to read {}", path.display()))
+        }
+    };
+
+    let mut file: ConfigFile = toml::from_str(&contents).map_err(|e| {
+        // The full error quotes the offending line, which may hold a secret
+        let line = e
+            .span()
+            .map(|span| {
+                format!(
+                    " at line {}",
+                    contents[..span.start].matches('\n').count() + 1
+                )
+            })
+            .unwrap_or_default();
+        StorageError::Config(format!(
+            "Invalid config file {}{}: {}",
+            path.display(),
+            line,
+            e.message()
+        ))
+    })?;
+    match name {
+        Some(name) => file.profile.remove(name).ok_or_else(|| {
+            let mut names: Vec<&str> = file.profile.keys().map(String::as_str).collect();
+            names.sort_unstable();
+            StorageError::Config(format!(
+                "Profile '{}' not found in {} (profiles: {})",
+                name,
+                path.display(),
+                if names.is_empty() {
+                    "none".to_string()
+                } else {
+                    names.join(", ")
+                }
+            ))
+            .into()
+        }),
+        None => Ok(file.profile.remove("default").unwrap_or_default()),
+    }
+}
+
+/// What `--credentials-command` must print.
+#[derive(Deserialize)]
+struct CommandCredentials {
+    access_key: String,
+    secret_key: String,
+    session_token: Option<String>,
+}
+
+/// Runs `command` through the shell and parses the credentials it prints,
+/// so they can come from Vault, SOPS, or any other secret store. Its stderr
+/// is passed through for prompts and diagnostics.
+fn run_credentials_command(command: &str) -> Result<CommandCredentials> {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    let output = shell
+        .arg(command)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .with_context(|| format!("Failed to run credentials command `{}`", command))?;
+    if !output.status.success() {
+        bail!(StorageError::Config(format!(
+            "Credentials command `{}` failed with {}",
+            command, output.status
+        )));
+    }
+    serde_json::from_slice(&output.stdout).map_err(|e| {
+        // serde_json can quote the offending value, which may be a secret
+        let reason = if e.to_string().starts_with("missing field") {
+            e.to_string()
+        } else {
+            format!("{:?} error at line {}", e.classify(), e.line())
+        };
+        StorageError::Config(format!(
+            "Credentials command `{}` didn't print a JSON object with access_key and secret_key ({})",
+            command, reason
+        ))
+        .into()
+    })
 }
 
 impl StorageConfig {
+    /// A config for `bucket` with the given credentials and the CLI's
+    /// defaults for everything else, for use as a library. The fields are
+    /// public, so adjust any others directly.
+    pub fn new(
+        bucket: impl Into<String>,
+        region: impl Into<String>,
+        access_key: impl Into<String>,
+        secret_key: impl Into<String>,
+    ) -> Self {
+        StorageConfig {
+            bucket: bucket.into(),
+            region: region.into(),
+            access_key: access_key.into(),
+            secret_key: secret_key.into(),
+            session_token: None,
+            endpoint: None,
+            max_size: DEFAULT_MAX_SIZE,
+            default_prefix: None,
+            delimiter: crate::keys::DEFAULT_DELIMITER.to_string(),
+            cache_rules: default_cache_rules(),
+            probe: false,
+            units: Units::default(),
+            concurrency: 4,
+            json: false,
+            output_format: OutputFormat::default(),
+            progress: ProgressMode::default(),
+            passphrase: None,
+            max_retries: 3,
+            unsigned_payload: false,
+            retry_budget: None,
+            assume_role: None,
+            profile: None,
+        }
+    }
+
+    /// Builds the config from, in order of precedence, the command-line
+    /// flags, the environment, the selected config file profile, and the
+    /// defaults.
     pub fn load_from_cli(cli: &crate::cli::Cli) -> Result<Self> {
-        fn get_value(cli_value: &Option<String>, env_var: &str, default: &str) -> String {
+        fn get_value(
+            cli_value: &Option<String>,
+            env_var: &str,
+            profile_value: &Option<String>,
+            default: &str,
+        ) -> String {
             cli_value
                 .clone()
                 .or_else(|| env::var(env_var).ok())
+                .or_else(|| profile_value.clone())
                 .unwrap_or_else(|| default.to_string())
         }
 
-        let bucket = get_value(&cli.bucket, "STORAGE_BUCKET", "default-bucket");
-        let region = get_value(&cli.region, "STORAGE_REGION", "us-east-1");
-        let access_key = get_value(&cli.access_key, "STORAGE_ACCESS_KEY", "");
-        let secret_key = get_value(&cli.secret_key, "STORAGE_SECRET_KEY", "");
+        let profile_name = cli
+            .profile
+            .clone()
+            .or_else(|| env::var("STORAGE_PROFILE").ok())
+            .filter(|p| !p.is_empty());
+        let profile = load_profile(profile_name.as_deref())?;
+
+        let bucket = get_value(
+            &cli.bucket,
+            "STORAGE_BUCKET",
+            &profile.bucket,
+            "default-bucket",
+        );
+        let region = get_value(&cli.region, "STORAGE_REGION", &profile.region, "us-east-1");
+        let (access_key, secret_key, session_token) = match &cli.credentials_command {
+            Some(command) => {
+                let credentials = run_credentials_command(command)?;
+                (
+                    credentials.access_key,
+                    credentials.secret_key,
+                    credentials.session_token,
+                )
+            }
+            None => (
+                get_value(
+                    &cli.access_key,
+                    "STORAGE_ACCESS_KEY",
+                    &profile.access_key,
+                    "",
+                ),
+                get_value(
+                    &cli.secret_key,
+                    "STORAGE_SECRET_KEY",
+                    &profile.secret_key,
+                    "",
+                ),
+                cli.session_token
+                    .clone()
+                    .or_else(|| env::var("STORAGE_SESSION_TOKEN").ok())
+                    .or(profile.session_token),
+            ),
+        };
+        let session_token = session_token.filter(|t| !t.is_empty());
         let endpoint = cli
             .endpoint
             .clone()
-            .or_else(|| env::var("STORAGE_URL").ok());
+            .or_else(|| env::var("STORAGE_URL").ok())
+            .or(profile.endpoint);
+        let default_prefix = cli
+            .default_prefix
+            .clone()
+            .or_else(|| env::var("STORAGE_DEFAULT_PREFIX").ok())
+            .or(profile.default_prefix)
+            .filter(|p| !p.is_empty());
+        let delimiter = get_value(
+            &cli.delimiter,
+            "STORAGE_DELIMITER",
+            &profile.delimiter,
+            crate::keys::DEFAULT_DELIMITER,
+        );
+        crate::keys::validate_delimiter(&delimiter)?;
+        let passphrase = cli
+            .passphrase
+            .clone()
+            .or_else(|| env::var("S3_PASSPHRASE").ok())
+            .filter(|p| !p.is_empty());
 
         if access_key.is_empty() || secret_key.is_empty() {
-            bail!("Access key and secret key must be provided via parameters or environment variables");
+            bail!(StorageError::Config(
+                "Access key and secret key must be provided via parameters, environment variables, or a config file profile"
+                    .to_string()
+            ));
         }
 
-        let max_size = env::var("STORAGE_MAX_SIZE")
-            .ok()
-            .and_then(|s| s.parse::<u64>().ok())
-            .unwrap_or(1024 * 1024 * 1024);
+        let max_size = if cli.no_size_limit {
+            0
+        } else {
+            cli.max_size
+                .or_else(|| {
+                    env::var("STORAGE_MAX_SIZE")
+                        .ok()
+                        .and_then(|s| s.parse::<u64>().ok())
+                })
+                .or(profile.max_size)
+                .unwrap_or(DEFAULT_MAX_SIZE)
+        };
+
+        let output_format = if cli.json {
+            OutputFormat::Json
+        } else {
+            cli.output_format
+        };
+
+        let cache_rules = match env::var("STORAGE_CACHE_RULES") {
+            Ok(rules) => parse_cache_rules(&rules)?,
+            Err(_) => default_cache_rules(),
+        };
 
         Ok(StorageConfig {
             bucket,
             region,
             access_key,
             secret_key,
+            session_token,
             endpoint,
             max_size,
+            default_prefix,
+            delimiter,
+            cache_rules,
+            probe: cli.probe,
+            units: cli.units,
+            concurrency: cli.concurrency.max(1),
+            json: output_format == OutputFormat::Json,
+            output_format,
+            progress: cli.progress,
+            passphrase,
+            max_retries: cli.max_retries,
+            unsigned_payload: cli.unsigned_payload,
+            retry_budget: cli.retry_budget,
+            assume_role: cli.assume_role.clone().map(|role_arn| AssumeRole {
+                role_arn,
+                session_name: cli.role_session_name.clone(),
+                external_id: cli.external_id.clone(),
+            }),
+            profile: profile_name,
+        })
+    }
+
+    /// Re-reads the credentials from `.env`, the environment, and the
+    /// config file profile, in that order of precedence, returning an
+    /// updated config if they changed. Used by long-running processes whose
+    /// credentials are rotated underneath them.
+    ///
+    /// `.env` is parsed rather than loaded, so the process environment,
+    /// which other threads may be reading, is never modified.
+    pub fn with_reloaded_credentials(&self) -> Result<Option<StorageConfig>> {
+        let dotenv: HashMap<String, String> = match dotenvy::dotenv_iter() {
+            Ok(iter) => iter
+                .collect::<Result<_, _>>()
+                .context("Failed to parse .env")?,
+            Err(e) if e.not_found() => HashMap::new(),
+            Err(e) => return Err(e).context("Failed to read .env"),
+        };
+        let profile = load_profile(self.profile.as_deref())?;
+        Ok(self.with_credentials_from(&dotenv, |name| env::var(name).ok(), &profile))
+    }
+
+    fn with_credentials_from(
+        &self,
+        dotenv: &HashMap<String, String>,
+        env: impl Fn(&str) -> Option<String>,
+        profile: &Profile,
+    ) -> Option<StorageConfig> {
+        let lookup = |name: &str, from_profile: &Option<String>| {
+            dotenv
+                .get(name)
+                .cloned()
+                .or_else(|| env(name))
+                .or_else(|| from_profile.clone())
+                .filter(|v| !v.is_empty())
+        };
+        let access_key = lookup("STORAGE_ACCESS_KEY", &profile.access_key)?;
+        let secret_key = lookup("STORAGE_SECRET_KEY", &profile.secret_key)?;
+        let session_token = lookup("STORAGE_SESSION_TOKEN", &profile.session_token);
+
+        let unchanged = access_key == self.access_key
+            && secret_key == self.secret_key
+            && session_token == self.session_token;
+        (!unchanged).then(|| StorageConfig {
+            access_key,
+            secret_key,
+            session_token,
+            ..self.clone()
         })
     }
+
+    /// The upload size cap, or `None` when it is disabled.
+    pub fn size_limit(&self) -> Option<u64> {
+        (self.max_size > 0).then_some(self.max_size)
+    }
+
+    /// Default `Cache-Control` for an upload of `content_type`, if any rule matches.
+    pub fn cache_control_for(&self, content_type: &str) -> Option<&str> {
+        crate::utils::longest_prefix_match(&self.cache_rules, content_type)
+    }
+
+    /// Prepends the configured default prefix to `key`.
+    ///
+    /// The two are concatenated literally, so a default prefix meant to act
+    /// as a folder should end with `/` (e.g. `team-a/`).
+    pub fn prefixed(&self, key: &str) -> String {
+        match &self.default_prefix {
+            Some(base) => crate::keys::join_prefix(base, key),
+            None => key.to_string(),
+        }
+    }
+}
+
+/// Long-lived caching for static assets, revalidation for HTML.
+fn default_cache_rules() -> Vec<(String, String)> {
+    const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+    [
+        ("text/html", "no-cache"),
+        ("text/css", IMMUTABLE),
+        ("text/javascript", IMMUTABLE),
+        ("application/javascript", IMMUTABLE),
+        ("image/", IMMUTABLE),
+        ("font/", IMMUTABLE),
+    ]
+    .into_iter()
+    .map(|(t, v)| (t.to_string(), v.to_string()))
+    .collect()
+}
+
+/// Parses `type=value` rules separated by `;`, e.g.
+/// `text/html=no-cache;image/=public, max-age=86400`.
+fn parse_cache_rules(rules: &str) -> Result<Vec<(String, String)>> {
+    rules
+        .split(';')
+        .map(str::trim)
+        .filter(|rule| !rule.is_empty())
+        .map(|rule| match rule.split_once('=') {
+            Some((t, v)) if !t.trim().is_empty() => {
+                Ok((t.trim().to_string(), v.trim().to_string()))
+            }
+            _ => bail!(StorageError::Config(format!(
+                "Invalid STORAGE_CACHE_RULES entry: {}",
+                rule
+            ))),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debug_redacts_secrets() {
+        let mut config = StorageConfig::new("bucket", "us-east-1", "AKIDEXAMPLE", "sekrit");
+        config.session_token = Some("tokenvalue".to_string());
+        config.passphrase = Some("hunter2".to_string());
+        let debug = format!("{:?}", config);
+        let pretty = format!("{:#?}", config);
+        for secret in ["AKIDEXAMPLE", "sekrit", "tokenvalue", "hunter2"] {
+            assert!(!debug.contains(secret), "{} leaked: {}", secret, debug);
+            assert!(!pretty.contains(secret), "{} leaked: {}", secret, pretty);
+        }
+        assert!(debug.contains("bucket"));
+    }
+
+    #[test]
+    fn reloaded_credentials_prefer_dotenv_then_environment_then_profile() {
+        let config = StorageConfig::new("bucket", "us-east-1", "old-key", "old-secret");
+        let profile = Profile {
+            access_key: Some("profile-key".into()),
+            secret_key: Some("profile-secret".into()),
+            ..Profile::default()
+        };
+        let no_env = |_: &str| None;
+
+        let rotated = config
+            .with_credentials_from(&HashMap::new(), no_env, &profile)
+            .expect("profile rotation not picked up");
+        assert_eq!(rotated.access_key, "profile-key");
+        assert_eq!(rotated.secret_key, "profile-secret");
+
+        let env = |name: &str| (name == "STORAGE_ACCESS_KEY").then(|| "env-key".to_string());
+        let dotenv = HashMap::from([(
+            "STORAGE_SECRET_KEY".to_string(),
+            "dotenv-secret".to_string(),
+        )]);
+        let rotated = config
+            .with_credentials_from(&dotenv, env, &profile)
+            .unwrap();
+        assert_eq!(rotated.access_key, "env-key");
+        assert_eq!(rotated.secret_key, "dotenv-secret");
+
+        assert!(rotated
+            .with_credentials_from(&dotenv, env, &profile)
+            .is_none());
+    }
 }
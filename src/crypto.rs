@@ -0,0 +1,201 @@
+use aes_gcm::aead::stream::{DecryptorBE32, EncryptorBE32};
+use aes_gcm::aead::{rand_core::RngCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key};
+use anyhow::{anyhow, bail, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::Engine;
+use std::collections::HashMap;
+use std::io::{IsTerminal, Read, Write};
+use std::path::Path;
+
+use crate::config::StorageConfig;
+
+/// User metadata entries describing a client-side encrypted object.
+pub const ALGORITHM_METADATA: &str = "cse-algorithm";
+pub const KDF_METADATA: &str = "cse-kdf";
+pub const SALT_METADATA: &str = "cse-salt";
+pub const NONCE_METADATA: &str = "cse-nonce";
+
+/// AES-256-GCM in the STREAM construction: the plaintext is sealed in
+/// 64 KiB chunks, each with its own tag, so neither side holds the whole
+/// file and truncation or reordering is detected.
+const ALGORITHM: &str = "AES-256-GCM-STREAM-BE32-64K";
+const CHUNK_SIZE: usize = 64 * 1024;
+const TAG_SIZE: usize = 16;
+const SALT_SIZE: usize = 16;
+/// STREAM uses 5 of GCM's 12 nonce bytes for the chunk counter and last flag.
+const NONCE_PREFIX_SIZE: usize = 7;
+
+/// The passphrase from `--passphrase` or `S3_PASSPHRASE`, or else asked for
+/// on the terminal. `confirm` asks twice, for passphrases about to be used
+/// to encrypt.
+pub fn passphrase(config: &StorageConfig, confirm: bool) -> Result<String> {
+    if let Some(passphrase) = &config.passphrase {
+        return Ok(passphrase.clone());
+    }
+    if !std::io::stdin().is_terminal() {
+        bail!("A passphrase is required: pass --passphrase or set S3_PASSPHRASE");
+    }
+    let mut prompt = dialoguer::Password::new().with_prompt("Passphrase");
+    if confirm {
+        prompt = prompt.with_confirmation("Repeat passphrase", "Passphrases don't match");
+    }
+    let passphrase = prompt.interact()?;
+    if passphrase.is_empty() {
+        bail!("The passphrase must not be empty");
+    }
+    Ok(passphrase)
+}
+
+/// Whether an object's metadata marks it as client-side encrypted.
+pub fn is_encrypted(metadata: Option<&HashMap<String, String>>) -> bool {
+    metadata.is_some_and(|m| m.contains_key(ALGORITHM_METADATA))
+}
+
+/// Encrypts `source` into `dest`, returning the metadata needed to decrypt it.
+pub fn encrypt_file(
+    source: &Path,
+    dest: &Path,
+    passphrase: &str,
+) -> Result<HashMap<String, String>> {
+    let mut salt = [0u8; SALT_SIZE];
+    let mut nonce = [0u8; NONCE_PREFIX_SIZE];
+    OsRng.fill_bytes(&mut salt);
+    OsRng.fill_bytes(&mut nonce);
+
+    let cipher = Aes256Gcm::new(&derive_key(passphrase, &salt)?);
+    let mut encryptor = EncryptorBE32::from_aead(cipher, nonce.as_slice().into());
+
+    let mut input = std::fs::File::open(source)?;
+    let mut output = std::io::BufWriter::new(std::fs::File::create(dest)?);
+    // The last chunk is sealed differently, so read one ahead to spot it
+    let mut current = read_chunk(&mut input)?;
+    loop {
+        let next = read_chunk(&mut input)?;
+        if next.is_empty() {
+            let sealed = encryptor
+                .encrypt_last(current.as_slice())
+                .map_err(|_| anyhow!("Encryption failed"))?;
+            output.write_all(&sealed)?;
+            break;
+        }
+        let sealed = encryptor
+            .encrypt_next(current.as_slice())
+            .map_err(|_| anyhow!("Encryption failed"))?;
+        output.write_all(&sealed)?;
+        current = next;
+    }
+    output.flush()?;
+
+    let b64 = base64::engine::general_purpose::STANDARD;
+    Ok(HashMap::from([
+        (ALGORITHM_METADATA.to_string(), ALGORITHM.to_string()),
+        (KDF_METADATA.to_string(), kdf_description()),
+        (SALT_METADATA.to_string(), b64.encode(salt)),
+        (NONCE_METADATA.to_string(), b64.encode(nonce)),
+    ]))
+}
+
+/// Reads up to one chunk, short only at the end of the file.
+fn read_chunk(input: &mut impl Read) -> std::io::Result<Vec<u8>> {
+    let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+    input.take(CHUNK_SIZE as u64).read_to_end(&mut chunk)?;
+    Ok(chunk)
+}
+
+/// Decrypts an object's body as it arrives, in whatever pieces the network
+/// delivers.
+pub struct Decryptor {
+    inner: Option<DecryptorBE32<Aes256Gcm>>,
+    buffer: Vec<u8>,
+}
+
+impl Decryptor {
+    /// Sets up decryption from the object's metadata, failing if it was
+    /// encrypted in a way this version doesn't understand.
+    pub fn new(metadata: &HashMap<String, String>, passphrase: &str) -> Result<Self> {
+        let field = |name: &str| {
+            metadata
+                .get(name)
+                .ok_or_else(|| anyhow!("Encrypted object is missing x-amz-meta-{}", name))
+        };
+        let algorithm = field(ALGORITHM_METADATA)?;
+        if algorithm != ALGORITHM {
+            bail!("Unsupported client-side encryption '{}'", algorithm);
+        }
+        let kdf = field(KDF_METADATA)?;
+        if *kdf != kdf_description() {
+            bail!("Unsupported key derivation '{}'", kdf);
+        }
+
+        let b64 = base64::engine::general_purpose::STANDARD;
+        let salt = b64
+            .decode(field(SALT_METADATA)?)
+            .ok()
+            .filter(|s| s.len() == SALT_SIZE)
+            .ok_or_else(|| anyhow!("Malformed x-amz-meta-{}", SALT_METADATA))?;
+        let nonce = b64
+            .decode(field(NONCE_METADATA)?)
+            .ok()
+            .filter(|n| n.len() == NONCE_PREFIX_SIZE)
+            .ok_or_else(|| anyhow!("Malformed x-amz-meta-{}", NONCE_METADATA))?;
+
+        let cipher = Aes256Gcm::new(&derive_key(passphrase, &salt)?);
+        Ok(Decryptor {
+            inner: Some(DecryptorBE32::from_aead(cipher, nonce.as_slice().into())),
+            buffer: Vec::with_capacity(2 * (CHUNK_SIZE + TAG_SIZE)),
+        })
+    }
+
+    /// Takes the next piece of ciphertext and returns whatever plaintext it
+    /// completes. One sealed chunk is always held back, since only the end
+    /// of the body shows which chunk is last.
+    pub fn update(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        const SEALED: usize = CHUNK_SIZE + TAG_SIZE;
+        self.buffer.extend_from_slice(data);
+        let mut plain = Vec::new();
+        let mut start = 0;
+        while self.buffer.len() - start > SEALED {
+            let inner = self.inner.as_mut().expect("decryptor used after finish");
+            let opened = inner
+                .decrypt_next(&self.buffer[start..start + SEALED])
+                .map_err(|_| decrypt_error())?;
+            plain.extend_from_slice(&opened);
+            start += SEALED;
+        }
+        self.buffer.drain(..start);
+        Ok(plain)
+    }
+
+    /// Decrypts the final chunk, failing if the body was cut short.
+    pub fn finish(&mut self) -> Result<Vec<u8>> {
+        let inner = self.inner.take().expect("decryptor finished twice");
+        inner
+            .decrypt_last(self.buffer.as_slice())
+            .map_err(|_| decrypt_error())
+    }
+}
+
+fn decrypt_error() -> anyhow::Error {
+    anyhow!("Decryption failed: wrong passphrase, or the object is corrupt or truncated")
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Key<Aes256Gcm>> {
+    let mut key = Key::<Aes256Gcm>::default();
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, Params::default())
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow!("Key derivation failed: {}", e))?;
+    Ok(key)
+}
+
+/// The KDF and its parameters, recorded so a later change of defaults
+/// can't silently derive a different key.
+fn kdf_description() -> String {
+    let params = Params::default();
+    format!(
+        "argon2id:m={},t={},p={}",
+        params.m_cost(),
+        params.t_cost(),
+        params.p_cost()
+    )
+}
@@ -0,0 +1,210 @@
+use aws_sdk_s3::config::http::HttpResponse;
+use aws_sdk_s3::error::{DisplayErrorContext, ProvideErrorMetadata, SdkError};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum StorageError {
+    #[error("Object '{key}' not found in bucket '{bucket}'")]
+    NotFound { key: String, bucket: String },
+
+    /// A 404 from the backend, for a key, bucket or upload the failed
+    /// request doesn't name
+    #[error("Not found: {message}")]
+    Missing { message: String },
+
+    #[error("Access denied: {message}")]
+    AccessDenied { message: String },
+
+    #[error("File is {size} bytes, over the {limit}-byte upload limit")]
+    FileTooLarge { size: u64, limit: u64 },
+
+    #[error("Bucket '{bucket}' already exists and belongs to another account")]
+    BucketTaken { bucket: String },
+
+    /// A local file couldn't be read or written
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    /// The endpoint couldn't be reached, or a request got no response in time
+    #[error("{message}")]
+    Network { message: String },
+
+    /// A request the backend rejected or that never got a response;
+    /// `status` is the HTTP status when there was one
+    #[error("{message}")]
+    S3 {
+        status: Option<u16>,
+        code: Option<String>,
+        message: String,
+    },
+
+    /// Invalid settings or options, found before anything was sent
+    #[error("{0}")]
+    Config(String),
+}
+
+impl StorageError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            StorageError::Config(_) => 2,
+            StorageError::NotFound { .. } | StorageError::Missing { .. } => 3,
+            StorageError::FileTooLarge { .. } => 4,
+            StorageError::BucketTaken { .. } => 5,
+            StorageError::AccessDenied { .. } => 6,
+            StorageError::S3 { .. } => 7,
+            StorageError::Io(_) => 8,
+            StorageError::Network { .. } => 9,
+        }
+    }
+
+    /// An `S3` error that didn't come from a failed SDK call, such as a
+    /// response missing a field or content that fails a check.
+    pub fn backend(message: impl Into<String>) -> Self {
+        StorageError::S3 {
+            status: None,
+            code: None,
+            message: message.into(),
+        }
+    }
+
+    /// What the user can do about the error, if there's anything to suggest.
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            StorageError::FileTooLarge { .. } => Some(
+                "increase --max-size or set STORAGE_MAX_SIZE (0 or --no-size-limit disables the check)",
+            ),
+            StorageError::BucketTaken { .. } => {
+                Some("bucket names are shared by every account; choose a different --bucket")
+            }
+            StorageError::AccessDenied { .. } => Some(
+                "check the access key and secret key, and that their policy allows this action on the bucket",
+            ),
+            StorageError::Network { .. } => {
+                Some("check --endpoint and --region, and that this machine can reach the endpoint")
+            }
+            _ => None,
+        }
+    }
+
+    /// The `StorageError` anywhere in `err`'s chain, so one wrapped in
+    /// `.context(...)` is still found.
+    pub fn find(err: &anyhow::Error) -> Option<&StorageError> {
+        err.chain()
+            .find_map(|cause| cause.downcast_ref::<StorageError>())
+    }
+
+    /// Exit code for any error: its `StorageError`'s, or 1 for errors that
+    /// haven't been given a kind.
+    pub fn exit_code_for(err: &anyhow::Error) -> i32 {
+        StorageError::find(err).map_or(1, StorageError::exit_code)
+    }
+}
+
+impl<E> From<SdkError<E, HttpResponse>> for StorageError
+where
+    E: ProvideErrorMetadata + std::error::Error + Send + Sync + 'static,
+{
+    /// Sorts an SDK failure by its HTTP status: 403 is `AccessDenied`, 404
+    /// (or a `NoSuch...` code) is `Missing`, a request that was never
+    /// answered is `Network`, and every other response is `S3`.
+    fn from(err: SdkError<E, HttpResponse>) -> Self {
+        if matches!(
+            err,
+            SdkError::DispatchFailure(_) | SdkError::TimeoutError(_)
+        ) {
+            return StorageError::Network {
+                message: DisplayErrorContext(&err).to_string(),
+            };
+        }
+        let status = err.raw_response().map(|r| r.status().as_u16());
+        let code = err.code().map(str::to_string);
+        let message = DisplayErrorContext(&err).to_string();
+        match status {
+            // HEAD responses have no body, so no message to pass on
+            Some(403) => StorageError::AccessDenied {
+                message: err
+                    .message()
+                    .unwrap_or("the request was refused with HTTP 403")
+                    .to_string(),
+            },
+            _ if status == Some(404)
+                || matches!(
+                    code.as_deref(),
+                    Some("NoSuchKey" | "NoSuchBucket" | "NoSuchUpload")
+                ) =>
+            {
+                StorageError::Missing {
+                    message: err
+                        .message()
+                        .or(code.as_deref())
+                        .unwrap_or("the request got HTTP 404")
+                        .to_string(),
+                }
+            }
+            _ => StorageError::S3 {
+                status,
+                code,
+                message,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_s3::error::ErrorMetadata;
+    use aws_sdk_s3::operation::copy_object::CopyObjectError;
+    use aws_smithy_types::body::SdkBody;
+
+    fn sdk_error(status: u16, code: Option<&str>) -> StorageError {
+        let mut meta = ErrorMetadata::builder();
+        if let Some(code) = code {
+            meta = meta.code(code);
+        }
+        let response = http::Response::builder()
+            .status(status)
+            .body(SdkBody::empty())
+            .unwrap();
+        StorageError::from(SdkError::service_error(
+            CopyObjectError::generic(meta.build()),
+            HttpResponse::try_from(response).unwrap(),
+        ))
+    }
+
+    #[test]
+    fn sdk_errors_are_sorted_by_status() {
+        assert!(matches!(sdk_error(404, None), StorageError::Missing { .. }));
+        assert!(matches!(
+            sdk_error(404, Some("NoSuchKey")),
+            StorageError::Missing { .. }
+        ));
+        assert!(matches!(
+            sdk_error(404, Some("NoSuchBucket")),
+            StorageError::Missing { .. }
+        ));
+        assert!(matches!(
+            sdk_error(403, Some("AccessDenied")),
+            StorageError::AccessDenied { .. }
+        ));
+        assert!(matches!(
+            sdk_error(500, Some("InternalError")),
+            StorageError::S3 {
+                status: Some(500),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn not_found_errors_share_an_exit_code() {
+        assert_eq!(sdk_error(404, Some("NoSuchKey")).exit_code(), 3);
+        let named = StorageError::NotFound {
+            key: "a.txt".to_string(),
+            bucket: "bucket".to_string(),
+        };
+        assert_eq!(named.exit_code(), 3);
+        assert_eq!(sdk_error(403, None).exit_code(), 6);
+        assert_eq!(sdk_error(500, None).exit_code(), 7);
+    }
+}
@@ -0,0 +1,300 @@
+use anyhow::{anyhow, bail, Result};
+use std::path::{Component, Path};
+
+/// User metadata entry recording a file's name when its key doesn't end in it.
+///
+/// The value is percent-encoded, since metadata travels as HTTP headers.
+pub const ORIGINAL_FILENAME_METADATA: &str = "original-filename";
+
+/// User metadata entry holding the hex SHA-256 of the uploaded file.
+pub const SHA256_METADATA: &str = "sha256";
+
+/// The key delimiter unless `--delimiter` says otherwise.
+pub const DEFAULT_DELIMITER: &str = "/";
+
+/// Derives an object key from a local path.
+///
+/// With a `base`, the key is the path relative to it joined with
+/// `delimiter` (`images/logo.png`); without one, it is just the file name.
+pub fn derive_key_from_path(path: &Path, base: Option<&Path>, delimiter: &str) -> Result<String> {
+    let relative = match base {
+        Some(base) => path
+            .strip_prefix(base)
+            .map_err(|_| anyhow!("{} is not inside {}", path.display(), base.display()))?,
+        None => Path::new(
+            path.file_name()
+                .ok_or_else(|| anyhow!("{} has no file name", path.display()))?,
+        ),
+    };
+
+    let segments: Vec<_> = relative
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(s) => Some(s.to_string_lossy()),
+            _ => None,
+        })
+        .collect();
+    if segments.is_empty() {
+        bail!("Cannot derive a key from {}", path.display());
+    }
+    Ok(segments.join(delimiter))
+}
+
+/// Prepends `prefix` to `key`.
+///
+/// Prefixes are literal key prefixes: no `/` is inserted, so a folder-style
+/// prefix must carry its own trailing slash.
+pub fn join_prefix(prefix: &str, key: &str) -> String {
+    format!("{}{}", prefix, key)
+}
+
+/// Turns a prefix into a folder prefix by ensuring it ends in `delimiter`,
+/// so `logs` matches `logs/app.txt` but not `logs-old/app.txt`. Empty stays
+/// empty.
+pub fn as_dir(prefix: &str, delimiter: &str) -> String {
+    if prefix.is_empty() || prefix.ends_with(delimiter) {
+        prefix.to_string()
+    } else {
+        format!("{}{}", prefix, delimiter)
+    }
+}
+
+/// Whether `key` names a folder (or a folder marker) rather than an object.
+pub fn is_dir_key(key: &str, delimiter: &str) -> bool {
+    key.is_empty() || key.ends_with(delimiter)
+}
+
+/// The part of `key` after its last `delimiter`.
+pub fn last_segment<'a>(key: &'a str, delimiter: &str) -> &'a str {
+    key.rsplit(delimiter).next().unwrap_or(key)
+}
+
+/// Checks a `--delimiter` value: one or more characters, none of them
+/// control characters.
+pub fn validate_delimiter(delimiter: &str) -> Result<()> {
+    if delimiter.is_empty() {
+        bail!("--delimiter must not be empty");
+    }
+    if delimiter.chars().any(char::is_control) {
+        bail!(
+            "--delimiter must not contain control characters: {:?}",
+            delimiter
+        );
+    }
+    Ok(())
+}
+
+/// A `Content-Disposition` value that makes browsers save the object under
+/// the key's last segment. Non-ASCII names also get an RFC 5987
+/// `filename*`, with an ASCII fallback for older clients.
+pub fn attachment_disposition(key: &str) -> String {
+    let name = key.rsplit('/').next().unwrap_or(key);
+    let fallback: String = name
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' => '_',
+            c if c.is_ascii() && !c.is_ascii_control() => c,
+            _ => '_',
+        })
+        .collect();
+    if name.is_ascii() {
+        format!("attachment; filename=\"{}\"", fallback)
+    } else {
+        format!(
+            "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+            fallback,
+            urlencoding::encode(name)
+        )
+    }
+}
+
+/// Normalizes user-supplied key input.
+///
+/// Backslashes become `/`, leading, repeated, and `.` segments are dropped,
+/// and a trailing `/` is kept. `..` segments and control characters are
+/// rejected, as is input that normalizes to nothing.
+pub fn sanitize_key(key: &str) -> Result<String> {
+    if key.chars().any(char::is_control) {
+        bail!("Key contains control characters: {:?}", key);
+    }
+
+    let key = key.replace('\\', "/");
+    let mut segments = Vec::new();
+    for segment in key.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => bail!("Key must not contain '..' segments: {}", key),
+            s => segments.push(s),
+        }
+    }
+    if segments.is_empty() {
+        bail!("Key is empty after normalization: {:?}", key);
+    }
+
+    let mut sanitized = segments.join("/");
+    if key.ends_with('/') {
+        sanitized.push('/');
+    }
+    Ok(sanitized)
+}
+
+/// Checks a key given for a single upload names an object: it must not be
+/// empty, start with `/`, or end with `/`.
+pub fn validate_object_key(key: &str) -> Result<()> {
+    if key.trim().is_empty() {
+        bail!("--key must not be empty");
+    }
+    if key.starts_with('/') {
+        bail!("--key must not start with '/': {}", key);
+    }
+    if key.ends_with('/') {
+        bail!("--key must name an object, not a folder: {}", key);
+    }
+    Ok(())
+}
+
+/// Splits an `s3://bucket/key` URI into its bucket and (possibly empty) key.
+pub fn parse_s3_uri(uri: &str) -> Result<(String, String)> {
+    let rest = uri
+        .strip_prefix("s3://")
+        .ok_or_else(|| anyhow!("Not an s3:// URI: {}", uri))?;
+    let (bucket, key) = rest.split_once('/').unwrap_or((rest, ""));
+    if bucket.is_empty() {
+        bail!("Missing bucket in URI: {}", uri);
+    }
+    Ok((bucket.to_string(), key.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derive_key_uses_file_name_without_base() {
+        let key = |p: &str| derive_key_from_path(Path::new(p), None, "/").unwrap();
+        assert_eq!(key("docs/report.pdf"), "report.pdf");
+        assert_eq!(key("./report.pdf"), "report.pdf");
+        assert_eq!(key("../up/report.pdf"), "report.pdf");
+        assert_eq!(key("docs/report.pdf/"), "report.pdf");
+        assert_eq!(key("données/résumé.txt"), "résumé.txt");
+    }
+
+    #[test]
+    fn derive_key_keeps_structure_below_base() {
+        let key = |p: &str, base: &str, delim: &str| {
+            derive_key_from_path(Path::new(p), Some(Path::new(base)), delim).unwrap()
+        };
+        assert_eq!(key("site/images/logo.png", "site", "/"), "images/logo.png");
+        assert_eq!(key("site/images/logo.png", "site/", "/"), "images/logo.png");
+        assert_eq!(
+            key("site/./images/logo.png", "site", "/"),
+            "images/logo.png"
+        );
+        assert_eq!(key("site/images/logo.png", "site", ":"), "images:logo.png");
+        assert_eq!(
+            key("site/日本/ファイル.txt", "site", "/"),
+            "日本/ファイル.txt"
+        );
+    }
+
+    #[test]
+    fn derive_key_rejects_paths_without_a_name() {
+        assert!(derive_key_from_path(Path::new(""), None, "/").is_err());
+        assert!(derive_key_from_path(Path::new(".."), None, "/").is_err());
+        assert!(derive_key_from_path(Path::new("docs/.."), None, "/").is_err());
+        assert!(derive_key_from_path(Path::new("site"), Some(Path::new("site")), "/").is_err());
+        assert!(
+            derive_key_from_path(Path::new("other/a.txt"), Some(Path::new("site")), "/").is_err()
+        );
+    }
+
+    #[test]
+    fn join_prefix_is_literal() {
+        assert_eq!(join_prefix("uploads/", "a.txt"), "uploads/a.txt");
+        assert_eq!(join_prefix("uploads", "a.txt"), "uploadsa.txt");
+        assert_eq!(join_prefix("", "a.txt"), "a.txt");
+        assert_eq!(join_prefix("uploads/", ""), "uploads/");
+        assert_eq!(join_prefix("ünï/", "çödé.txt"), "ünï/çödé.txt");
+    }
+
+    #[test]
+    fn as_dir_adds_one_trailing_delimiter() {
+        assert_eq!(as_dir("logs", "/"), "logs/");
+        assert_eq!(as_dir("logs/", "/"), "logs/");
+        assert_eq!(as_dir("logs//", "/"), "logs//");
+        assert_eq!(as_dir("", "/"), "");
+        assert_eq!(as_dir("logs", "::"), "logs::");
+        assert_eq!(as_dir("журнал", "/"), "журнал/");
+    }
+
+    #[test]
+    fn sanitize_key_normalizes_segments() {
+        let key = |k: &str| sanitize_key(k).unwrap();
+        assert_eq!(key("a/b.txt"), "a/b.txt");
+        assert_eq!(key("/a//b.txt"), "a/b.txt");
+        assert_eq!(key("./a/./b.txt"), "a/b.txt");
+        assert_eq!(key("a\\b\\c.txt"), "a/b/c.txt");
+        assert_eq!(key("a/b/"), "a/b/");
+        assert_eq!(key("a\\"), "a/");
+        assert_eq!(key("ünï/çödé 日本.txt"), "ünï/çödé 日本.txt");
+    }
+
+    #[test]
+    fn sanitize_key_rejects_escapes_and_empty_keys() {
+        for bad in [
+            "", "/", ".", "./", "//", "a/../b", "..", "a\\..\\b", "a\nb", "a\0b",
+        ] {
+            assert!(sanitize_key(bad).is_err(), "{:?} accepted", bad);
+        }
+        // Only whole `..` segments escape
+        assert_eq!(sanitize_key("a..b/..c").unwrap(), "a..b/..c");
+    }
+
+    #[test]
+    fn validate_object_key_accepts_keys_as_given() {
+        for good in [
+            "a/b.txt",
+            "a//b.txt",
+            "a\\b.txt",
+            "./a",
+            "a/../b",
+            "日本.txt",
+        ] {
+            assert!(validate_object_key(good).is_ok(), "{:?} rejected", good);
+        }
+        for bad in ["", "  ", "/a.txt", "a/"] {
+            assert!(validate_object_key(bad).is_err(), "{:?} accepted", bad);
+        }
+    }
+
+    #[test]
+    fn parse_s3_uri_splits_bucket_and_key() {
+        let parse = |u: &str| parse_s3_uri(u).unwrap();
+        assert_eq!(
+            parse("s3://bucket/a/b.txt"),
+            ("bucket".into(), "a/b.txt".into())
+        );
+        assert_eq!(parse("s3://bucket/a/"), ("bucket".into(), "a/".into()));
+        assert_eq!(parse("s3://bucket/"), ("bucket".into(), "".into()));
+        assert_eq!(parse("s3://bucket"), ("bucket".into(), "".into()));
+        assert_eq!(parse("s3://bucket//a"), ("bucket".into(), "/a".into()));
+        assert_eq!(
+            parse("s3://bucket/ünï.txt"),
+            ("bucket".into(), "ünï.txt".into())
+        );
+    }
+
+    #[test]
+    fn parse_s3_uri_rejects_other_schemes_and_missing_bucket() {
+        for bad in [
+            "",
+            "bucket/key",
+            "S3://bucket/key",
+            "https://bucket/key",
+            "s3://",
+            "s3:///key",
+        ] {
+            assert!(parse_s3_uri(bad).is_err(), "{:?} accepted", bad);
+        }
+    }
+}
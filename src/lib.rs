@@ -0,0 +1,44 @@
+//! Upload, download, list and delete files in S3-compatible storage.
+//!
+//! The `s3-uploader` binary is a thin wrapper over this crate, so every
+//! command is also available as a function. Results are returned, or
+//! written to the `out` writer a function takes; `verbose` progress and
+//! warnings go to stderr.
+//!
+//! ```no_run
+//! use s3_uploader::{upload_file, StorageConfig, UploadOptions};
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let mut config = StorageConfig::new("my-bucket", "us-east-1", "AKIA...", "secret");
+//! config.endpoint = Some("https://s3.example.com".to_string());
+//!
+//! let options = UploadOptions {
+//!     key: Some("reports/q1.pdf".to_string()),
+//!     ..Default::default()
+//! };
+//! let info = upload_file("q1.pdf", &config, false, &options).await?;
+//! println!("{}", info.download_url);
+//! # Ok(())
+//! # }
+//! ```
+
+#[macro_use]
+pub mod log;
+
+pub mod cli;
+pub mod commands;
+pub mod config;
+pub mod crypto;
+pub mod error;
+pub mod keys;
+mod permissions;
+pub mod s3_client;
+pub mod utils;
+
+pub use commands::delete::{delete_file, DeleteOutcome};
+pub use commands::download::{download_file, DownloadOptions};
+pub use commands::list::{list_files, ListOptions};
+pub use commands::upload::{upload_file, UploadInfo, UploadOptions};
+pub use config::StorageConfig;
+pub use error::StorageError;
+pub use s3_client::create_client;
@@ -0,0 +1,62 @@
+//! Log lines for the terminal and, with `--log-file`, a file.
+//!
+//! `elog!` and `olog!` print like `eprintln!` and `println!`, and also
+//! append the line to the log file, prefixed with a UTC timestamp. The file
+//! is opened for appending, so it can be rotated by an external tool that
+//! truncates it in place (e.g. logrotate's `copytruncate`).
+
+use anyhow::{Context, Result};
+use aws_sdk_s3::primitives::{DateTime, DateTimeFormat};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+static LOG_FILE: OnceLock<Mutex<File>> = OnceLock::new();
+
+/// Sends every following log line to `path` as well. Only the first call
+/// has any effect.
+pub fn set_log_file(path: &Path) -> Result<()> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open log file {}", path.display()))?;
+    let _ = LOG_FILE.set(Mutex::new(file));
+    Ok(())
+}
+
+/// Appends `line` to the log file, if there is one. A failed write is
+/// dropped rather than failing the command it was logging.
+pub fn write_line(line: &str) {
+    let Some(file) = LOG_FILE.get() else {
+        return;
+    };
+    let now = DateTime::from(std::time::SystemTime::now())
+        .fmt(DateTimeFormat::DateTime)
+        .unwrap_or_default();
+    if let Ok(mut file) = file.lock() {
+        let _ = writeln!(file, "{} {}", now, line);
+    }
+}
+
+/// `eprintln!` that is also written to the log file.
+#[macro_export]
+macro_rules! elog {
+    ($($arg:tt)*) => {{
+        let line = format!($($arg)*);
+        eprintln!("{}", line);
+        $crate::log::write_line(&line);
+    }};
+}
+
+/// `println!` that is also written to the log file, for the server's log
+/// lines; command results are written to `out`, not logged.
+#[macro_export]
+macro_rules! olog {
+    ($($arg:tt)*) => {{
+        let line = format!($($arg)*);
+        println!("{}", line);
+        $crate::log::write_line(&line);
+    }};
+}
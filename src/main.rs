@@ -1,54 +1,505 @@
-mod cli;
-mod commands;
-mod config;
-mod s3_client;
-mod utils;
-
 use anyhow::Result;
-use clap::Parser;
+use clap::{CommandFactory, Parser};
 use dotenvy::dotenv;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use s3_uploader::{cli, commands, config, crypto, elog, error, keys, log, utils};
 
 use cli::{Cli, Commands};
-use commands::{delete, download, list, server, upload};
+use commands::copy::CopyOptions;
+use commands::delete::DeleteOutcome;
+use commands::download::{DownloadOptions, Overwrite, RecursiveOptions};
+use commands::list::ListOptions;
+use commands::metadata::MetadataUpdate;
+use commands::rename_regex::RenameOptions;
+use commands::server::ServerOptions;
+use commands::sync::SyncOptions;
+use commands::transfer::TransferOptions;
+use commands::upload::{BatchOptions, Encryption, MultipartMode, UploadOptions};
+use commands::upload_url::UrlUploadOptions;
+use commands::{
+    bucket_info, copy, create_bucket, delete, download, dry_run, generate_index, list,
+    list_uploads, metadata, purge, rename_regex, server, stat, sync, transfer, upload, upload_url,
+};
 use config::StorageConfig;
+use error::StorageError;
+use utils::OutputFormat;
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() {
+    if let Err(err) = run().await {
+        elog!("Error: {:?}", err);
+        if let Some(hint) = StorageError::find(&err).and_then(StorageError::hint) {
+            elog!("Hint: {}", hint);
+        }
+        std::process::exit(StorageError::exit_code_for(&err));
+    }
+}
+
+async fn run() -> Result<()> {
     dotenv().ok();
 
     let cli = Cli::parse();
+    if let Some(path) = &cli.log_file {
+        log::set_log_file(path)?;
+    }
+    // Completions don't touch storage, so they work without any credentials
+    if let Commands::Completions { shell } = cli.command {
+        // Rendered first, since generate panics if stdout is closed early
+        let mut script = Vec::new();
+        clap_complete::generate(
+            shell,
+            &mut Cli::command(),
+            env!("CARGO_BIN_NAME"),
+            &mut script,
+        );
+        std::io::stdout().write_all(&script)?;
+        return Ok(());
+    }
     let config = StorageConfig::load_from_cli(&cli)?;
+    // Command results go here; verbose progress goes to stderr
+    let mut out = std::io::stdout();
+
+    if config.output_format == OutputFormat::Yaml && !matches!(cli.command, Commands::Stat { .. }) {
+        elog!("⚠️ --output-format yaml is only supported by stat; printing text");
+    }
+    let plain_http = config
+        .endpoint
+        .as_deref()
+        .is_some_and(|e| e.to_ascii_lowercase().starts_with("http://"));
+    if config.unsigned_payload && plain_http {
+        elog!("⚠️ --unsigned-payload over plain HTTP leaves upload bodies unprotected in transit");
+    }
+
+    // Sync, rename and transfer plan their changes themselves, so their dry
+    // runs list them; create-bucket's target needn't exist yet
+    if cli.dry_run
+        && !matches!(
+            cli.command,
+            Commands::Sync { .. }
+                | Commands::RenameRegex { .. }
+                | Commands::Transfer { .. }
+                | Commands::CreateBucket
+        )
+    {
+        return dry_run::dry_run(&cli.command, &config, cli.verbose, &mut out).await;
+    }
 
     match &cli.command {
-        Commands::Upload { file_path, expires } => {
-            let info =
-                upload::upload_file(&file_path, &config, cli.verbose, Some(*expires)).await?;
-            println!("Uploaded: {} -> {}", info.file_name, info.download_url);
+        Commands::Upload {
+            file_path,
+            expires,
+            multipart_threshold,
+            part_size,
+            no_multipart,
+            force_multipart,
+            resumable,
+            restart,
+            flatten,
+            prefix,
+            key,
+            encrypt,
+            preserve_permissions,
+            verify_listed,
+            follow_symlinks,
+            metadata,
+            tags,
+            metadata_from_file,
+            sse,
+            sse_kms_key_id,
+            sse_kms_context,
+            bucket_key_enabled,
+            wait,
+            wait_timeout,
+            per_file_progress,
+            cache_control,
+            auto_cache_control,
+            content_type,
+            storage_class,
+        } => {
+            let multipart = if *no_multipart {
+                MultipartMode::Never
+            } else if *force_multipart {
+                MultipartMode::Always
+            } else {
+                MultipartMode::Auto
+            };
+            if let Some(key) = key {
+                keys::validate_object_key(key)?;
+            }
+            upload::tagging(tags)?;
+            if let Some(class) = storage_class {
+                upload::validate_storage_class(class)?;
+            }
+            let options = UploadOptions {
+                key: key.clone(),
+                prefix: prefix.clone(),
+                expires_seconds: Some(*expires),
+                delete_after_expiry: true,
+                multipart,
+                multipart_threshold: *multipart_threshold,
+                part_size: *part_size,
+                cache_control: cache_control.clone(),
+                auto_cache_control: *auto_cache_control,
+                progress: None,
+                wait: wait.then(|| Duration::from_secs(*wait_timeout)),
+                encryption: Encryption {
+                    sse: sse.clone(),
+                    kms_key_id: sse_kms_key_id.clone(),
+                    kms_context: sse_kms_context.clone(),
+                    bucket_key: *bucket_key_enabled,
+                },
+                metadata: upload::resolve_metadata(
+                    metadata_from_file.as_deref().map(Path::new),
+                    metadata,
+                )?,
+                tags: tags.clone(),
+                resumable: *resumable,
+                restart: *restart,
+                force_download: false,
+                content_type: content_type.clone(),
+                acl: None,
+                storage_class: storage_class.clone(),
+                verify_listed: *verify_listed,
+                passphrase: encrypt
+                    .then(|| crypto::passphrase(&config, true))
+                    .transpose()?,
+                preserve_permissions: *preserve_permissions,
+            };
+            if options.encryption.bucket_key && !options.encryption.uses_kms() {
+                elog!(
+                    "⚠️ --bucket-key-enabled only has an effect with SSE-KMS (--sse aws:kms or a KMS bucket default)"
+                );
+            }
+            let path = Path::new(file_path);
+            if path.is_dir() {
+                if key.is_some() {
+                    anyhow::bail!(
+                        "--key names a single object; it can't be used to upload a directory"
+                    );
+                }
+                let batch = BatchOptions {
+                    flatten: *flatten,
+                    concurrency: config.concurrency,
+                    per_file_progress: *per_file_progress,
+                    follow_symlinks: *follow_symlinks,
+                };
+                let summary =
+                    upload::upload_dir(path, &batch, &config, cli.verbose, &options).await?;
+                upload::write_batch_summary(&mut out, &summary, config.json)?;
+                if !summary.failed.is_empty() {
+                    anyhow::bail!("{} file(s) failed to upload", summary.failed.len());
+                }
+            } else if file_path == upload::STDIN_PATH {
+                // No length to draw a bar against; --verbose reports each part
+                let info = upload::upload_file(file_path, &config, cli.verbose, &options).await?;
+                upload::write_upload_info(&mut out, &info, config.json)?;
+            } else {
+                let size = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+                let progress =
+                    utils::make_progress_bar(size, config.units, config.progress, cli.verbose);
+                let options = UploadOptions {
+                    progress: Some(progress.clone()),
+                    ..options
+                };
+                let uploaded = upload::upload_file(file_path, &config, cli.verbose, &options).await;
+                match &uploaded {
+                    Ok(_) => progress.finish_and_clear(),
+                    Err(_) => progress.abandon(),
+                }
+                upload::write_upload_info(&mut out, &uploaded?, config.json)?;
+            }
+        }
+        Commands::RenameRegex {
+            pattern,
+            replace,
+            prefix,
+            yes,
+        } => {
+            let options = RenameOptions {
+                pattern: pattern.clone(),
+                replacement: replace.clone(),
+                prefix: prefix.clone(),
+                dry_run: cli.dry_run,
+                yes: *yes,
+            };
+            rename_regex::rename_regex(&options, &config, cli.verbose, &mut out).await?;
+        }
+        Commands::Transfer { src, dst, delete } => {
+            let options = TransferOptions {
+                delete: *delete,
+                dry_run: cli.dry_run,
+            };
+            transfer::transfer(src, dst, &options, &config, cli.verbose, &mut out).await?;
+        }
+        Commands::UploadUrl {
+            url,
+            key,
+            follow_redirects,
+        } => {
+            let options = UrlUploadOptions {
+                key: key.clone(),
+                max_redirects: *follow_redirects,
+            };
+            let info = upload_url::upload_url(url, &options, &config, cli.verbose).await?;
+            upload::write_upload_info(&mut out, &info, config.json)?;
         }
         Commands::Download {
             file_name,
             output,
             presign,
+            qr,
+            qr_file,
+            check,
             expires,
+            buffer_size,
+            prefetch,
+            if_modified_since,
+            restore_filename,
+            auto_decompress,
+            wait_for,
+            no_verify,
+            no_decrypt,
+            preserve_permissions,
+            recursive,
+            tags,
+            skip_existing,
+            dir,
+            update_only,
+        } => {
+            let options = DownloadOptions {
+                output: output.clone(),
+                presign: *presign,
+                check: *check,
+                expires_seconds: *expires,
+                qr: *qr,
+                qr_file: qr_file.clone(),
+                buffer_size: *buffer_size,
+                prefetch: *prefetch,
+                if_modified_since: if_modified_since.clone(),
+                restore_filename: *restore_filename,
+                auto_decompress: *auto_decompress,
+                wait_for: wait_for.map(Duration::from_secs),
+                verify: !*no_verify,
+                decrypt: !*no_decrypt,
+                preserve_permissions: *preserve_permissions,
+                show_progress: true,
+            };
+            if *recursive {
+                let overwrite = if *skip_existing {
+                    Overwrite::SkipExisting
+                } else if *update_only {
+                    Overwrite::UpdateOnly
+                } else {
+                    Overwrite::Always
+                };
+                let recursive = RecursiveOptions {
+                    tags: tags.clone(),
+                    overwrite,
+                    dir: *dir,
+                };
+                download::download_recursive(
+                    file_name,
+                    &recursive,
+                    &options,
+                    &config,
+                    cli.verbose,
+                    &mut out,
+                )
+                .await?;
+            } else {
+                download::download_file(file_name, &options, &config, cli.verbose, &mut out)
+                    .await?;
+            }
+        }
+        Commands::List {
+            prefix,
+            limit,
+            show_owner,
+            continuation_token,
+            show_token,
+            show_checksum,
+            show_metadata,
+            dir,
+            page_size,
+            tags,
+        } => {
+            let options = ListOptions {
+                prefix: prefix.clone(),
+                limit: *limit,
+                show_owner: *show_owner,
+                continuation_token: continuation_token.clone(),
+                show_token: *show_token,
+                show_checksum: *show_checksum,
+                show_metadata: *show_metadata,
+                dir: *dir,
+                page_size: *page_size,
+                tags: tags.clone(),
+            };
+            list::list_files(&options, &config, cli.verbose, &mut out).await?;
+        }
+        Commands::ListUploads { prefix, dir } => {
+            list_uploads::list_uploads(prefix.as_deref(), *dir, &config, cli.verbose, &mut out)
+                .await?;
+        }
+        Commands::Delete {
+            file_name,
+            recursive: true,
+            yes,
+            dir,
+            ..
         } => {
-            download::download_file(
-                &file_name,
-                output.as_deref(),
-                *presign,
-                *expires,
+            delete::delete_prefix(file_name, *dir, *yes, &config, cli.verbose, &mut out).await?;
+        }
+        Commands::Delete {
+            file_name,
+            ignore_missing,
+            ..
+        } => {
+            let key = config.prefixed(file_name);
+            let outcome = delete::delete_file(file_name, &config, cli.verbose).await?;
+            if outcome == DeleteOutcome::NotFound && !*ignore_missing {
+                return Err(StorageError::NotFound {
+                    key,
+                    bucket: config.bucket.clone(),
+                }
+                .into());
+            }
+            if config.json {
+                let result = serde_json::json!({
+                    "key": key,
+                    "bucket": config.bucket,
+                    "deleted": outcome == DeleteOutcome::Deleted,
+                });
+                writeln!(out, "{}", result)?;
+            } else if cli.verbose {
+                match outcome {
+                    DeleteOutcome::Deleted => elog!("✅ Deleted file: {}", key),
+                    DeleteOutcome::NotFound => elog!("  File {} already absent", key),
+                }
+            }
+        }
+        Commands::Purge {
+            confirm,
+            delete_bucket,
+        } => {
+            purge::purge_bucket(confirm, *delete_bucket, &config, cli.verbose, &mut out).await?;
+        }
+        Commands::Copy {
+            source,
+            dest,
+            dest_bucket,
+            move_source,
+            copy_source_if_match,
+            copy_source_if_unmodified_since,
+        } => {
+            let options = CopyOptions {
+                if_match: copy_source_if_match.clone(),
+                if_unmodified_since: copy_source_if_unmodified_since.clone(),
+                dest_bucket: dest_bucket.clone(),
+                move_source: *move_source,
+            };
+            copy::copy_file(source, dest, &options, &config, cli.verbose).await?;
+        }
+        Commands::Sync {
+            local_dir,
+            prefix,
+            delete,
+        } => {
+            let options = SyncOptions {
+                delete: *delete,
+                dry_run: cli.dry_run,
+            };
+            sync::sync_dir(
+                local_dir,
+                prefix.as_deref(),
+                &options,
                 &config,
                 cli.verbose,
+                &mut out,
             )
             .await?;
         }
-        Commands::List { prefix, limit } => {
-            list::list_files(prefix.as_deref(), *limit, &config, cli.verbose).await?;
+        Commands::GenerateIndex { prefix } => {
+            generate_index::generate_index(prefix.as_deref(), &config, cli.verbose).await?;
+        }
+        Commands::Stat { file_name, peek } => {
+            stat::stat_file(file_name, *peek, &config, cli.verbose, &mut out).await?;
+        }
+        Commands::CreateBucket => {
+            create_bucket::create_bucket(cli.dry_run, &config, cli.verbose, &mut out).await?;
         }
-        Commands::Delete { file_name } => {
-            delete::delete_file(&file_name, &config, cli.verbose).await?;
+        Commands::BucketInfo => {
+            bucket_info::bucket_info(&config, cli.verbose, &mut out).await?;
+        }
+        Commands::UpdateMetadata {
+            file_name,
+            set,
+            remove,
+            content_type,
+            cache_control,
+        } => {
+            let update = MetadataUpdate {
+                set: set.clone(),
+                remove: remove.clone(),
+                content_type: content_type.clone(),
+                cache_control: cache_control.clone(),
+            };
+            metadata::update_metadata(file_name, &update, &config, cli.verbose).await?;
+        }
+        Commands::Server {
+            port,
+            routes,
+            route_default,
+            identifier_mode,
+            refresh_credentials,
+            temp_dir,
+            multipart_threshold,
+            part_size,
+            force_download,
+            upload_acl,
+        } => {
+            if refresh_credentials.is_some()
+                && (cli.access_key.is_some()
+                    || cli.secret_key.is_some()
+                    || cli.credentials_command.is_some())
+            {
+                anyhow::bail!(
+                    "--refresh-credentials reloads STORAGE_ACCESS_KEY and STORAGE_SECRET_KEY from the environment; don't pass --access-key, --secret-key, or --credentials-command with it"
+                );
+            }
+            if config.retry_budget.is_some() {
+                anyhow::bail!(
+                    "--retry-budget covers a whole run, so once spent the server would never retry again; drop it for server"
+                );
+            }
+            // Checked up front so a bad size fails at startup, not on every upload
+            if let Some(size) = part_size {
+                upload::check_part_size(*size, config.units)?;
+            }
+            if let Some(acl) = upload_acl {
+                upload::validate_acl(acl)?;
+            }
+            let options = ServerOptions {
+                type_routes: routes.clone(),
+                default_route: route_default.clone(),
+                identifier_mode: *identifier_mode,
+                credentials_refresh: refresh_credentials.map(Duration::from_secs),
+                temp_dir: temp_dir
+                    .as_ref()
+                    .map(PathBuf::from)
+                    .unwrap_or_else(std::env::temp_dir),
+                multipart_threshold: *multipart_threshold,
+                part_size: *part_size,
+                force_download: *force_download,
+                upload_acl: upload_acl.clone(),
+            };
+            server::start_server(config, cli.verbose, *port, options).await?;
         }
-        Commands::Server { port } => {
-            server::start_server(config, cli.verbose, *port).await?;
+        Commands::Completions { .. } => {
+            unreachable!("completions are printed before loading the config")
         }
     }
 
@@ -0,0 +1,87 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// User metadata entries recording a file's Unix permission bits (octal)
+/// and owner.
+pub const MODE_METADATA: &str = "file-mode";
+pub const UID_METADATA: &str = "file-uid";
+pub const GID_METADATA: &str = "file-gid";
+
+/// The mode, uid and gid of `path` as metadata entries.
+#[cfg(unix)]
+pub fn capture(path: &Path) -> Result<HashMap<String, String>> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = std::fs::metadata(path)?;
+    Ok(HashMap::from([
+        (
+            MODE_METADATA.to_string(),
+            format!("{:o}", metadata.mode() & 0o7777),
+        ),
+        (UID_METADATA.to_string(), metadata.uid().to_string()),
+        (GID_METADATA.to_string(), metadata.gid().to_string()),
+    ]))
+}
+
+/// Applies the mode and owner recorded in `metadata` to `path`.
+///
+/// Changing the owner usually needs root, so a refused `chown` is reported
+/// and the mode is still applied.
+#[cfg(unix)]
+pub fn restore(path: &Path, metadata: &HashMap<String, String>, verbose: bool) -> Result<()> {
+    use anyhow::Context;
+    use std::os::unix::fs::PermissionsExt;
+
+    let parse = |name: &str| metadata.get(name).and_then(|v| v.parse::<u32>().ok());
+    let uid = parse(UID_METADATA);
+    let gid = parse(GID_METADATA);
+    if uid.is_some() || gid.is_some() {
+        match std::os::unix::fs::chown(path, uid, gid) {
+            Ok(()) if verbose => {
+                elog!("  Restored owner {:?}:{:?}", uid, gid)
+            }
+            Ok(()) => {}
+            Err(e) => elog!(
+                "⚠️ Could not restore owner of {} to {:?}:{:?}: {}",
+                path.display(),
+                uid,
+                gid,
+                e
+            ),
+        }
+    }
+
+    let mode = metadata
+        .get(MODE_METADATA)
+        .and_then(|v| u32::from_str_radix(v, 8).ok());
+    if let Some(mode) = mode {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode & 0o7777))
+            .with_context(|| format!("Failed to set mode {:o} on {}", mode, path.display()))?;
+        if verbose {
+            elog!("  Restored mode {:o}", mode);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn capture(_path: &Path) -> Result<HashMap<String, String>> {
+    warn_unsupported();
+    Ok(HashMap::new())
+}
+
+#[cfg(not(unix))]
+pub fn restore(_path: &Path, _metadata: &HashMap<String, String>, _verbose: bool) -> Result<()> {
+    warn_unsupported();
+    Ok(())
+}
+
+/// Warns once per run that Unix permissions can't be kept here.
+#[cfg(not(unix))]
+fn warn_unsupported() {
+    static WARNED: std::sync::Once = std::sync::Once::new();
+    WARNED.call_once(|| {
+        elog!("⚠️ --preserve-permissions has no effect on this platform");
+    });
+}
@@ -1,24 +1,69 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use aws_config::sts::AssumeRoleProvider;
 use aws_config::SdkConfig;
-use aws_sdk_s3::config::{Credentials, Region};
+use aws_credential_types::provider::error::CredentialsError;
+use aws_credential_types::provider::{future, ProvideCredentials, SharedCredentialsProvider};
+use aws_sdk_s3::config::http::HttpResponse;
+use aws_sdk_s3::config::interceptors::{
+    BeforeTransmitInterceptorContextRef, FinalizerInterceptorContextRef, InterceptorContext,
+};
+use aws_sdk_s3::config::retry::RetryConfig;
+use aws_sdk_s3::config::retry::{ClassifyRetry, RetryAction};
+use aws_sdk_s3::config::{ConfigBag, Credentials, Intercept, Region, RuntimeComponents};
+use aws_sdk_s3::error::{
+    BoxError, ConnectorError, DisplayErrorContext, ProvideErrorMetadata, SdkError,
+};
+use aws_sdk_s3::primitives::{DateTime, DateTimeFormat};
 use aws_sdk_s3::Client;
+use aws_smithy_runtime_api::client::retries::classifiers::RetryClassifierPriority;
+use aws_smithy_types::config_bag::{Storable, StoreReplace};
+use aws_smithy_types::retry::ErrorKind;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
 
-use crate::config::StorageConfig;
+use crate::config::{AssumeRole, StorageConfig};
+use crate::error::StorageError;
+
+/// Regions discovered for buckets whose configured region turned out wrong.
+fn corrected_regions() -> &'static Mutex<HashMap<String, String>> {
+    static REGIONS: OnceLock<Mutex<HashMap<String, String>>> = OnceLock::new();
+    REGIONS.get_or_init(|| Mutex::new(HashMap::new()))
+}
 
 pub async fn create_client(config: &StorageConfig, verbose: bool) -> Result<Client> {
     if verbose {
-        println!("🔧 Creating S3 client for bucket {}", config.bucket);
+        elog!("🔧 Creating S3 client for bucket {}", config.bucket);
+    }
+
+    // Only the first client of a run pays for the probe
+    static PROBED: AtomicBool = AtomicBool::new(false);
+    if (config.probe || verbose) && !PROBED.swap(true, Ordering::SeqCst) {
+        probe_endpoint(config, verbose).await?;
     }
 
     let credentials = Credentials::new(
         config.access_key.clone(),
         config.secret_key.clone(),
-        None,
+        config.session_token.clone(),
         None,
         "custom",
     );
+    let credentials = match &config.assume_role {
+        Some(role) => assumed_role_credentials(config, role, credentials, verbose).await?,
+        None => SharedCredentialsProvider::new(credentials),
+    };
 
-    let region = Region::new(config.region.clone());
+    let region = corrected_regions()
+        .lock()
+        .unwrap()
+        .get(&config.bucket)
+        .cloned()
+        .unwrap_or_else(|| config.region.clone());
+    let region = Region::new(region);
 
     let mut loader = aws_config::ConfigLoader::default()
         .region(region)
@@ -30,9 +75,625 @@ pub async fn create_client(config: &StorageConfig, verbose: bool) -> Result<Clie
 
     let sdk_config: SdkConfig = loader.load().await;
 
-    let client_config = aws_sdk_s3::config::Builder::from(&sdk_config)
+    // Standard mode retries every request on throttling, 5xx, and transport
+    // failures, with jittered exponential backoff; client errors such as 403
+    // fail on the first attempt.
+    let mut client_config = aws_sdk_s3::config::Builder::from(&sdk_config)
         .behavior_version_latest()
-        .build();
+        .retry_config(
+            RetryConfig::standard().with_max_attempts(config.max_retries.saturating_add(1)),
+        )
+        .retry_classifier(RetryAfter);
+    if let Some(limit) = config.retry_budget {
+        client_config = client_config
+            .interceptor(RetryBudget { limit })
+            .retry_classifier(RetryBudget { limit });
+    }
+    let client_config = client_config.build();
 
     Ok(Client::from_conf(client_config))
 }
+
+/// A client configured like `create_client`'s, with the SDK's standard
+/// retries, whose requests are answered by `respond` instead of a server.
+#[cfg(test)]
+pub(crate) fn stub_client(
+    max_retries: u32,
+    respond: impl Fn(http::Request<aws_smithy_types::body::SdkBody>) -> http::Response<String>
+        + Send
+        + Sync
+        + 'static,
+) -> Client {
+    let config = aws_sdk_s3::config::Builder::new()
+        .behavior_version_latest()
+        .region(Region::new("us-east-1"))
+        .credentials_provider(Credentials::new("AKIDTEST", "secret", None, None, "test"))
+        .endpoint_url("http://stub.invalid")
+        .force_path_style(true)
+        .retry_config(RetryConfig::standard().with_max_attempts(max_retries.saturating_add(1)))
+        .retry_classifier(RetryAfter)
+        .http_client(aws_smithy_http_client::test_util::infallible_client_fn(
+            respond,
+        ))
+        .build();
+    Client::from_conf(config)
+}
+
+/// How long before they expire assumed-role credentials are renewed.
+const ROLE_REFRESH_MARGIN: Duration = Duration::from_secs(5 * 60);
+
+/// Assumed-role credentials, fetched on first use and fetched again when
+/// they are about to expire, so a long-running command keeps working.
+#[derive(Debug)]
+struct AssumedRole {
+    provider: AssumeRoleProvider,
+    cached: tokio::sync::Mutex<Option<Credentials>>,
+}
+
+impl ProvideCredentials for AssumedRole {
+    fn provide_credentials<'a>(&'a self) -> future::ProvideCredentials<'a>
+    where
+        Self: 'a,
+    {
+        future::ProvideCredentials::new(async move {
+            let mut cached = self.cached.lock().await;
+            let fresh = cached.as_ref().filter(|c| {
+                c.expiry().is_none_or(|expiry| {
+                    expiry
+                        .duration_since(std::time::SystemTime::now())
+                        .is_ok_and(|left| left > ROLE_REFRESH_MARGIN)
+                })
+            });
+            if let Some(credentials) = fresh {
+                return Ok(credentials.clone());
+            }
+            let credentials = self.provider.provide_credentials().await?;
+            *cached = Some(credentials.clone());
+            Ok(credentials)
+        })
+    }
+}
+
+/// The provider of `role`'s credentials, shared by every client of the run
+/// and keyed by what it was built from, so the role is assumed once rather
+/// than per client. The first fetch happens here, so a role that can't be
+/// assumed fails before any S3 request.
+async fn assumed_role_credentials(
+    config: &StorageConfig,
+    role: &AssumeRole,
+    base: Credentials,
+    verbose: bool,
+) -> Result<SharedCredentialsProvider> {
+    static ASSUMED: tokio::sync::Mutex<Option<(String, SharedCredentialsProvider)>> =
+        tokio::sync::Mutex::const_new(None);
+
+    let key = format!("{}|{}|{}", role.role_arn, config.access_key, config.region);
+    let mut assumed = ASSUMED.lock().await;
+    if let Some((built_from, provider)) = assumed.as_ref() {
+        if *built_from == key {
+            return Ok(provider.clone());
+        }
+    }
+
+    if verbose {
+        elog!("🎭 Assuming role {}", role.role_arn);
+    }
+    // STS is reached at its own regional endpoint, never the storage endpoint
+    let sts_config = aws_config::ConfigLoader::default()
+        .behavior_version(aws_config::BehaviorVersion::latest())
+        .region(Region::new(config.region.clone()))
+        .credentials_provider(base)
+        .load()
+        .await;
+    let session_name = role.session_name.clone().unwrap_or_else(|| {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        format!("s3-uploader-{}", now.as_secs())
+    });
+    let mut builder = AssumeRoleProvider::builder(&role.role_arn)
+        .session_name(session_name)
+        .configure(&sts_config);
+    if let Some(external_id) = &role.external_id {
+        builder = builder.external_id(external_id);
+    }
+    let provider = AssumedRole {
+        provider: builder.build().await,
+        cached: tokio::sync::Mutex::new(None),
+    };
+
+    let credentials = provider.provide_credentials().await.map_err(|e| {
+        let message = format!(
+            "Could not assume role {}: {}",
+            role.role_arn,
+            DisplayErrorContext(&e)
+        );
+        // STS being unreachable says nothing about whether the role allows us
+        let unreachable = matches!(e, CredentialsError::ProviderTimedOut(_))
+            || std::iter::successors(Some(&e as &dyn std::error::Error), |cause| cause.source())
+                .any(|cause| cause.is::<ConnectorError>());
+        if unreachable {
+            StorageError::Network { message }
+        } else {
+            StorageError::AccessDenied { message }
+        }
+    })?;
+    if verbose {
+        if let Some(expiry) = credentials.expiry() {
+            elog!(
+                "  Assumed role until {}",
+                DateTime::from(expiry)
+                    .fmt(DateTimeFormat::DateTime)
+                    .unwrap_or_default()
+            );
+        }
+    }
+
+    let provider = SharedCredentialsProvider::new(provider);
+    *assumed = Some((key, provider.clone()));
+    Ok(provider)
+}
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Opens a TCP connection to the endpoint so an unreachable host fails fast
+/// with a clear message instead of hanging inside the first S3 call.
+async fn probe_endpoint(config: &StorageConfig, verbose: bool) -> Result<()> {
+    let endpoint = config
+        .endpoint
+        .clone()
+        .unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", config.region));
+    let address = endpoint_address(&endpoint)?;
+
+    if verbose {
+        elog!("🔌 Probing endpoint {}", address);
+    }
+
+    let message = match tokio::time::timeout(PROBE_TIMEOUT, TcpStream::connect(&address)).await {
+        Ok(Ok(_)) => return Ok(()),
+        Ok(Err(e)) => format!("Cannot reach endpoint {}: {}", endpoint, e),
+        Err(_) => format!(
+            "Cannot reach endpoint {}: no response within {}s",
+            endpoint,
+            PROBE_TIMEOUT.as_secs()
+        ),
+    };
+    Err(StorageError::Network { message }.into())
+}
+
+/// Extracts `host:port` from an endpoint URL, defaulting the port from the scheme.
+fn endpoint_address(endpoint: &str) -> Result<String> {
+    let (scheme, rest) = endpoint.split_once("://").unwrap_or(("https", endpoint));
+    let authority = rest.split('/').next().unwrap_or_default();
+    let authority = authority.rsplit('@').next().unwrap_or_default();
+    if authority.is_empty() {
+        return Err(anyhow!("Invalid endpoint URL: {}", endpoint));
+    }
+
+    // A colon after any IPv6 brackets means an explicit port
+    let has_port = authority
+        .rsplit_once(']')
+        .map_or(authority, |(_, tail)| tail)
+        .contains(':');
+    if has_port {
+        return Ok(authority.to_string());
+    }
+    let port = if scheme.eq_ignore_ascii_case("http") {
+        80
+    } else {
+        443
+    };
+    Ok(format!("{}:{}", authority, port))
+}
+
+/// Runs `op` with a fresh client, retrying once against the bucket's real
+/// region if the first attempt fails because `config.region` is wrong.
+pub async fn with_region_retry<T, F, Fut>(config: &StorageConfig, verbose: bool, op: F) -> Result<T>
+where
+    F: Fn(Client) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let client = create_client(config, verbose).await?;
+    let err = match op(client.clone()).await {
+        Ok(value) => return Ok(value),
+        Err(err) => err,
+    };
+
+    let region = match bucket_region(&client, &config.bucket).await {
+        Some(region) if region != client.config().region().map_or("", |r| r.as_ref()) => region,
+        _ => return Err(err),
+    };
+
+    elog!(
+        "⚠️ Bucket {} is in region {}, not {}; retrying there",
+        config.bucket,
+        region,
+        config.region
+    );
+    corrected_regions()
+        .lock()
+        .unwrap()
+        .insert(config.bucket.clone(), region);
+
+    let client = create_client(config, verbose).await?;
+    op(client).await
+}
+
+/// Asks the endpoint which region `bucket` lives in. S3 reports it in the
+/// `x-amz-bucket-region` header even when rejecting the request.
+async fn bucket_region(client: &Client, bucket: &str) -> Option<String> {
+    match client.head_bucket().bucket(bucket).send().await {
+        Ok(output) => output.bucket_region().map(str::to_string),
+        Err(e) => e
+            .raw_response()
+            .and_then(|r| r.headers().get("x-amz-bucket-region"))
+            .map(str::to_string),
+    }
+}
+
+/// Whether a failed request is worth retrying: throttling, 5xx responses,
+/// timeouts, and connection failures are; other 4xx responses are not.
+pub fn is_retryable<E: ProvideErrorMetadata>(err: &SdkError<E, HttpResponse>) -> bool {
+    match err {
+        SdkError::TimeoutError(_) | SdkError::ResponseError(_) => true,
+        SdkError::DispatchFailure(failure) => !failure.is_user(),
+        SdkError::ServiceError(service) => {
+            let status = service.raw().status().as_u16();
+            status >= 500
+                || status == 429
+                || matches!(
+                    service.err().code(),
+                    Some("SlowDown" | "Throttling" | "ThrottlingException" | "RequestTimeout")
+                )
+        }
+        _ => false,
+    }
+}
+
+/// Longest `Retry-After` honoured, so a bogus header can't stall a run.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// The server's requested wait from a `Retry-After` header, given either as
+/// seconds or as an HTTP date.
+pub fn retry_after<E>(err: &SdkError<E, HttpResponse>) -> Option<Duration> {
+    response_retry_after(err.raw_response()?)
+}
+
+fn response_retry_after(response: &HttpResponse) -> Option<Duration> {
+    let value = response.headers().get("retry-after")?.trim();
+    let wait = match value.parse::<u64>() {
+        Ok(seconds) => Duration::from_secs(seconds),
+        Err(_) => {
+            let at = DateTime::from_str(value, DateTimeFormat::HttpDate).ok()?;
+            let at = std::time::SystemTime::try_from(at).ok()?;
+            at.duration_since(std::time::SystemTime::now())
+                .unwrap_or_default()
+        }
+    };
+    Some(wait.min(MAX_RETRY_AFTER))
+}
+
+/// How long to wait before retrying `err`: the server's `Retry-After` when
+/// it sent one, otherwise `backoff`.
+pub fn retry_delay<E>(err: &SdkError<E, HttpResponse>, backoff: Duration) -> Duration {
+    retry_after(err).unwrap_or(backoff)
+}
+
+/// Time spent retrying so far in this run, across every client and request.
+static RETRY_SPENT_MS: AtomicU64 = AtomicU64::new(0);
+
+/// Adds `time` to the time spent retrying.
+pub fn charge_retry(time: Duration) {
+    RETRY_SPENT_MS.fetch_add(time.as_millis() as u64, Ordering::Relaxed);
+}
+
+/// Whether retrying has used up `budget`; `None` is no budget.
+pub fn retry_budget_spent(budget: Option<Duration>) -> bool {
+    budget
+        .is_some_and(|limit| Duration::from_millis(RETRY_SPENT_MS.load(Ordering::Relaxed)) >= limit)
+}
+
+/// Caps the time the SDK's own retries may take, shared with `with_retry`.
+///
+/// As an interceptor it charges each backoff wait and each retried attempt;
+/// as a retry classifier it forbids retries once `limit` is spent, so a
+/// request fails with its own error rather than being retried again.
+#[derive(Debug, Clone, Copy)]
+struct RetryBudget {
+    limit: Duration,
+}
+
+/// When the current attempt of a request started and, once it's over, ended.
+#[derive(Debug, Clone)]
+struct AttemptClock {
+    started: Instant,
+    ended: Option<Instant>,
+    retry: bool,
+}
+
+impl Storable for AttemptClock {
+    type Storer = StoreReplace<Self>;
+}
+
+impl Intercept for RetryBudget {
+    fn name(&self) -> &'static str {
+        "RetryBudget"
+    }
+
+    fn read_before_attempt(
+        &self,
+        _context: &BeforeTransmitInterceptorContextRef<'_>,
+        _runtime_components: &RuntimeComponents,
+        cfg: &mut ConfigBag,
+    ) -> Result<(), BoxError> {
+        // A previous attempt that ended means this one is a retry
+        let waited = cfg.load::<AttemptClock>().and_then(|c| c.ended);
+        if let Some(ended) = waited {
+            charge_retry(ended.elapsed());
+        }
+        cfg.interceptor_state().store_put(AttemptClock {
+            started: Instant::now(),
+            ended: None,
+            retry: waited.is_some(),
+        });
+        Ok(())
+    }
+
+    fn read_after_attempt(
+        &self,
+        _context: &FinalizerInterceptorContextRef<'_>,
+        _runtime_components: &RuntimeComponents,
+        cfg: &mut ConfigBag,
+    ) -> Result<(), BoxError> {
+        if let Some(clock) = cfg.load::<AttemptClock>().cloned() {
+            if clock.retry {
+                charge_retry(clock.started.elapsed());
+            }
+            cfg.interceptor_state().store_put(AttemptClock {
+                ended: Some(Instant::now()),
+                ..clock
+            });
+        }
+        Ok(())
+    }
+}
+
+impl ClassifyRetry for RetryBudget {
+    fn classify_retry(&self, _ctx: &InterceptorContext) -> RetryAction {
+        if retry_budget_spent(Some(self.limit)) {
+            RetryAction::RetryForbidden
+        } else {
+            RetryAction::NoActionIndicated
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "RetryBudget"
+    }
+}
+
+/// Makes the SDK's own retries wait as long as a throttled or failing
+/// server asks with `Retry-After`, rather than its usual backoff.
+///
+/// It runs after the SDK's classifiers and only sets the delay for
+/// responses they would retry anyway: 429 and 5xx. The SDK still caps the
+/// wait at its maximum backoff of 20 seconds.
+#[derive(Debug, Clone, Copy)]
+struct RetryAfter;
+
+impl ClassifyRetry for RetryAfter {
+    fn classify_retry(&self, ctx: &InterceptorContext) -> RetryAction {
+        let Some(response) = ctx.response() else {
+            return RetryAction::NoActionIndicated;
+        };
+        let kind = match response.status().as_u16() {
+            429 | 503 => ErrorKind::ThrottlingError,
+            status if status >= 500 => ErrorKind::ServerError,
+            _ => return RetryAction::NoActionIndicated,
+        };
+        match response_retry_after(response) {
+            Some(delay) => RetryAction::retryable_error_with_explicit_delay(kind, delay),
+            None => RetryAction::NoActionIndicated,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "RetryAfter"
+    }
+
+    fn priority(&self) -> RetryClassifierPriority {
+        RetryClassifierPriority::run_after(RetryClassifierPriority::transient_error_classifier())
+    }
+}
+
+/// First and largest waits between attempts of `with_retry`.
+const RETRY_BASE: Duration = Duration::from_millis(500);
+const RETRY_CAP: Duration = Duration::from_secs(20);
+
+/// Runs `op` again after each retryable failure, up to `max_retries` times.
+///
+/// This is for operations that must be repeated as a whole, such as a part
+/// whose body has to be re-read from disk. Each wait is the server's
+/// `Retry-After` if it sent one, otherwise a random time between half and
+/// all of an exponentially growing backoff, so parallel retries don't line
+/// up.
+/// Errors `is_retryable` rejects, like 403 or 404, fail at once, as does
+/// every error once `budget` is spent.
+pub async fn with_retry<T, E, F, Fut>(
+    max_retries: u32,
+    budget: Option<Duration>,
+    what: &str,
+    verbose: bool,
+    mut op: F,
+) -> Result<T, SdkError<E, HttpResponse>>
+where
+    E: ProvideErrorMetadata + std::error::Error + 'static,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, SdkError<E, HttpResponse>>>,
+{
+    let mut retries = 0;
+    loop {
+        match op().await {
+            Ok(value) => return Ok(value),
+            Err(e) if retries < max_retries && is_retryable(&e) && !retry_budget_spent(budget) => {
+                let backoff = RETRY_BASE
+                    .saturating_mul(1 << retries.min(16))
+                    .min(RETRY_CAP);
+                let delay = retry_delay(&e, jitter(backoff));
+                retries += 1;
+                if verbose {
+                    elog!(
+                        "  ⚠️ {} failed: {} (retry {}/{} in {} ms)",
+                        what,
+                        e,
+                        retries,
+                        max_retries,
+                        delay.as_millis()
+                    );
+                }
+                tokio::time::sleep(delay).await;
+                charge_retry(delay);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// A random duration between half of `max` and `max`.
+fn jitter(max: Duration) -> Duration {
+    use std::hash::{BuildHasher, Hasher};
+
+    // Each RandomState is freshly keyed, which is all the randomness needed here
+    let random = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish();
+    let half = max / 2;
+    half + Duration::from_nanos(random % (half.as_nanos() as u64).max(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_s3::error::ErrorMetadata;
+    use aws_sdk_s3::operation::get_object::GetObjectError;
+    use aws_sdk_s3::primitives::ByteStream;
+    use aws_smithy_types::body::SdkBody;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+
+    /// A `GetObject` service error with `status` and, if given, an error code.
+    fn service_error(status: u16, code: Option<&str>) -> SdkError<GetObjectError, HttpResponse> {
+        let mut meta = ErrorMetadata::builder().message("test");
+        if let Some(code) = code {
+            meta = meta.code(code);
+        }
+        let response = http::Response::builder()
+            .status(status)
+            .body(SdkBody::empty())
+            .unwrap();
+        SdkError::service_error(
+            GetObjectError::generic(meta.build()),
+            HttpResponse::try_from(response).unwrap(),
+        )
+    }
+
+    #[test]
+    fn client_errors_are_not_retried() {
+        assert!(!is_retryable(&service_error(403, Some("AccessDenied"))));
+        assert!(!is_retryable(&service_error(404, Some("NoSuchKey"))));
+        assert!(!is_retryable(&service_error(400, Some("InvalidArgument"))));
+    }
+
+    #[test]
+    fn server_errors_and_throttling_are_retried() {
+        assert!(is_retryable(&service_error(503, Some("SlowDown"))));
+        assert!(is_retryable(&service_error(503, None)));
+        assert!(is_retryable(&service_error(500, Some("InternalError"))));
+        assert!(is_retryable(&service_error(502, None)));
+        assert!(is_retryable(&service_error(504, None)));
+        assert!(is_retryable(&service_error(429, None)));
+        // Throttling reported with a 400 status by some gateways
+        assert!(is_retryable(&service_error(400, Some("Throttling"))));
+        assert!(is_retryable(&service_error(400, Some("RequestTimeout"))));
+    }
+
+    /// A client that answers every request with `status`, and a count of
+    /// the requests it received.
+    fn failing_client(max_retries: u32, status: u16) -> (Client, Arc<AtomicUsize>) {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let seen = attempts.clone();
+        let client = stub_client(max_retries, move |_| {
+            seen.fetch_add(1, Ordering::SeqCst);
+            http::Response::builder()
+                .status(status)
+                .body(String::new())
+                .unwrap()
+        });
+        (client, attempts)
+    }
+
+    /// How many requests a failing `HeadObject` and `PutObject` each made.
+    async fn attempts_for(max_retries: u32, status: u16) -> (usize, usize) {
+        let (client, attempts) = failing_client(max_retries, status);
+        let head = client
+            .head_object()
+            .bucket("bucket")
+            .key("key")
+            .send()
+            .await;
+        assert!(head.is_err());
+        let heads = attempts.swap(0, Ordering::SeqCst);
+
+        let put = client
+            .put_object()
+            .bucket("bucket")
+            .key("key")
+            .body(ByteStream::from_static(b"data"))
+            .send()
+            .await;
+        assert!(put.is_err());
+        (heads, attempts.load(Ordering::SeqCst))
+    }
+
+    #[tokio::test]
+    async fn sdk_sends_forbidden_requests_once() {
+        assert_eq!(attempts_for(2, 403).await, (1, 1));
+    }
+
+    #[tokio::test]
+    async fn sdk_retries_unavailable_up_to_max_retries() {
+        assert_eq!(attempts_for(2, 503).await, (3, 3));
+    }
+
+    #[tokio::test]
+    async fn sdk_retries_wait_for_retry_after() {
+        let attempts = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let seen = attempts.clone();
+        let client = stub_client(1, move |_| {
+            if seen.fetch_add(1, Ordering::SeqCst) == 0 {
+                http::Response::builder()
+                    .status(503)
+                    .header("Retry-After", "2")
+                    .body(String::new())
+                    .unwrap()
+            } else {
+                http::Response::builder()
+                    .status(200)
+                    .body(String::new())
+                    .unwrap()
+            }
+        });
+
+        let started = Instant::now();
+        client
+            .head_object()
+            .bucket("bucket")
+            .key("key")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+        // The SDK's own backoff for a first retry is under a second
+        assert!(started.elapsed() >= Duration::from_secs(2));
+    }
+}
@@ -1,11 +1,244 @@
-pub fn format_size(bytes: u64) -> String {
-    const UNITS: [&str; 6] = ["B", "KB", "MB", "GB", "TB", "PB"];
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use sha2::Digest;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Unit system for displayed sizes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Units {
+    /// Powers of 1024: KiB, MiB, GiB
+    #[default]
+    Binary,
+    /// Powers of 1000: KB, MB, GB
+    Si,
+}
+
+/// How command results are written to stdout.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable text
+    #[default]
+    Text,
+    /// JSON, as with --json
+    Json,
+    /// YAML (stat only; other commands print text)
+    Yaml,
+}
+
+/// When transfer progress is shown on stderr.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ProgressMode {
+    /// A bar when stdout and stderr are terminals, periodic log lines otherwise
+    #[default]
+    Auto,
+    /// A bar whenever stderr is a terminal, even with stdout redirected
+    Always,
+    /// No progress output
+    Never,
+}
+
+pub fn format_size(bytes: u64, units: Units) -> String {
+    let (base, labels) = match units {
+        Units::Binary => (1024_f64, ["B", "KiB", "MiB", "GiB", "TiB", "PiB"]),
+        Units::Si => (1000_f64, ["B", "KB", "MB", "GB", "TB", "PB"]),
+    };
     if bytes == 0 {
         return "0 B".to_string();
     }
-    let base = 1024_f64;
     let exponent = (bytes as f64).log(base).floor() as i32;
-    let exponent = exponent.min(UNITS.len() as i32 - 1);
+    let exponent = exponent.min(labels.len() as i32 - 1);
+    if exponent == 0 {
+        return format!("{} B", bytes);
+    }
     let value = bytes as f64 / base.powi(exponent);
-    format!("{:.2} {}", value, UNITS[exponent as usize])
+    format!("{:.2} {}", value, labels[exponent as usize])
+}
+
+/// Builds a bar style, rendering `{bytes}`/`{total_bytes}` in `units`.
+pub fn progress_style(template: &str, units: Units) -> ProgressStyle {
+    let template = match units {
+        Units::Binary => template.to_string(),
+        Units::Si => template
+            .replace("{bytes}", "{decimal_bytes}")
+            .replace("{total_bytes}", "{decimal_total_bytes}")
+            .replace("{bytes_per_sec}", "{decimal_bytes_per_sec}"),
+    };
+    ProgressStyle::with_template(&template)
+        .expect("progress template is valid")
+        .progress_chars("=> ")
+}
+
+/// A transfer progress bar on stderr: a compact bar and percentage, plus
+/// bytes and speed when `verbose`. Where `mode` rules out drawing a bar, as
+/// when output is piped or redirected, it logs progress lines instead (see
+/// `log_progress`), and with `ProgressMode::Never` it shows nothing.
+pub fn make_progress_bar(
+    total: u64,
+    units: Units,
+    mode: ProgressMode,
+    verbose: bool,
+) -> ProgressBar {
+    if !draws_bars(mode) {
+        let pb = ProgressBar::with_draw_target(Some(total), ProgressDrawTarget::hidden());
+        if mode != ProgressMode::Never {
+            log_progress(&pb, units);
+        }
+        return pb;
+    }
+    let template = if verbose {
+        "  [{bar:30}] {percent:>3}% {bytes}/{total_bytes} {bytes_per_sec} ETA {eta}"
+    } else {
+        "[{bar:30}] {percent:>3}% ETA {eta}"
+    };
+    let pb = ProgressBar::new(total);
+    pb.set_style(progress_style(template, units));
+    pb
+}
+
+/// Whether `mode` allows drawing progress bars on this terminal.
+pub fn draws_bars(mode: ProgressMode) -> bool {
+    match mode {
+        ProgressMode::Auto => io::stdout().is_terminal() && io::stderr().is_terminal(),
+        ProgressMode::Always => io::stderr().is_terminal(),
+        ProgressMode::Never => false,
+    }
+}
+
+/// Longest quiet spell between progress log lines.
+const PROGRESS_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Logs `pb`'s progress to stderr as plain lines, for logs where a bar
+/// can't be drawn: one line per 10% done, and at least one every 10s, so a
+/// long transfer in CI visibly stays alive. It stops once the bar is
+/// finished or dropped, and a transfer done within a second logs nothing.
+pub fn log_progress(pb: &ProgressBar, units: Units) {
+    let weak = pb.downgrade();
+    std::thread::spawn(move || {
+        let started = Instant::now();
+        let mut last_line = started;
+        let mut last_step = 0;
+        loop {
+            std::thread::sleep(Duration::from_secs(1));
+            let Some(pb) = weak.upgrade() else { break };
+            if pb.is_finished() {
+                break;
+            }
+            let (done, total) = (pb.position(), pb.length().unwrap_or(0));
+            let percent = (done * 100).checked_div(total).unwrap_or(0);
+            let step = percent / 10;
+            if step > last_step || last_line.elapsed() >= PROGRESS_LOG_INTERVAL {
+                elog!(
+                    "⏳ {}% ({} of {}) after {}s",
+                    percent,
+                    format_size(done, units),
+                    format_size(total, units),
+                    started.elapsed().as_secs()
+                );
+                last_line = Instant::now();
+                last_step = step;
+            }
+        }
+    });
+}
+
+/// Looks up `value` in `(prefix, result)` rules; the longest matching prefix wins.
+pub fn longest_prefix_match<'a>(rules: &'a [(String, String)], value: &str) -> Option<&'a str> {
+    rules
+        .iter()
+        .filter(|(prefix, _)| value.starts_with(prefix.as_str()))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(_, result)| result.as_str())
+}
+
+/// Hashes a file in fixed-size chunks.
+pub fn hash_file<D: Digest>(path: &Path) -> io::Result<Vec<u8>> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = D::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finalize().to_vec())
+}
+
+/// Lowercase hex encoding of `bytes`.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Writes the buffered output of each finished item in a batch, reports the
+/// failures on stderr, and fails if there were any. `done` is the past-tense
+/// verb for the summary line, e.g. `Downloaded`.
+pub fn report_batch<K: std::fmt::Display>(
+    results: Vec<(K, anyhow::Result<Vec<u8>>)>,
+    action: &str,
+    done: &str,
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    let mut failed = 0;
+    for (item, result) in &results {
+        match result {
+            Ok(report) => out.write_all(report)?,
+            Err(e) => {
+                elog!("❌ Failed to {} {}: {:#}", action, item, e);
+                failed += 1;
+            }
+        }
+    }
+    writeln!(
+        out,
+        "{} {} object(s), {} failed",
+        done,
+        results.len() - failed,
+        failed
+    )?;
+    if failed > 0 {
+        anyhow::bail!("{} object(s) failed to {}", failed, action);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_size_binary() {
+        let size = |bytes| format_size(bytes, Units::Binary);
+        assert_eq!(size(0), "0 B");
+        assert_eq!(size(999), "999 B");
+        assert_eq!(size(1000), "1000 B");
+        assert_eq!(size(1023), "1023 B");
+        assert_eq!(size(1024), "1.00 KiB");
+        assert_eq!(size(1536), "1.50 KiB");
+        assert_eq!(size(1024 * 1024), "1.00 MiB");
+        assert_eq!(size(1 << 30), "1.00 GiB");
+        assert_eq!(size(1 << 40), "1.00 TiB");
+        assert_eq!(size(1 << 50), "1.00 PiB");
+        // PiB is the largest unit
+        assert_eq!(size(1 << 60), "1024.00 PiB");
+        assert_eq!(size(u64::MAX), "16384.00 PiB");
+    }
+
+    #[test]
+    fn format_size_si() {
+        let size = |bytes| format_size(bytes, Units::Si);
+        assert_eq!(size(0), "0 B");
+        assert_eq!(size(999), "999 B");
+        assert_eq!(size(1000), "1.00 KB");
+        assert_eq!(size(1023), "1.02 KB");
+        assert_eq!(size(1024), "1.02 KB");
+        assert_eq!(size(1_000_000), "1.00 MB");
+        assert_eq!(size(1_000_000_000), "1.00 GB");
+        assert_eq!(size(1_000_000_000_000), "1.00 TB");
+        assert_eq!(size(1_000_000_000_000_000), "1.00 PB");
+        // PB is the largest unit
+        assert_eq!(size(1_000_000_000_000_000_000), "1000.00 PB");
+        assert_eq!(size(u64::MAX), "18446.74 PB");
+    }
 }
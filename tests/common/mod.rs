@@ -0,0 +1,161 @@
+//! A minimal HTTP server standing in for S3, and a way to run the built
+//! binary against it.
+
+// Each test crate uses only some of these
+#![allow(dead_code)]
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// A request as the fake server received it.
+pub struct Request {
+    pub method: String,
+    /// Path and query, e.g. `/bucket/key?uploads`
+    pub target: String,
+    /// Header names are lowercase
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Response {
+    pub fn new(status: u16) -> Self {
+        Response {
+            status,
+            headers: Vec::new(),
+            body: String::new(),
+        }
+    }
+
+    /// An S3 error response with `code`.
+    pub fn error(status: u16, code: &str) -> Self {
+        Response {
+            body: format!(
+                "<?xml version=\"1.0\"?><Error><Code>{}</Code><Message>fake {}</Message></Error>",
+                code, status
+            ),
+            ..Response::new(status)
+        }
+    }
+}
+
+/// Serves every request with `handle` on a local port, returning the
+/// endpoint URL. Each connection carries one request.
+pub fn fake_s3(handle: impl Fn(&Request) -> Response + Send + 'static) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+            let Some(request) = read_request(&mut BufReader::new(stream.try_clone().unwrap()))
+            else {
+                continue;
+            };
+            let response = handle(&request);
+            let mut head = format!("HTTP/1.1 {} Fake\r\nConnection: close\r\n", response.status);
+            // A HEAD response gives the length of the body it stands for
+            let sized = response
+                .headers
+                .iter()
+                .any(|(name, _)| name.eq_ignore_ascii_case("content-length"));
+            if !sized {
+                head.push_str(&format!("Content-Length: {}\r\n", response.body.len()));
+            }
+            for (name, value) in &response.headers {
+                head.push_str(&format!("{}: {}\r\n", name, value));
+            }
+            head.push_str("\r\n");
+            let _ = stream.write_all(head.as_bytes());
+            if request.method != "HEAD" {
+                let _ = stream.write_all(response.body.as_bytes());
+            }
+        }
+    });
+    format!("http://{}", addr)
+}
+
+fn read_request(reader: &mut impl BufRead) -> Option<Request> {
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let target = parts.next()?.to_string();
+
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line).ok()?;
+        let Some((name, value)) = line.trim_end().split_once(':') else {
+            break;
+        };
+        headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+    }
+    let mut request = Request {
+        method,
+        target,
+        headers,
+        body: Vec::new(),
+    };
+    let length = request
+        .header("content-length")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
+    request.body.resize(length, 0);
+    reader.read_exact(&mut request.body).ok()?;
+    Some(request)
+}
+
+/// A directory with no `.env` or config file, so only the given settings
+/// apply. Each test passes its own `name`.
+pub fn empty_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("s3-uploader-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Runs the binary in `dir` with nothing from the environment.
+pub fn run_in(dir: &std::path::Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_s3-uploader"))
+        .args(args)
+        .env_clear()
+        .env("HOME", dir)
+        .env("XDG_CONFIG_HOME", dir)
+        .current_dir(dir)
+        .output()
+        .unwrap()
+}
+
+/// Flags for `bucket` at `endpoint` with placeholder credentials, followed
+/// by `rest`.
+pub fn against<'a>(endpoint: &'a str, rest: &[&'a str]) -> Vec<&'a str> {
+    let mut args = vec![
+        "--bucket",
+        "bucket",
+        "--region",
+        "us-east-1",
+        "--access-key",
+        "AKIDEXAMPLE",
+        "--secret-key",
+        "secret",
+        "--endpoint",
+        endpoint,
+    ];
+    args.extend_from_slice(rest);
+    args
+}
@@ -0,0 +1,42 @@
+//! Runs the built binary against fake or unreachable endpoints and checks
+//! it exits with the code documented for each kind of failure.
+
+mod common;
+
+use common::{against, empty_dir, fake_s3, run_in, Response};
+use std::process::Output;
+
+fn run(name: &str, args: &[&str]) -> Output {
+    run_in(&empty_dir(name), args)
+}
+
+#[test]
+fn missing_configuration_exits_2() {
+    let output = run("no-config", &["list"]);
+    assert_eq!(output.status.code(), Some(2), "{:?}", output);
+}
+
+#[test]
+fn missing_object_exits_3() {
+    let endpoint = fake_s3(|_| Response::error(404, "NoSuchKey"));
+    let output = run("missing", &against(&endpoint, &["stat", "missing.txt"]));
+    assert_eq!(output.status.code(), Some(3), "{:?}", output);
+}
+
+#[test]
+fn bad_credentials_exit_6() {
+    let endpoint = fake_s3(|_| Response::error(403, "InvalidAccessKeyId"));
+    let output = run("denied", &against(&endpoint, &["list"]));
+    assert_eq!(output.status.code(), Some(6), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Hint:"));
+}
+
+#[test]
+fn unreachable_endpoint_exits_9() {
+    // Nothing listens on port 1
+    let output = run(
+        "unreachable",
+        &against("http://127.0.0.1:1", &["--probe", "list"]),
+    );
+    assert_eq!(output.status.code(), Some(9), "{:?}", output);
+}
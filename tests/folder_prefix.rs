@@ -0,0 +1,46 @@
+//! `--dir` turns a recursive command's prefix into a folder, so `logs`
+//! doesn't also cover `logs-old/`.
+
+mod common;
+
+use common::{against, empty_dir, fake_s3, run_in, Response};
+use std::sync::{Arc, Mutex};
+
+/// An empty bucket that records the target of every listing.
+fn empty_listing() -> (String, Arc<Mutex<Vec<String>>>) {
+    let listings: Arc<Mutex<Vec<String>>> = Arc::default();
+    let seen = listings.clone();
+    let endpoint = fake_s3(move |request| {
+        seen.lock().unwrap().push(request.target.clone());
+        Response {
+            body: "<ListBucketResult><Name>bucket</Name><KeyCount>0</KeyCount><IsTruncated>false</IsTruncated></ListBucketResult>".to_string(),
+            ..Response::new(200)
+        }
+    });
+    (endpoint, listings)
+}
+
+fn listed_prefix(name: &str, args: &[&str]) -> String {
+    let (endpoint, listings) = empty_listing();
+    let output = run_in(&empty_dir(name), &against(&endpoint, args));
+    assert!(output.status.success(), "{:?}", output);
+    let listings = listings.lock().unwrap();
+    let target = listings.first().expect("nothing listed");
+    target
+        .split(['?', '&'])
+        .find_map(|pair| pair.strip_prefix("prefix="))
+        .unwrap_or_default()
+        .to_string()
+}
+
+#[test]
+fn recursive_delete_with_dir_lists_the_folder() {
+    let args = ["delete", "logs", "--recursive", "--dir", "--yes"];
+    assert_eq!(listed_prefix("delete-dir", &args), "logs%2F");
+}
+
+#[test]
+fn recursive_download_with_dir_lists_the_folder() {
+    let args = ["download", "logs", "--recursive", "--dir"];
+    assert_eq!(listed_prefix("download-dir", &args), "logs%2F");
+}
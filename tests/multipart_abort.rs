@@ -0,0 +1,51 @@
+//! A multipart upload whose part fails is aborted, unless `--resumable`
+//! asks for it to be kept.
+
+mod common;
+
+use common::{against, empty_dir, fake_s3, run_in, Response};
+use std::sync::{Arc, Mutex};
+
+/// Accepts the multipart upload, refuses every part, and records the
+/// method of each request.
+fn refusing_parts() -> (String, Arc<Mutex<Vec<String>>>) {
+    let methods: Arc<Mutex<Vec<String>>> = Arc::default();
+    let seen = methods.clone();
+    let endpoint = fake_s3(move |request| {
+        seen.lock().unwrap().push(request.method.clone());
+        match request.method.as_str() {
+            "POST" => Response {
+                body: "<InitiateMultipartUploadResult><UploadId>upload-id</UploadId></InitiateMultipartUploadResult>".to_string(),
+                ..Response::new(200)
+            },
+            "PUT" => Response::error(403, "AccessDenied"),
+            "DELETE" => Response::new(204),
+            _ => Response::error(501, "NotImplemented"),
+        }
+    });
+    (endpoint, methods)
+}
+
+fn upload(name: &str, extra: &[&str]) -> Vec<String> {
+    let dir = empty_dir(name);
+    std::fs::write(dir.join("big.bin"), vec![7u8; 1024]).unwrap();
+    let (endpoint, methods) = refusing_parts();
+    let mut args = vec!["upload", "big.bin", "--force-multipart"];
+    args.extend_from_slice(extra);
+    let output = run_in(&dir, &against(&endpoint, &args));
+    assert!(!output.status.success(), "{:?}", output);
+    let methods = methods.lock().unwrap().clone();
+    methods
+}
+
+#[test]
+fn failed_multipart_upload_is_aborted() {
+    let methods = upload("multipart-abort", &[]);
+    assert!(methods.iter().any(|m| m == "DELETE"), "{:?}", methods);
+}
+
+#[test]
+fn resumable_multipart_upload_is_kept() {
+    let methods = upload("multipart-keep", &["--resumable"]);
+    assert!(!methods.iter().any(|m| m == "DELETE"), "{:?}", methods);
+}
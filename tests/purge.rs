@@ -0,0 +1,60 @@
+//! `purge` only falls back to listing current objects when the endpoint
+//! doesn't implement version listing.
+
+mod common;
+
+use common::{against, empty_dir, fake_s3, run_in, Response};
+use std::sync::{Arc, Mutex};
+
+/// Answers `ListObjectVersions` with `versions` and records every request
+/// as `METHOD target`.
+fn bucket(versions: fn() -> Response) -> (String, Arc<Mutex<Vec<String>>>) {
+    let requests: Arc<Mutex<Vec<String>>> = Arc::default();
+    let seen = requests.clone();
+    let endpoint = fake_s3(move |request| {
+        seen.lock()
+            .unwrap()
+            .push(format!("{} {}", request.method, request.target));
+        if request.target.contains("versions") {
+            versions()
+        } else {
+            Response {
+                body: "<ListBucketResult><Name>bucket</Name><KeyCount>0</KeyCount><IsTruncated>false</IsTruncated></ListBucketResult>".to_string(),
+                ..Response::new(200)
+            }
+        }
+    });
+    (endpoint, requests)
+}
+
+fn purge(name: &str, versions: fn() -> Response) -> (bool, Vec<String>) {
+    let (endpoint, requests) = bucket(versions);
+    let args = ["purge", "--confirm", "bucket"];
+    let output = run_in(&empty_dir(name), &against(&endpoint, &args));
+    let requests = requests.lock().unwrap().clone();
+    (output.status.success(), requests)
+}
+
+#[test]
+fn refused_version_listing_fails_the_purge() {
+    let (succeeded, requests) = purge("purge-denied", || Response::error(403, "AccessDenied"));
+    assert!(!succeeded);
+    assert!(
+        !requests.iter().any(|r| r.contains("list-type=2")),
+        "fell back to listing objects: {:?}",
+        requests
+    );
+}
+
+#[test]
+fn unimplemented_version_listing_falls_back_to_objects() {
+    let (succeeded, requests) = purge("purge-unsupported", || {
+        Response::error(501, "NotImplemented")
+    });
+    assert!(succeeded, "{:?}", requests);
+    assert!(
+        requests.iter().any(|r| r.contains("list-type=2")),
+        "{:?}",
+        requests
+    );
+}
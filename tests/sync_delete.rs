@@ -0,0 +1,25 @@
+//! `sync --delete` must not treat the whole bucket as the folder to mirror.
+
+mod common;
+
+use common::{against, empty_dir, fake_s3, run_in, Response};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+#[test]
+fn delete_without_a_prefix_is_refused_before_any_request() {
+    let dir = empty_dir("sync-delete");
+    std::fs::create_dir_all(dir.join("site")).unwrap();
+    std::fs::write(dir.join("site/index.html"), b"<h1>hi</h1>").unwrap();
+    let requests = Arc::new(AtomicUsize::new(0));
+    let seen = requests.clone();
+    let endpoint = fake_s3(move |_| {
+        seen.fetch_add(1, Ordering::SeqCst);
+        Response::error(500, "InternalError")
+    });
+
+    let output = run_in(&dir, &against(&endpoint, &["sync", "site", "--delete"]));
+    assert!(!output.status.success(), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Refusing"));
+    assert_eq!(requests.load(Ordering::SeqCst), 0);
+}
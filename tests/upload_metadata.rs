@@ -0,0 +1,90 @@
+//! Uploads a file with the built binary to a fake S3 that keeps what it's
+//! sent, then reads the object back with `stat`, which uses `head_object`.
+
+mod common;
+
+use common::{against, empty_dir, fake_s3, run_in, Request, Response};
+use sha2::{Digest, Sha256};
+use std::sync::{Arc, Mutex};
+
+/// The path of a request target, without its query.
+fn path(target: &str) -> &str {
+    target.split('?').next().unwrap_or(target)
+}
+
+/// Stores the headers of each `PUT` and serves them back on `HEAD`.
+fn object_store() -> (String, Arc<Mutex<Vec<Request>>>) {
+    let puts: Arc<Mutex<Vec<Request>>> = Arc::new(Mutex::new(Vec::new()));
+    let stored = puts.clone();
+    let endpoint = fake_s3(move |request| {
+        let mut stored = stored.lock().unwrap();
+        match request.method.as_str() {
+            "PUT" => {
+                stored.push(Request {
+                    method: request.method.clone(),
+                    target: request.target.clone(),
+                    headers: request.headers.clone(),
+                    body: request.body.clone(),
+                });
+                Response {
+                    headers: vec![("ETag".to_string(), "\"stored\"".to_string())],
+                    ..Response::new(200)
+                }
+            }
+            "HEAD" => match stored
+                .iter()
+                .rev()
+                .find(|put| path(&put.target) == path(&request.target))
+            {
+                Some(put) => Response {
+                    headers: put
+                        .headers
+                        .iter()
+                        .filter(|(name, _)| {
+                            name.starts_with("x-amz-meta-") || name == "content-type"
+                        })
+                        .cloned()
+                        .chain([
+                            ("Content-Length".to_string(), put.body.len().to_string()),
+                            ("ETag".to_string(), "\"stored\"".to_string()),
+                        ])
+                        .collect(),
+                    ..Response::new(200)
+                },
+                None => Response::error(404, "NoSuchKey"),
+            },
+            _ => Response::error(501, "NotImplemented"),
+        }
+    });
+    (endpoint, puts)
+}
+
+#[test]
+fn sha256_metadata_round_trips_through_head_object() {
+    let dir = empty_dir("upload-metadata");
+    let contents = b"checksummed contents\n";
+    std::fs::write(dir.join("report.txt"), contents).unwrap();
+    let (endpoint, puts) = object_store();
+
+    let upload = run_in(&dir, &against(&endpoint, &["upload", "report.txt"]));
+    assert!(upload.status.success(), "{:?}", upload);
+
+    let digest = Sha256::digest(contents);
+    let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+    {
+        let puts = puts.lock().unwrap();
+        let put = puts.last().expect("no PUT received");
+        assert_eq!(put.body, contents);
+        assert_eq!(put.header("x-amz-meta-sha256"), Some(hex.as_str()));
+        // The native checksum carries the same hash, base64-encoded
+        use base64::Engine;
+        let native = base64::engine::general_purpose::STANDARD.encode(digest);
+        assert_eq!(put.header("x-amz-checksum-sha256"), Some(native.as_str()));
+    }
+
+    let stat = run_in(&dir, &against(&endpoint, &["--json", "stat", "report.txt"]));
+    assert!(stat.status.success(), "{:?}", stat);
+    let stat: serde_json::Value = serde_json::from_slice(&stat.stdout).unwrap();
+    assert_eq!(stat["metadata"]["sha256"], hex.as_str());
+    assert_eq!(stat["size"], contents.len());
+}